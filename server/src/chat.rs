@@ -5,22 +5,36 @@ use std::sync::Mutex;
 use crate::chat::chat_manager::ChatManager;
 use rocket::response::content::Json;
 
+pub mod attachments;
 pub mod chat_manager;
-mod chat_room;
+pub mod link_preview;
+pub mod message_store;
+pub mod presence;
+pub mod chat_room;
 mod chat_user;
+mod formatting;
 mod name_extractor;
+mod slug;
 
 
 //TBD: Message data definition to go here.
 pub mod chat_data {
 
     use serde::{Deserialize, Serialize};
+    use crate::user::Profile;
 
     #[derive(Serialize, Deserialize, Debug)]
     pub struct RoomCreated {
+        //Where to open the room's WebSocket, by its slug.
         pub path: String,
         pub name: String,
-        pub id: String
+        pub id: String,
+        pub slug: String,
+        //Only temporary rooms have these, grace is in seconds and expires_at is RFC 3339.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub grace: Option<i64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub expires_at: Option<String>
     }
 
     #[derive(Serialize, Deserialize, Debug)]
@@ -36,15 +50,147 @@ pub mod chat_data {
 
     #[derive(Serialize, Deserialize, Debug)]
     pub struct ChatUser {
-        pub name: String
+        pub name: String,
+        //Taken from the cookie sent with the websocket handshake, never from the join message.
+        #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+        pub user_id: Option<String>,
+        //Only read on joining, a full room queues the user instead of turning them away.
+        #[serde(default, skip_serializing)]
+        pub wait: bool
+    }
+
+    //The reason in the close frame sent when a full room turns someone away.
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct RoomFull {
+        pub error: String,
+        pub capacity: usize
+    }
+
+    //Sent to a queued user when they join the queue and whenever they move up.
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct QueuePosition {
+        pub position: usize,
+        pub capacity: usize
     }
 
     #[derive(Serialize, Deserialize, Debug)]
     pub struct ChatMessage {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub id: Option<u64>,
+        pub from: String,
+        pub msg: String,
+        //Sanitized HTML rendering of msg, always produced by the server.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub html: Option<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub mentions: Vec<Mention>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub attachments: Vec<String>
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct Mention {
+        pub name: String,
+        pub registered: bool
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct MentionNotification {
+        pub mentioned: String,
+        pub room_id: String,
+        pub room: String,
+        pub message_id: u64,
         pub from: String,
         pub msg: String
     }
 
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct ReadReceipt {
+        pub from: String,
+        pub read: u64
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct FavoriteRoom {
+        pub room_id: String,
+        pub name: String
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct UnreadCount {
+        pub room_id: String,
+        pub name: String,
+        pub unread: u64
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct TypingEvent {
+        pub from: String,
+        pub typing: bool
+    }
+
+    //Tells a room who is in it, with enough of their profile to show next to their messages.
+    //Members go by name, their user id is what signs them in so it never leaves the server.
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct PresenceEvent {
+        pub name: String,
+        pub registered: bool,
+        //Whether they're connected to this room, status is across all of them.
+        pub online: bool,
+        pub status: PresenceStatus,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub profile: Option<Profile>
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum PresenceStatus {
+        Online,
+        Away,
+        Dnd,
+        Offline
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct PresenceUpdate {
+        pub status: PresenceStatus
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct UserPresence {
+        pub user_name: String,
+        pub status: PresenceStatus
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct LinkPreview {
+        pub message_id: u64,
+        pub url: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub title: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub description: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub image: Option<String>
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct AttachmentCreated {
+        pub id: String,
+        pub name: String,
+        pub content_type: String,
+        pub size: u64
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct SearchHit {
+        pub room_id: String,
+        pub message_id: u64,
+        pub from: String,
+        pub sent_at: String,
+        pub snippet: String
+    }
+
     #[derive(Serialize,Deserialize, Debug)]
     pub struct RoomAvailable {
         pub name: String,
@@ -55,6 +201,116 @@ pub mod chat_data {
     pub struct RoomDeleted {
         pub room_id: String
     }
+
+    //Anything left out stays as it is, an empty topic or description clears it and so does a capacity of 0.
+    #[derive(Deserialize, Debug, Default)]
+    pub struct RoomPatch {
+        pub name: Option<String>,
+        pub topic: Option<String>,
+        pub description: Option<String>,
+        pub capacity: Option<usize>
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct RoomInfo {
+        pub room_id: String,
+        pub name: String,
+        pub slug: String,
+        pub topic: Option<String>,
+        pub description: Option<String>,
+        pub capacity: Option<usize>
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum OwnershipAction {
+        Transferred,
+        CoOwnerAdded,
+        CoOwnerRemoved,
+        //The owner's account was deleted and the room went to the user_id.
+        HandedOver
+    }
+
+    impl OwnershipAction {
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                OwnershipAction::Transferred => "transferred",
+                OwnershipAction::CoOwnerAdded => "co_owner_added",
+                OwnershipAction::CoOwnerRemoved => "co_owner_removed",
+                OwnershipAction::HandedOver => "handed_over"
+            }
+        }
+
+        pub fn parse(action: &str) -> Option<Self> {
+            match action {
+                "transferred" => Some(OwnershipAction::Transferred),
+                "co_owner_added" => Some(OwnershipAction::CoOwnerAdded),
+                "co_owner_removed" => Some(OwnershipAction::CoOwnerRemoved),
+                "handed_over" => Some(OwnershipAction::HandedOver),
+                _ => None
+            }
+        }
+    }
+
+    //One entry in a room's ownership history as it's stored, user_id is who gained or lost the role.
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    pub struct OwnershipChange {
+        pub room_id: String,
+        pub action: OwnershipAction,
+        pub user_id: String,
+        pub changed_by: String,
+        pub changed_at: i64
+    }
+
+    //Who owns a room by user id, the routes name everyone before it leaves the server.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct RoomOwnership {
+        pub room_id: String,
+        pub owner_id: String,
+        pub co_owners: Vec<String>,
+        pub history: Vec<OwnershipChange>
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct NewOwner {
+        pub user_name: String
+    }
+
+    //An ownership change as other users see it, by name.
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    pub struct OwnershipEvent {
+        pub action: OwnershipAction,
+        pub user_name: String,
+        pub changed_by: String,
+        pub changed_at: i64
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct RoomOwners {
+        pub room_id: String,
+        pub owner: String,
+        pub co_owners: Vec<String>,
+        pub history: Vec<OwnershipEvent>
+    }
+
+    //limit is None for users without a quota, remaining also counts the server wide limit.
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    pub struct RoomQuota {
+        pub owned: usize,
+        pub limit: Option<usize>,
+        pub remaining: usize,
+        pub server_remaining: usize
+    }
+
+    //Sent to everyone in a room after its owner edits it, cleared fields come through as null.
+    #[derive(Serialize, Deserialize, Debug)]
+    pub struct TopicChanged {
+        pub room_id: String,
+        pub name: String,
+        pub topic: Option<String>,
+        pub description: Option<String>,
+        pub changed_by: String
+    }
 }
 
 pub mod chat_routes {
@@ -62,23 +318,54 @@ pub mod chat_routes {
     use rocket::State;
     use rocket_contrib::json::Json;
 
-    use crate::chat::chat_data::{ChatRoom, ChatRooms, RoomCreated, RoomAvailable, RoomDeleted};
-    use crate::chat::chat_manager::{ChatManager, Error};
-    use rocket::http::Cookies;
-
-    #[post("/<name>")]
-    pub fn create_room(cm: State<Mutex<ChatManager>>, name: String, cookies: Cookies) -> Result<Json<RoomCreated>, Error> {
-        let user_id = String::from(cookies.get("user-id").unwrap().value());
-        let result = cm.lock().unwrap().create_new_room(name.clone(), user_id);
-        match result {
-                Ok(mut res) => {
-                    res.path = format!("room/{}", name);
-                    Ok(Json(res))
-                },
-                Err(err) => {
-                    Err(err)
-                }
-            }
+    use crate::chat::chat_data::{ChatRoom, ChatRooms, RoomCreated, RoomAvailable, RoomDeleted, RoomPatch, RoomInfo, SearchHit,
+                                 NewOwner, RoomOwners, RoomOwnership, OwnershipEvent, RoomQuota};
+    use crate::chat::chat_manager::{ChatManager, Error as ChatError};
+    use crate::chat::message_store::{MessageStore, SearchQuery};
+    use crate::user::user_db_service::{UserDbService, DELETED_USER_NAME};
+    use crate::routes::api_error::ApiError;
+    use crate::routes::auth::AuthUser;
+    use rocket::http::Status;
+    use rocket::request::Form;
+    use std::sync::Arc;
+    use std::collections::HashMap;
+    use chrono::{DateTime, NaiveDate};
+
+    //Any of these makes the room temporary, grace is in seconds.
+    #[derive(FromForm)]
+    pub struct RoomOptions {
+        temporary: Option<bool>,
+        grace: Option<i64>,
+        expires_at: Option<String>
+    }
+
+    //Guests have to register before they can own a room.
+    #[post("/<name>?<options..>")]
+    pub fn create_room(cm: State<Mutex<ChatManager>>, auth: AuthUser, name: String, options: Form<RoomOptions>) -> Result<Json<RoomCreated>, ApiError> {
+        if auth.user.to_user().guest {
+            return Err(ApiError::forbidden("Guests can't create rooms, register to create one."));
+        }
+        let user_id = auth.user_id().clone();
+        let options = options.into_inner();
+        let expires_at = parse_time(options.expires_at, false)
+            .map_err(|_| ApiError::new(Status::BadRequest, "validation", String::from("expires_at isn't a valid time.")))?;
+        let res = if options.temporary.unwrap_or(false) || options.grace.is_some() || expires_at.is_some() {
+            cm.lock().unwrap().create_temporary_room(name, user_id, options.grace, expires_at)?
+        } else {
+            cm.lock().unwrap().create_new_room(name, user_id)?
+        };
+        Ok(Json(res))
+    }
+
+    //Guests can't create rooms at all, so they have nothing left.
+    #[get("/quota")]
+    pub fn quota(cm: State<Mutex<ChatManager>>, auth: AuthUser) -> Json<RoomQuota> {
+        let mut quota = cm.lock().unwrap().quota(auth.user_id());
+        if auth.user.to_user().guest {
+            quota.limit = Some(0);
+            quota.remaining = 0;
+        }
+        Json(quota)
     }
 
     #[get("/")]
@@ -116,18 +403,150 @@ pub mod chat_routes {
         Json(response)
     }
 
-    #[delete("/<room_id>")]
-    pub fn delete_room(cm: State<Mutex<ChatManager>>, room_id: String, cookies: Cookies) -> Result<Json<RoomDeleted>, Error> {
-        let owner_id = cookies.get("user-id").unwrap().value().to_string();
-        let result = cm.lock().unwrap().delete_room(room_id.clone(), owner_id);
-        match result {
-            Ok(r) => {
-                Ok(Json(RoomDeleted{
-                    room_id
-                }))
-            },
-            Err(e) => {
-                Err(e)
+    #[delete("/<key>")]
+    pub fn delete_room(cm: State<Mutex<ChatManager>>, auth: AuthUser, key: String) -> Result<Json<RoomDeleted>, ApiError> {
+        let mut chat_mgr = cm.lock().unwrap();
+        let room_id = room_id(&chat_mgr, key);
+        chat_mgr.delete_room(room_id.clone(), auth.user_id().clone())?;
+        Ok(Json(RoomDeleted {
+            room_id
+        }))
+    }
+
+    #[patch("/<key>", format = "json", data = "<patch>")]
+    pub fn update_room(cm: State<Mutex<ChatManager>>, auth: AuthUser, key: String, patch: Json<RoomPatch>) -> Result<Json<RoomInfo>, ApiError> {
+        let chat_mgr = cm.lock().unwrap();
+        let info = chat_mgr.update_room(&room_id(&chat_mgr, key), auth.user_id(), auth.user.user_name(), patch.into_inner())?;
+        Ok(Json(info))
+    }
+
+    #[get("/<key>/owners")]
+    pub fn owners(cm: State<Mutex<ChatManager>>, db: State<Arc<UserDbService>>, auth: AuthUser, key: String) -> Result<Json<RoomOwners>, ApiError> {
+        let chat_mgr = cm.lock().unwrap();
+        let ownership = chat_mgr.owners(&room_id(&chat_mgr, key), auth.user_id())?;
+        Ok(Json(named_owners(&db, ownership)?))
+    }
+
+    #[put("/<key>/owner", format = "json", data = "<owner>")]
+    pub fn transfer_ownership(cm: State<Mutex<ChatManager>>, db: State<Arc<UserDbService>>, auth: AuthUser, key: String,
+                              owner: Json<NewOwner>) -> Result<Json<RoomOwners>, ApiError> {
+        let new_owner = can_own_rooms(&db, owner.into_inner().user_name)?;
+        let chat_mgr = cm.lock().unwrap();
+        let ownership = chat_mgr.transfer_ownership(&room_id(&chat_mgr, key), auth.user_id(), new_owner)?;
+        Ok(Json(named_owners(&db, ownership)?))
+    }
+
+    #[post("/<key>/co-owners", format = "json", data = "<owner>")]
+    pub fn add_co_owner(cm: State<Mutex<ChatManager>>, db: State<Arc<UserDbService>>, auth: AuthUser, key: String,
+                        owner: Json<NewOwner>) -> Result<Json<RoomOwners>, ApiError> {
+        let co_owner = can_own_rooms(&db, owner.into_inner().user_name)?;
+        let chat_mgr = cm.lock().unwrap();
+        let ownership = chat_mgr.add_co_owner(&room_id(&chat_mgr, key), auth.user_id(), co_owner)?;
+        Ok(Json(named_owners(&db, ownership)?))
+    }
+
+    #[delete("/<key>/co-owners/<user_name>")]
+    pub fn remove_co_owner(cm: State<Mutex<ChatManager>>, db: State<Arc<UserDbService>>, auth: AuthUser, key: String,
+                           user_name: String) -> Result<Json<RoomOwners>, ApiError> {
+        let co_owner = user_id_of(&db, user_name)?;
+        let chat_mgr = cm.lock().unwrap();
+        let ownership = chat_mgr.remove_co_owner(&room_id(&chat_mgr, key), auth.user_id(), &co_owner)?;
+        Ok(Json(named_owners(&db, ownership)?))
+    }
+
+    //Rooms can be given by id or by slug.
+    fn room_id(chat_mgr: &ChatManager, key: String) -> String {
+        chat_mgr.find_room(&key).unwrap_or(key)
+    }
+
+    //Guests can't create rooms, so they can't be handed one either.
+    fn can_own_rooms(db: &UserDbService, user_name: String) -> Result<String, ApiError> {
+        let user_id = user_id_of(db, user_name)?;
+        if db.retrieve_user_by_id(user_id.clone())?.to_user().guest {
+            return Err(ApiError::new(Status::BadRequest, "validation", String::from("Guests can't own rooms.")));
+        }
+        Ok(user_id)
+    }
+
+    fn user_id_of(db: &UserDbService, user_name: String) -> Result<String, ApiError> {
+        db.find_user_by_name(user_name)?.user_id().cloned()
+            .ok_or_else(|| ApiError::not_found(String::from("User doesn't exist.")))
+    }
+
+    //User ids are session credentials, so owners and their history only go out by name.
+    fn named_owners(db: &UserDbService, ownership: RoomOwnership) -> Result<RoomOwners, ApiError> {
+        let mut names: HashMap<String, String> = HashMap::new();
+        let mut name_of = |user_id: &String| -> Result<String, ApiError> {
+            if let Some(name) = names.get(user_id) {
+                return Ok(name.clone());
+            }
+            let user = db.retrieve_user_by_id(user_id.clone())?;
+            let name = if user.user_id().is_some() { user.user_name().clone() } else { String::from(DELETED_USER_NAME) };
+            names.insert(user_id.clone(), name.clone());
+            Ok(name)
+        };
+        let owner = name_of(&ownership.owner_id)?;
+        let co_owners = ownership.co_owners.iter().map(&mut name_of).collect::<Result<Vec<String>, ApiError>>()?;
+        let history = ownership.history.iter().map(|change| Ok(OwnershipEvent {
+            action: change.action,
+            user_name: name_of(&change.user_id)?,
+            changed_by: name_of(&change.changed_by)?,
+            changed_at: change.changed_at
+        })).collect::<Result<Vec<OwnershipEvent>, ApiError>>()?;
+        Ok(RoomOwners {
+            room_id: ownership.room_id,
+            owner,
+            co_owners,
+            history
+        })
+    }
+
+    #[derive(FromForm)]
+    pub struct SearchParams {
+        q: String,
+        room: Option<String>,
+        from: Option<String>,
+        after: Option<String>,
+        before: Option<String>
+    }
+
+    #[get("/?<params..>")]
+    pub fn search(cm: State<Mutex<ChatManager>>, store: State<Arc<Mutex<MessageStore>>>, auth: AuthUser,
+                  params: Form<SearchParams>) -> Result<Json<Vec<SearchHit>>, ApiError> {
+        let params = params.into_inner();
+        let accessible = cm.lock().unwrap().accessible_rooms(auth.user_id());
+        let rooms = match params.room {
+            Some(r) if accessible.contains(&r) => vec![r],
+            Some(_) => return Err(ChatError::RoomNotFound.into()),
+            None => accessible
+        };
+        let query = SearchQuery {
+            text: params.q,
+            rooms,
+            from: params.from,
+            after: parse_time(params.after, false)?,
+            before: parse_time(params.before, true)?
+        };
+        Ok(Json(store.lock().unwrap().search(&query)?))
+    }
+
+    //Accepts RFC 3339 timestamps or plain dates, a date bound covers the whole day.
+    fn parse_time(value: Option<String>, end_of_day: bool) -> Result<Option<i64>, ApiError> {
+        match value {
+            None => Ok(None),
+            Some(v) => {
+                if let Ok(time) = DateTime::parse_from_rfc3339(&v) {
+                    Ok(Some(time.timestamp()))
+                } else if let Ok(date) = NaiveDate::parse_from_str(&v, "%Y-%m-%d") {
+                    let time = if end_of_day {
+                        date.and_hms_opt(23, 59, 59)
+                    } else {
+                        date.and_hms_opt(0, 0, 0)
+                    };
+                    Ok(time.map(|t| t.and_utc().timestamp()))
+                } else {
+                    Err(ApiError::new(Status::BadRequest, "validation", format!("{} is not a date or time.", v)))
+                }
             }
         }
     }
@@ -164,7 +583,9 @@ impl Extractor for JsonExtractor {
 
             for user in users {
                 room.users.push(ChatUser {
-                    name: user.clone()
+                    name: user.clone(),
+                    user_id: None,
+                    wait: false
                 });
             }
 
@@ -177,7 +598,210 @@ impl Extractor for JsonExtractor {
 #[cfg(test)]
 mod test {
     use crate::chat::JsonExtractor;
-    use crate::chat::chat_room::Extractor;
+    use crate::chat::chat_room::{Extractor, RoomServices};
+    use crate::chat::chat_manager::{ChatManager, RoomLimits};
+    use crate::storage::Storage;
+    use crate::storage::sqlite::SqliteStorage;
+    use crate::chat::chat_data::{RoomInfo, RoomOwners, RoomQuota, RoomCreated, RoomDeleted, SearchHit};
+    use crate::chat::message_store::{MessageStore, StoredMessage};
+    use crate::user::user_db_service::UserDbService;
+    use crate::user::User;
+    use rocket::local::Client;
+    use rocket::http::{ContentType, Cookie, Status};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn guests_and_strangers_cannot_create_rooms() {
+        let db = Arc::new(UserDbService::new());
+        let guest_id = db.create_guest().unwrap().user_id().cloned().unwrap();
+        let rocket = rocket::ignite()
+            .manage(db)
+            .manage(Mutex::new(ChatManager::new()))
+            .mount("/room", routes![super::chat_routes::create_room]);
+        let client = Client::new(rocket).unwrap();
+
+        let response = client.post("/room/annex").dispatch();
+        assert_eq!(Status::Unauthorized, response.status());
+        let response = client.post("/room/annex").cookie(Cookie::new("user-id", guest_id)).dispatch();
+        assert_eq!(Status::Forbidden, response.status());
+        let response = client.post("/room/annex").cookie(Cookie::new("user-id", "made-up-id")).dispatch();
+        assert_eq!(Status::Unauthorized, response.status());
+    }
+
+    #[test]
+    fn rooms_can_be_created_temporary() {
+        let db = Arc::new(UserDbService::new());
+        let user_id = db.create_user(Box::new(User::new(String::from("mscott")))).unwrap().user_id().cloned().unwrap();
+        let rocket = rocket::ignite()
+            .manage(db)
+            .manage(Mutex::new(ChatManager::new()))
+            .mount("/room", routes![super::chat_routes::create_room]);
+        let client = Client::new(rocket).unwrap();
+        let create = |path: &'static str| client.post(path).cookie(Cookie::new("user-id", user_id.clone())).dispatch();
+
+        let mut response = create("/room/annex?grace=60");
+        let room: RoomCreated = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(Some(60), room.grace);
+        assert_eq!(None, room.expires_at);
+        assert_eq!(Status::BadRequest, create("/room/sales?expires_at=soon").status());
+        assert_eq!(Status::BadRequest, create("/room/sales?expires_at=2001-01-01").status());
+        let mut response = create("/room/sales");
+        let room: RoomCreated = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(None, room.grace);
+    }
+
+    #[test]
+    fn quota_reports_what_is_left() {
+        let db = Arc::new(UserDbService::new());
+        let user_id = db.create_user(Box::new(User::new(String::from("mscott")))).unwrap().user_id().cloned().unwrap();
+        let guest_id = db.create_guest().unwrap().user_id().cloned().unwrap();
+        let limits = RoomLimits { per_user: Some(3), ..RoomLimits::default() };
+        let mut cm = ChatManager::new().with_limits(limits);
+        cm.create_new_room(String::from("annex"), user_id.clone()).unwrap();
+        let rocket = rocket::ignite()
+            .manage(db)
+            .manage(Mutex::new(cm))
+            .mount("/room", routes![super::chat_routes::quota]);
+        let client = Client::new(rocket).unwrap();
+        let quota = |user_id: String| -> RoomQuota {
+            let mut response = client.get("/room/quota").cookie(Cookie::new("user-id", user_id)).dispatch();
+            serde_json::from_str(&response.body_string().unwrap()).unwrap()
+        };
+
+        assert_eq!(RoomQuota { owned: 1, limit: Some(3), remaining: 2, server_remaining: 9 }, quota(user_id));
+        assert_eq!(RoomQuota { owned: 0, limit: Some(0), remaining: 0, server_remaining: 9 }, quota(guest_id));
+        assert_eq!(Status::Unauthorized, client.get("/room/quota").dispatch().status());
+    }
+
+    #[test]
+    fn owner_can_edit_room_details() {
+        let db = Arc::new(UserDbService::new());
+        let owner_id = db.create_user(Box::new(User::new(String::from("mscott")))).unwrap().user_id().cloned().unwrap();
+        let other_id = db.create_user(Box::new(User::new(String::from("dschrute")))).unwrap().user_id().cloned().unwrap();
+        let mut cm = ChatManager::new();
+        let room = cm.create_new_room(String::from("annex"), owner_id.clone()).unwrap();
+        let rocket = rocket::ignite()
+            .manage(db)
+            .manage(Mutex::new(cm))
+            .mount("/room", routes![super::chat_routes::update_room]);
+        let client = Client::new(rocket).unwrap();
+        let path = format!("/room/{}", room.id);
+
+        let response = client.patch(&path)
+            .header(ContentType::JSON)
+            .cookie(Cookie::new("user-id", other_id))
+            .body(r#"{"topic":"Bears"}"#)
+            .dispatch();
+        assert_eq!(Status::Forbidden, response.status());
+
+        let mut response = client.patch(&path)
+            .header(ContentType::JSON)
+            .cookie(Cookie::new("user-id", owner_id))
+            .body(r#"{"name":"Conference Room","topic":"Diversity Day"}"#)
+            .dispatch();
+        let info: RoomInfo = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(room.id, info.room_id);
+        assert_eq!("Conference Room", info.name);
+        assert_eq!(Some(String::from("Diversity Day")), info.topic);
+        assert_eq!(None, info.description);
+    }
+
+    #[test]
+    fn only_a_signed_in_owner_can_delete_a_room() {
+        let db = Arc::new(UserDbService::new());
+        let owner_id = db.create_user(Box::new(User::new(String::from("mscott")))).unwrap().user_id().cloned().unwrap();
+        let other_id = db.create_user(Box::new(User::new(String::from("dschrute")))).unwrap().user_id().cloned().unwrap();
+        let mut cm = ChatManager::new();
+        let room = cm.create_new_room(String::from("annex"), owner_id.clone()).unwrap();
+        let rocket = rocket::ignite()
+            .manage(db)
+            .manage(Mutex::new(cm))
+            .mount("/room", routes![super::chat_routes::delete_room]);
+        let client = Client::new(rocket).unwrap();
+        let path = format!("/room/{}", room.id);
+
+        assert_eq!(Status::Unauthorized, client.delete(&path).dispatch().status());
+        assert_eq!(Status::Unauthorized, client.delete(&path).cookie(Cookie::new("user-id", "made-up-id")).dispatch().status());
+        assert_eq!(Status::Forbidden, client.delete(&path).cookie(Cookie::new("user-id", other_id)).dispatch().status());
+
+        let mut response = client.delete(&path).cookie(Cookie::new("user-id", owner_id.clone())).dispatch();
+        let deleted: RoomDeleted = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(room.id, deleted.room_id);
+        assert_eq!(Status::NotFound, client.delete(&path).cookie(Cookie::new("user-id", owner_id)).dispatch().status());
+    }
+
+    #[test]
+    fn rooms_are_only_handed_to_registered_users() {
+        let db = Arc::new(UserDbService::new());
+        let owner_id = db.create_user(Box::new(User::new(String::from("mscott")))).unwrap().user_id().cloned().unwrap();
+        let other_id = db.create_user(Box::new(User::new(String::from("dschrute")))).unwrap().user_id().cloned().unwrap();
+        let guest = db.create_guest().unwrap();
+        let (guest_id, guest_name) = (guest.user_id().cloned().unwrap(), guest.user_name().clone());
+        let storage: Arc<dyn Storage> = Arc::new(SqliteStorage::in_memory().unwrap());
+        let mut cm = ChatManager::with_services(RoomServices { storage: Some(storage), ..RoomServices::default() });
+        cm.create_new_room(String::from("Annex"), owner_id.clone()).unwrap();
+        let rocket = rocket::ignite()
+            .manage(db)
+            .manage(Mutex::new(cm))
+            .mount("/room", routes![super::chat_routes::transfer_ownership, super::chat_routes::owners]);
+        let client = Client::new(rocket).unwrap();
+        let transfer = |user_name: &str| client.put("/room/annex/owner")
+            .header(ContentType::JSON)
+            .cookie(Cookie::new("user-id", owner_id.clone()))
+            .body(format!(r#"{{"user_name":"{}"}}"#, user_name))
+            .dispatch();
+
+        assert_eq!(Status::BadRequest, transfer(&guest_name).status());
+        assert_eq!(Status::NotFound, transfer("nobody").status());
+        let body = transfer("dschrute").body_string().unwrap();
+        assert!(!body.contains(&owner_id) && !body.contains(&other_id));
+        let owners: RoomOwners = serde_json::from_str(&body).unwrap();
+        assert_eq!("dschrute", owners.owner);
+        assert_eq!(vec![String::from("mscott")], owners.co_owners);
+        assert_eq!(vec![(String::from("dschrute"), String::from("mscott"))],
+                   owners.history.into_iter().map(|change| (change.user_name, change.changed_by)).collect::<Vec<_>>());
+
+        let response = client.get("/room/annex/owners").cookie(Cookie::new("user-id", guest_id)).dispatch();
+        assert_eq!(Status::Forbidden, response.status());
+    }
+
+    #[test]
+    fn search_only_covers_rooms_the_user_can_reach() {
+        let db = Arc::new(UserDbService::new());
+        let owner_id = db.create_user(Box::new(User::new(String::from("mscott")))).unwrap().user_id().cloned().unwrap();
+        let other_id = db.create_user(Box::new(User::new(String::from("dschrute")))).unwrap().user_id().cloned().unwrap();
+        let mut cm = ChatManager::new();
+        let room = cm.create_new_room(String::from("annex"), owner_id.clone()).unwrap();
+        let store = MessageStore::new();
+        store.save(&StoredMessage {
+            room_id: room.id.clone(),
+            message_id: 1,
+            from: String::from("mscott"),
+            sender_id: Some(owner_id.clone()),
+            msg: String::from("Jello in the annex"),
+            rendered: String::from("Jello in the annex"),
+            mentions: vec![],
+            attachments: vec![],
+            sent_at: 100
+        }).unwrap();
+        let rocket = rocket::ignite()
+            .manage(db)
+            .manage(Mutex::new(cm))
+            .manage(Arc::new(Mutex::new(store)))
+            .mount("/search", routes![super::chat_routes::search]);
+        let client = Client::new(rocket).unwrap();
+        let in_room = format!("/search?q=jello&room={}", room.id);
+        let hits = |path: &str, user_id: &str| -> Vec<SearchHit> {
+            let mut response = client.get(path).cookie(Cookie::new("user-id", String::from(user_id))).dispatch();
+            serde_json::from_str(&response.body_string().unwrap()).unwrap()
+        };
+
+        assert_eq!(Status::Unauthorized, client.get("/search?q=jello").dispatch().status());
+        assert_eq!(Status::NotFound, client.get(&in_room).cookie(Cookie::new("user-id", other_id.clone())).dispatch().status());
+        assert!(hits("/search?q=jello", &other_id).is_empty());
+        assert_eq!(1, hits(&in_room, &owner_id).len());
+        assert_eq!(1, hits("/search?q=jello", &owner_id).len());
+    }
 
     #[test]
     fn extractor_starts_with_no_current_room() {
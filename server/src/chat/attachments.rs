@@ -0,0 +1,224 @@
+use std::fmt::{Display, Formatter};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+use crate::chat::message_store::MessageStore;
+
+pub const MAX_ATTACHMENT_SIZE: u64 = 5 * 1024 * 1024;
+const ALLOWED_TYPES: [&str; 6] = ["image/png", "image/jpeg", "image/gif", "image/webp", "application/pdf", "text/plain"];
+
+//Where attachment bytes live, metadata is kept in the MessageStore.
+pub trait AttachmentStorage: Send + Sync {
+    fn save(&self, id: &str, bytes: &[u8]) -> io::Result<()>;
+
+    fn load(&self, id: &str) -> io::Result<Vec<u8>>;
+
+    fn remove(&self, id: &str) -> io::Result<()>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attachment {
+    pub id: String,
+    pub room_id: String,
+    pub uploader_id: String,
+    pub file_name: String,
+    pub content_type: String,
+    pub size: u64
+}
+
+impl Attachment {
+    pub fn new(room_id: String, uploader_id: String, file_name: String, content_type: String, size: u64) -> Self {
+        Attachment {
+            id: Uuid::new_v4().to_string(),
+            room_id,
+            uploader_id,
+            file_name,
+            content_type,
+            size
+        }
+    }
+}
+
+pub struct AttachmentService {
+    storage: Box<dyn AttachmentStorage>,
+    store: Arc<Mutex<MessageStore>>
+}
+
+impl AttachmentService {
+    pub fn new(storage: Box<dyn AttachmentStorage>, store: Arc<Mutex<MessageStore>>) -> Self {
+        AttachmentService {
+            storage,
+            store
+        }
+    }
+
+    pub fn upload(&self, attachment: Attachment, bytes: &[u8]) -> Result<Attachment, AttachmentError> {
+        validate(&attachment.content_type, bytes)?;
+        self.storage.save(&attachment.id, bytes).map_err(|e| AttachmentError::Storage(e.to_string()))?;
+        if let Err(e) = self.store.lock().unwrap().save_attachment(&attachment) {
+            let _ = self.storage.remove(&attachment.id);
+            return Err(AttachmentError::Storage(e.to_string()));
+        }
+        Ok(attachment)
+    }
+
+    pub fn find(&self, id: &str) -> Option<Attachment> {
+        self.store.lock().unwrap().find_attachment(id).unwrap_or(None)
+    }
+
+    pub fn download(&self, attachment: &Attachment) -> Result<Vec<u8>, AttachmentError> {
+        self.storage.load(&attachment.id).map_err(|e| AttachmentError::Storage(e.to_string()))
+    }
+
+    //Only drops the stored bytes, for when the metadata row is already gone.
+    pub fn remove(&self, id: &str) -> Result<(), AttachmentError> {
+        self.storage.remove(id).map_err(|e| AttachmentError::Storage(e.to_string()))
+    }
+}
+
+pub struct LocalDiskStorage {
+    root: PathBuf
+}
+
+impl LocalDiskStorage {
+    pub fn new(root: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(&root)?;
+        Ok(LocalDiskStorage {
+            root
+        })
+    }
+
+    //Only generated ids are accepted so a request can't name a path outside the root.
+    fn path_for(&self, id: &str) -> io::Result<PathBuf> {
+        match Uuid::parse_str(id) {
+            Ok(uuid) => Ok(self.root.join(uuid.to_string())),
+            Err(_) => Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid attachment id"))
+        }
+    }
+}
+
+impl AttachmentStorage for LocalDiskStorage {
+    fn save(&self, id: &str, bytes: &[u8]) -> io::Result<()> {
+        fs::write(self.path_for(id)?, bytes)
+    }
+
+    fn load(&self, id: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.path_for(id)?)
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        fs::remove_file(self.path_for(id)?)
+    }
+}
+
+pub fn validate(content_type: &str, bytes: &[u8]) -> Result<(), AttachmentError> {
+    if bytes.len() as u64 > MAX_ATTACHMENT_SIZE {
+        Err(AttachmentError::TooLarge)
+    } else if bytes.is_empty() {
+        Err(AttachmentError::Empty)
+    } else if !ALLOWED_TYPES.contains(&content_type) || !content_matches(content_type, bytes) {
+        Err(AttachmentError::UnsupportedType)
+    } else {
+        Ok(())
+    }
+}
+
+//Checks the leading bytes so a file can't claim to be an image it isn't.
+fn content_matches(content_type: &str, bytes: &[u8]) -> bool {
+    match content_type {
+        "image/png" => bytes.starts_with(b"\x89PNG\r\n\x1a\n"),
+        "image/jpeg" => bytes.starts_with(b"\xff\xd8\xff"),
+        "image/gif" => bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a"),
+        "image/webp" => bytes.len() > 12 && bytes.starts_with(b"RIFF") && &bytes[8..12] == b"WEBP",
+        "application/pdf" => bytes.starts_with(b"%PDF-"),
+        "text/plain" => std::str::from_utf8(bytes).is_ok(),
+        _ => false
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum AttachmentError {
+    TooLarge,
+    Empty,
+    UnsupportedType,
+    Storage(String)
+}
+
+impl std::error::Error for AttachmentError {}
+impl Display for AttachmentError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            AttachmentError::TooLarge => write!(f, "Attachment exceeds the {} byte limit.", MAX_ATTACHMENT_SIZE),
+            AttachmentError::Empty => write!(f, "Attachment is empty."),
+            AttachmentError::UnsupportedType => write!(f, "Attachment type is not allowed."),
+            AttachmentError::Storage(ref e) => write!(f, "Unable to store attachment: {}", e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chat::attachments::{validate, Attachment, AttachmentError, AttachmentService, AttachmentStorage, LocalDiskStorage, MAX_ATTACHMENT_SIZE};
+    use crate::chat::message_store::MessageStore;
+    use std::sync::{Arc, Mutex};
+    use uuid::Uuid;
+
+    fn storage() -> LocalDiskStorage {
+        let root = std::env::temp_dir().join(format!("crabby-attachments-{}", Uuid::new_v4()));
+        LocalDiskStorage::new(root).unwrap()
+    }
+
+    #[test]
+    fn stored_attachment_can_be_loaded_and_removed() {
+        let storage = storage();
+        let id = Uuid::new_v4().to_string();
+        storage.save(&id, b"that's what she said").unwrap();
+        assert_eq!(b"that's what she said".to_vec(), storage.load(&id).unwrap());
+        storage.remove(&id).unwrap();
+        assert!(storage.load(&id).is_err());
+    }
+
+    #[test]
+    fn uploaded_attachment_is_found_and_downloaded() {
+        let service = AttachmentService::new(Box::new(storage()), Arc::new(Mutex::new(MessageStore::new())));
+        let attachment = Attachment::new(String::from("room-1"), String::from("abcd-1234"),
+                                         String::from("notes.txt"), String::from("text/plain"), 5);
+        let uploaded = service.upload(attachment, b"beets").unwrap();
+
+        let found = service.find(&uploaded.id).unwrap();
+        assert_eq!(uploaded, found);
+        assert_eq!(b"beets".to_vec(), service.download(&found).unwrap());
+    }
+
+    #[test]
+    fn rejected_upload_is_not_recorded() {
+        let service = AttachmentService::new(Box::new(storage()), Arc::new(Mutex::new(MessageStore::new())));
+        let attachment = Attachment::new(String::from("room-1"), String::from("abcd-1234"),
+                                         String::from("page.html"), String::from("text/html"), 9);
+        let id = attachment.id.clone();
+        assert!(service.upload(attachment, b"<p>hi</p>").is_err());
+        assert!(service.find(&id).is_none());
+    }
+
+    #[test]
+    fn ids_that_are_not_uuids_are_rejected() {
+        let storage = storage();
+        assert!(storage.load("../../etc/passwd").is_err());
+    }
+
+    #[test]
+    fn oversized_attachments_are_rejected() {
+        let bytes = vec![b'a'; MAX_ATTACHMENT_SIZE as usize + 1];
+        assert_eq!(Err(AttachmentError::TooLarge), validate("text/plain", &bytes));
+    }
+
+    #[test]
+    fn content_must_match_declared_type() {
+        assert!(validate("image/png", b"\x89PNG\r\n\x1a\nrest-of-image").is_ok());
+        assert_eq!(Err(AttachmentError::UnsupportedType), validate("image/png", b"<script>"));
+        assert_eq!(Err(AttachmentError::UnsupportedType), validate("text/html", b"<p>hi</p>"));
+    }
+}
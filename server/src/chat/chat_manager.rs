@@ -1,59 +1,138 @@
-use crate::chat::chat_room::{ChatRoom, Extractor};
-use crate::chat::chat_room::room_data::ChatData;
+use crate::chat::chat_room::{ChatRoom, Extractor, RoomServices, RoomDirectory, RoomRequest};
+use crate::chat::chat_room::expiry::{Expiry, ExpiryCheck};
+use crate::chat::chat_room::room_data::{ChatData, RoomMeta, Owners};
 use std::collections::HashMap;
 use std::fmt::{Debug, Display, Formatter};
-use std::net::{TcpListener, SocketAddr, TcpStream};
+use std::net::{TcpListener, SocketAddr};
 use threadpool::ThreadPool;
 use std::sync::mpsc::{Sender, Receiver};
 use std::io::{Write};
 use std::sync::{mpsc, Arc, Mutex};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::borrow::Cow;
-use log::info;
+use log::{info, warn};
 use crate::chat::chat_manager::Error::{TooManyRooms};
 use crate::chat::name_extractor;
+use crate::chat::slug::slugify;
 use std::fmt;
 use crate::chat::chat_user::User;
-use crate::chat::chat_data::RoomCreated;
+use crate::chat::chat_data::{RoomCreated, UnreadCount, FavoriteRoom, PresenceStatus, RoomPatch, RoomInfo, TopicChanged,
+                             RoomOwnership, OwnershipAction, OwnershipChange, RoomQuota};
+use crate::chat::presence::{self, PRESENCE_SWEEP_INTERVAL};
+use std::thread;
+use std::time::Instant;
+use crate::user::IUser;
+use crate::user::user_db_service::DbServiceError;
+use crate::storage::RoomRecord;
+use chrono::{TimeZone, Utc};
+use std::time::Duration;
+use uuid::Uuid;
+use tungstenite::Message;
 
-const ROOM_LIMIT: usize = 10;
-const ROOM_LIMIT_AND_MGR: usize = ROOM_LIMIT+1;
+pub const DEFAULT_ROOM_LIMIT: usize = 10;
+const MAX_ROOM_NAME: usize = 64;
+const MAX_TOPIC: usize = 250;
+const MAX_DESCRIPTION: usize = 2000;
+const MAX_CO_OWNERS: usize = 10;
+const MAX_CAPACITY: usize = 1000;
+pub const DEFAULT_GRACE: i64 = 300;
+const MAX_GRACE: i64 = 24 * 60 * 60;
+const MAX_LIFETIME: i64 = 30 * 24 * 60 * 60;
+const EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+//Room ids by slug, so a join URL finds its room without scanning every name.
+type SlugIndex = Arc<Mutex<HashMap<String, String>>>;
+
+//How many rooms can run at once and how many of them one user can own. Admins
+//aren't held to the per user quota, the server wide limit still applies to them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoomLimits {
+    pub max_rooms: usize,
+    pub per_user: Option<usize>,
+    pub admins: Vec<String>
+}
+
+impl Default for RoomLimits {
+    fn default() -> Self {
+        RoomLimits {
+            max_rooms: DEFAULT_ROOM_LIMIT,
+            per_user: None,
+            admins: vec![]
+        }
+    }
+}
+
+impl RoomLimits {
+    fn quota_for(&self, user_id: &str) -> Option<usize> {
+        if self.admins.iter().any(|id| id == user_id) {
+            None
+        } else {
+            self.per_user
+        }
+    }
+}
 
 pub struct ChatManager {
-    rooms: Arc<Mutex<HashMap<ChatData, Sender<TcpStream>>>>,
+    rooms: RoomDirectory,
+    slugs: SlugIndex,
     started: AtomicBool,
-    thread: Mutex<ThreadPool>
+    thread: Mutex<ThreadPool>,
+    services: RoomServices,
+    limits: RoomLimits
 }
 
 impl ChatManager {
     pub fn new() -> Self {
         ChatManager {
             rooms: Arc::new(Mutex::new(HashMap::new())),
-            thread: Mutex::new(ThreadPool::new(ROOM_LIMIT_AND_MGR)),
+            slugs: Arc::new(Mutex::new(HashMap::new())),
+            thread: Mutex::new(ThreadPool::new(DEFAULT_ROOM_LIMIT + 1)),
             started: AtomicBool::new(false),
+            services: RoomServices::default(),
+            limits: RoomLimits::default()
         }
     }
 
+    //Each room runs on its own pool thread, with one more for the listener.
+    pub fn with_limits(mut self, limits: RoomLimits) -> Self {
+        self.thread = Mutex::new(ThreadPool::new(limits.max_rooms + 1));
+        self.limits = limits;
+        self
+    }
+
+    pub fn with_services(services: RoomServices) -> Self {
+        let mut cm = ChatManager::new();
+        cm.services = services;
+        cm
+    }
+
     pub fn run(&mut self, server_addr: SocketAddr) {
         if self.started.load(Ordering::Relaxed) {
             panic!("Illegal operation to start manager twice")
         } else {
             let rooms_clone = self.rooms.clone();
+            let slugs = self.slugs.clone();
             self.thread.lock().unwrap().execute(move || {
                 let conn = TcpListener::bind(server_addr).unwrap();
                 info!("Chat server is up and running... waiting for connections.");
                 for new_stream in conn.incoming() {
                     let mut stream = new_stream.unwrap();
-                    if let Some(name) = name_extractor::get_room_name(&mut stream) {
-                        for (room, tx) in rooms_clone.lock().unwrap().iter() {
-                            if room.name().eq(&name) {
-                                return tx.send(stream).unwrap();
+                    if let Some(key) = name_extractor::get_room_key(&mut stream) {
+                        let room_id = ChatManager::resolve_key(&slugs, &key);
+                        let rooms = rooms_clone.lock().unwrap();
+                        match room_id.and_then(|id| rooms.get(&id)) {
+                            Some((_, tx)) => {
+                                let _ = tx.send(RoomRequest::Join(stream));
+                            },
+                            None => {
+                                let _ = stream.write(b"HTTP/1.1 404 NOT FOUND");
                             }
                         }
-                        stream.write(b"HTTP/1.1 404 NOT FOUND");
                     }
                 }
             });
+            self.start_presence_sweep();
+            self.start_expiry_sweep();
             self.started.store(true, Ordering::Relaxed);
         }
     }
@@ -61,9 +140,76 @@ impl ChatManager {
     pub fn create_new_room(&mut self, name: String, owner_id: String) -> Result<RoomCreated, Error> {
         if self.too_many_rooms() {
            Err(Error::TooManyRooms)
+        } else if self.quota(&owner_id).remaining == 0 {
+           Err(Error::QuotaExceeded)
+        } else {
+            self.create_room(name, owner_id, None)
+        }
+    }
+
+    //A room that closes by itself, with neither time given it closes DEFAULT_GRACE
+    //seconds after its last user leaves.
+    pub fn create_temporary_room(&mut self, name: String, owner_id: String, grace: Option<i64>,
+                                 expires_at: Option<i64>) -> Result<RoomCreated, Error> {
+        let grace = if grace.is_none() && expires_at.is_none() { Some(DEFAULT_GRACE) } else { grace };
+        if let Some(grace) = grace {
+            if !(0..=MAX_GRACE).contains(&grace) {
+                return Err(Error::Invalid(format!("Grace period has to be between 0 and {} seconds.", MAX_GRACE)));
+            }
+        }
+        if let Some(expires_at) = expires_at {
+            let now = Utc::now().timestamp();
+            if expires_at <= now || expires_at > now + MAX_LIFETIME {
+                return Err(Error::Invalid(String::from("Expiry has to be in the future and at most 30 days away.")));
+            }
+        }
+        if self.too_many_rooms() {
+            Err(Error::TooManyRooms)
+        } else if self.quota(&owner_id).remaining == 0 {
+            Err(Error::QuotaExceeded)
         } else {
-            self.create_room(name, owner_id)
+            self.create_room(name, owner_id, Some(Expiry::new(grace, expires_at)))
+        }
+    }
+
+    //Starts every room the storage knows about, returning how many came back.
+    pub fn restore_rooms(&mut self) -> Result<usize, DbServiceError> {
+        let records = match self.services.storage.as_ref() {
+            Some(storage) => storage.rooms()?,
+            None => return Ok(0)
+        };
+        let mut restored = 0;
+        for record in records {
+            match Uuid::parse_str(&record.room_id) {
+                Ok(room_id) if !self.too_many_rooms() => {
+                    let (room, client_rx) = mpsc::channel();
+                    //Rooms saved before slugs existed, or whose slug got taken, are given a new one.
+                    let needs_slug = record.slug.is_empty() || self.slugs.lock().unwrap().contains_key(&record.slug);
+                    let slug = if needs_slug { self.unique_slug(&record.name) } else { record.slug };
+                    let meta = RoomMeta {
+                        name: record.name,
+                        topic: record.topic,
+                        description: record.description,
+                        capacity: record.capacity.map(|capacity| capacity as usize)
+                    };
+                    let owners = Owners {
+                        owner_id: record.owner_id,
+                        co_owners: record.co_owners
+                    };
+                    let mut data = ChatData::restore(room_id, meta, slug, owners, record.last_message_id);
+                    if record.grace.is_some() || record.expires_at.is_some() {
+                        data = data.with_expiry(Expiry::new(record.grace, record.expires_at));
+                    }
+                    if needs_slug {
+                        self.save_room(&data);
+                    }
+                    self.add_and_start_room(data, room, client_rx);
+                    restored += 1;
+                },
+                _ => warn!("Skipping saved room {}", record.room_id)
+            }
         }
+        Ok(restored)
     }
 
     pub fn delete_room(&mut self, room_id: String, owner_id: String) -> Result<(), Error> {
@@ -74,58 +220,425 @@ impl ChatManager {
         }
     }
 
+    //Only owners can edit a room, connected users hear about the change right away
+    //and are told who made it by name.
+    pub fn update_room(&self, room_id: &str, user_id: &str, user_name: &str, patch: RoomPatch) -> Result<RoomInfo, Error> {
+        let data = self.room_data(room_id)?;
+        if !data.can_manage(user_id) {
+            return Err(Error::NotOwner);
+        }
+        let mut meta = data.meta();
+        if let Some(name) = patch.name {
+            let name = name.trim();
+            if name.is_empty() {
+                return Err(Error::Invalid(String::from("Room name can't be empty.")));
+            }
+            if name.chars().count() > MAX_ROOM_NAME {
+                return Err(Error::Invalid(format!("Room name can't be longer than {} characters.", MAX_ROOM_NAME)));
+            }
+            let taken = self.rooms.lock().unwrap().values()
+                .any(|(other, _)| other.id() != room_id && other.name().to_lowercase() == name.to_lowercase());
+            if taken {
+                return Err(Error::NameTaken);
+            }
+            meta.name = String::from(name);
+        }
+        set_field(&mut meta.topic, patch.topic, "Topic", MAX_TOPIC)?;
+        set_field(&mut meta.description, patch.description, "Description", MAX_DESCRIPTION)?;
+        match patch.capacity {
+            Some(0) => meta.capacity = None,
+            Some(capacity) if capacity > MAX_CAPACITY =>
+                return Err(Error::Invalid(format!("Room capacity can't be more than {} users.", MAX_CAPACITY))),
+            Some(capacity) => meta.capacity = Some(capacity),
+            None => {}
+        }
+        data.set_meta(meta.clone());
+        self.save_room(&data);
+        //A raised or removed capacity lets whoever is queued in, the room does that on its own thread.
+        if let Some((_, tx)) = self.rooms.lock().unwrap().get(&data.id()) {
+            let _ = tx.send(RoomRequest::AdmitWaiting);
+        }
+
+        let event = TopicChanged {
+            room_id: data.id(),
+            name: meta.name.clone(),
+            topic: meta.topic.clone(),
+            description: meta.description.clone(),
+            changed_by: String::from(user_name)
+        };
+        let msg = Message::text(serde_json::to_string(&event).unwrap());
+        for tx in data.users().lock().unwrap().values() {
+            let _ = tx.send(msg.clone());
+        }
+        Ok(RoomInfo {
+            room_id: data.id(),
+            name: meta.name,
+            slug: data.slug(),
+            topic: meta.topic,
+            description: meta.description,
+            capacity: meta.capacity
+        })
+    }
+
+    //Who owns the room and how that came about, only its owners get to see it.
+    pub fn owners(&self, room_id: &str, user_id: &str) -> Result<RoomOwnership, Error> {
+        let data = self.room_data(room_id)?;
+        if !data.can_manage(user_id) {
+            return Err(Error::NotOwner);
+        }
+        Ok(self.room_owners(&data))
+    }
+
+    //The previous owner stays on as a co-owner, they can step down after.
+    pub fn transfer_ownership(&self, room_id: &str, user_id: &str, new_owner: String) -> Result<RoomOwnership, Error> {
+        let data = self.room_data(room_id)?;
+        if !data.is_owner(user_id) {
+            return Err(Error::NotOwner);
+        }
+        if data.is_owner(&new_owner) {
+            return Err(Error::Invalid(String::from("User already owns the room.")));
+        }
+        let mut owners = data.owners();
+        owners.co_owners.retain(|id| *id != new_owner);
+        if owners.co_owners.len() >= MAX_CO_OWNERS {
+            return Err(Error::Invalid(format!("A room can't have more than {} co-owners, remove one before handing it over.", MAX_CO_OWNERS)));
+        }
+        owners.co_owners.insert(0, owners.owner_id.clone());
+        owners.owner_id = new_owner.clone();
+        self.change_owners(&data, owners, OwnershipAction::Transferred, new_owner, user_id);
+        Ok(self.room_owners(&data))
+    }
+
+    pub fn add_co_owner(&self, room_id: &str, user_id: &str, co_owner: String) -> Result<RoomOwnership, Error> {
+        let data = self.room_data(room_id)?;
+        if !data.is_owner(user_id) {
+            return Err(Error::NotOwner);
+        }
+        let mut owners = data.owners();
+        if owners.includes(&co_owner) {
+            return Err(Error::Invalid(String::from("User already owns the room.")));
+        }
+        if owners.co_owners.len() >= MAX_CO_OWNERS {
+            return Err(Error::Invalid(format!("A room can't have more than {} co-owners.", MAX_CO_OWNERS)));
+        }
+        owners.co_owners.push(co_owner.clone());
+        self.change_owners(&data, owners, OwnershipAction::CoOwnerAdded, co_owner, user_id);
+        Ok(self.room_owners(&data))
+    }
+
+    //The owner can remove any co-owner, a co-owner only themselves.
+    pub fn remove_co_owner(&self, room_id: &str, user_id: &str, co_owner: &str) -> Result<RoomOwnership, Error> {
+        let data = self.room_data(room_id)?;
+        if !data.is_owner(user_id) && user_id != co_owner {
+            return Err(Error::NotOwner);
+        }
+        let mut owners = data.owners();
+        if !owners.co_owners.iter().any(|id| id == co_owner) {
+            return Err(Error::Invalid(String::from("User isn't a co-owner of the room.")));
+        }
+        owners.co_owners.retain(|id| id != co_owner);
+        self.change_owners(&data, owners, OwnershipAction::CoOwnerRemoved, String::from(co_owner), user_id);
+        Ok(self.room_owners(&data))
+    }
+
+    //Called once an account is gone. Its rooms go to their first co-owner, a room
+    //with nobody to take it over is closed so it isn't left without an owner.
+    pub fn hand_over_rooms(&mut self, user_id: &str) {
+        let rooms: Vec<ChatData> = self.rooms.lock().unwrap().values()
+            .map(|(data, _)| data.clone())
+            .filter(|data| data.can_manage(user_id))
+            .collect();
+        for data in rooms {
+            let mut owners = data.owners();
+            if !data.is_owner(user_id) {
+                owners.co_owners.retain(|id| id != user_id);
+                self.change_owners(&data, owners, OwnershipAction::CoOwnerRemoved, String::from(user_id), user_id);
+            } else if owners.co_owners.is_empty() {
+                info!("Closing room {}, its owner's account was deleted", data.name());
+                let _ = self.try_to_delete_room(data.id(), String::from(user_id));
+            } else {
+                owners.owner_id = owners.co_owners.remove(0);
+                let successor = owners.owner_id.clone();
+                self.change_owners(&data, owners, OwnershipAction::HandedOver, successor, user_id);
+            }
+        }
+    }
+
+    //Rooms the user owns count against their quota, co-owned ones don't.
+    pub fn quota(&self, user_id: &str) -> RoomQuota {
+        let rooms = self.rooms.lock().unwrap();
+        let owned = rooms.values().filter(|(data, _)| data.is_owner(user_id)).count();
+        let limit = self.limits.quota_for(user_id);
+        let server_remaining = self.limits.max_rooms.saturating_sub(rooms.len());
+        let remaining = match limit {
+            Some(limit) => limit.saturating_sub(owned).min(server_remaining),
+            None => server_remaining
+        };
+        RoomQuota {
+            owned,
+            limit,
+            remaining,
+            server_remaining
+        }
+    }
+
     pub fn name_is_available(&self, name: &String) -> bool {
         !self.name_is_unavailable(name)
     }
 
     pub fn list_rooms(&self) -> Vec<String> {
         let mut vec = vec![];
-        for room in self.rooms.lock().unwrap().keys() {
+        for (room, _) in self.rooms.lock().unwrap().values() {
             vec.push(room.name());
         }
         vec
     }
 
+    //The id of the room a join URL names, by its id or its slug.
+    pub fn find_room(&self, key: &str) -> Option<String> {
+        ChatManager::resolve_key(&self.slugs, key)
+    }
+
+    pub fn room_exists(&self, room_id: &str) -> bool {
+        self.rooms.lock().unwrap().contains_key(room_id)
+    }
+
+    //Favorites in the user's order with current names, ids of rooms that are gone are skipped.
+    pub fn favorite_rooms(&self, user: &dyn IUser) -> Vec<FavoriteRoom> {
+        let rooms = self.rooms.lock().unwrap();
+        user.favorites()
+            .filter_map(|id| rooms.get(id))
+            .map(|(room, _)| FavoriteRoom {
+                room_id: room.id(),
+                name: room.name()
+            })
+            .collect()
+    }
+
+    pub fn unread_counts(&self, user: &dyn IUser) -> Vec<UnreadCount> {
+        let rooms = self.rooms.lock().unwrap();
+        let mut counts = vec![];
+        for favorite in user.favorites() {
+            if let Some((room, _)) = rooms.get(favorite) {
+                counts.push(UnreadCount {
+                    room_id: room.id(),
+                    name: room.name(),
+                    unread: room.last_message_id().saturating_sub(user.read_marker(&room.id()))
+                });
+            }
+        }
+        counts
+    }
+
+    //Rooms whose messages and files the user can reach, the ones they own or co-own and the ones they're in.
+    pub fn accessible_rooms(&self, user_id: &str) -> Vec<String> {
+        self.rooms.lock().unwrap().values()
+            .filter(|(data, _)| data.owners().includes(user_id)
+                || data.members().iter().any(|(_, id)| id.as_deref() == Some(user_id)))
+            .map(|(data, _)| data.id())
+            .collect()
+    }
+
+    //Tells the user's rooms about a status they set themselves.
+    pub fn announce_presence(&self, user_id: &str, status: PresenceStatus) {
+        presence::announce(&self.rooms, user_id, status, None);
+    }
+
     pub fn get_room_data<T: Extractor>(&self, extractor: &mut T) {
-        for (room, _) in self.rooms.lock().unwrap().iter() {
+        for (room, _) in self.rooms.lock().unwrap().values() {
             room.extract_room_data(extractor);
         }
     }
 
-    fn create_room(&mut self, name: String, owner_id: String) -> Result<RoomCreated, Error> {
+    fn create_room(&mut self, name: String, owner_id: String, expiry: Option<Expiry>) -> Result<RoomCreated, Error> {
         if self.name_is_unavailable(&name) {
             Err(Error::NameTaken)
         } else {
 
             let (room, client_rx) = mpsc::channel();
-            let data = ChatData::new(name, owner_id);
+            let slug = self.unique_slug(&name);
+            let mut data = ChatData::new(name, owner_id).with_slug(slug);
+            if let Some(expiry) = expiry.clone() {
+                data = data.with_expiry(expiry);
+            }
             let result = RoomCreated {
-                path: String::from(""),
+                path: format!("room/{}", data.slug()),
                 name: data.name(),
-                id: data.id()
+                id: data.id(),
+                slug: data.slug(),
+                grace: expiry.as_ref().and_then(|e| e.grace),
+                expires_at: expiry.as_ref().and_then(|e| e.expires_at)
+                    .and_then(|t| Utc.timestamp_opt(t, 0).single())
+                    .map(|t| t.to_rfc3339())
             };
 
+            self.save_room(&data);
             self.add_and_start_room(data, room, client_rx);
             Ok(result)
         }
     }
 
-    fn add_and_start_room(&mut self, room_data: ChatData, room_tx: Sender<TcpStream>, room_rx: Receiver<TcpStream>) {
+    //Moves idle users to away, it runs beside the rooms so it doesn't take a pool thread.
+    fn start_presence_sweep(&self) {
+        if let Some(tracker) = self.services.presence.clone() {
+            let rooms = self.rooms.clone();
+            thread::spawn(move || loop {
+                thread::sleep(PRESENCE_SWEEP_INTERVAL);
+                for (user_id, status) in tracker.sweep(Instant::now()) {
+                    presence::announce(&rooms, &user_id, status, None);
+                }
+            });
+        }
+    }
+
+    fn start_expiry_sweep(&self) {
+        let rooms = self.rooms.clone();
+        let slugs = self.slugs.clone();
+        let services = self.services.clone();
+        thread::spawn(move || loop {
+            thread::sleep(EXPIRY_SWEEP_INTERVAL);
+            ChatManager::expire_rooms(&rooms, &slugs, &services, Utc::now().timestamp());
+        });
+    }
+
+    //Warns the users of rooms about to expire and closes the ones that have.
+    fn expire_rooms(rooms: &RoomDirectory, slugs: &SlugIndex, services: &RoomServices, now: i64) {
+        let checks: Vec<(ChatData, ExpiryCheck)> = rooms.lock().unwrap().values()
+            .filter_map(|(data, _)| data.check_expiry(now).map(|check| (data.clone(), check)))
+            .collect();
+        for (data, check) in checks {
+            match check {
+                ExpiryCheck::Warn(left) => {
+                    let text = format!("This room is temporary and closes in {}.", minutes(left));
+                    ChatRoom::system_message(&data, text);
+                },
+                ExpiryCheck::Close => {
+                    info!("Temporary room {} expired", data.name());
+                    ChatRoom::system_message(&data, String::from("This room has expired and is closing."));
+                    ChatManager::remove_room(rooms, slugs, services, &data.id());
+                },
+                ExpiryCheck::Keep => {}
+            }
+        }
+    }
+
+    fn save_room(&self, data: &ChatData) {
+        if let Some(storage) = self.services.storage.as_ref() {
+            if let Err(e) = storage.save_room(&ChatManager::room_record(data)) {
+                warn!("Unable to save room {}: {}", data.name(), e);
+            }
+        }
+    }
+
+    fn room_record(data: &ChatData) -> RoomRecord {
+        let meta = data.meta();
+        let owners = data.owners();
+        let expiry = data.expiry();
+        RoomRecord {
+            room_id: data.id(),
+            name: meta.name,
+            owner_id: owners.owner_id,
+            last_message_id: data.last_message_id(),
+            topic: meta.topic,
+            description: meta.description,
+            slug: data.slug(),
+            co_owners: owners.co_owners,
+            grace: expiry.as_ref().and_then(|e| e.grace),
+            expires_at: expiry.and_then(|e| e.expires_at),
+            capacity: meta.capacity.map(|capacity| capacity as i64)
+        }
+    }
+
+    fn room_data(&self, room_id: &str) -> Result<ChatData, Error> {
+        match self.rooms.lock().unwrap().get(room_id) {
+            Some((data, _)) => Ok(data.clone()),
+            None => Err(Error::RoomNotFound)
+        }
+    }
+
+    //Saved together with the history entry, so the log can't miss a change.
+    fn change_owners(&self, data: &ChatData, owners: Owners, action: OwnershipAction, user_id: String, changed_by: &str) {
+        data.set_owners(owners);
+        info!("Room {}: {} {} by {}", data.id(), action.as_str(), user_id, changed_by);
+        if let Some(storage) = self.services.storage.as_ref() {
+            let change = OwnershipChange {
+                room_id: data.id(),
+                action,
+                user_id,
+                changed_by: String::from(changed_by),
+                changed_at: Utc::now().timestamp()
+            };
+            if let Err(e) = storage.change_owners(&ChatManager::room_record(data), &change) {
+                warn!("Unable to save owners of room {}: {}", data.name(), e);
+            }
+        }
+    }
+
+    fn room_owners(&self, data: &ChatData) -> RoomOwnership {
+        let owners = data.owners();
+        let history = match self.services.storage.as_ref().map(|storage| storage.ownership_changes(&data.id())) {
+            Some(Ok(history)) => history,
+            Some(Err(e)) => {
+                warn!("Unable to load ownership history of room {}: {}", data.name(), e);
+                vec![]
+            },
+            None => vec![]
+        };
+        RoomOwnership {
+            room_id: data.id(),
+            owner_id: owners.owner_id,
+            co_owners: owners.co_owners,
+            history
+        }
+    }
+
+    fn add_and_start_room(&mut self, room_data: ChatData, room_tx: Sender<RoomRequest>, room_rx: Receiver<RoomRequest>) {
         self.add_room_to_map(room_data.clone(), room_tx);
         self.start_room_thread(room_data, room_rx);
     }
 
-    fn add_room_to_map(&mut self, room_data: ChatData, room_tx: Sender<TcpStream>) {
-        self.rooms.lock().unwrap().insert(room_data, room_tx);
+    fn add_room_to_map(&mut self, room_data: ChatData, room_tx: Sender<RoomRequest>) {
+        self.slugs.lock().unwrap().insert(room_data.slug(), room_data.id());
+        self.rooms.lock().unwrap().insert(room_data.id(), (room_data, room_tx));
     }
 
-    fn start_room_thread(&mut self, room_data: ChatData, client_rx: Receiver<TcpStream>) {
+    fn room_services(&self) -> RoomServices {
+        let mut services = self.services.clone();
+        services.rooms = Some(self.rooms.clone());
+        services
+    }
+
+    fn start_room_thread(&mut self, room_data: ChatData, client_rx: Receiver<RoomRequest>) {
+        let services = self.room_services();
         self.thread.lock().unwrap().execute(move || {
             let mut new_room = ChatRoom::new(room_data);
+            new_room.set_services(services);
             new_room.run_room(client_rx);
         });
     }
 
+    //Ids are tried first, anything else is slugified so a link with the room's name still works.
+    fn resolve_key(slugs: &SlugIndex, key: &str) -> Option<String> {
+        match Uuid::parse_str(key) {
+            Ok(room_id) => Some(room_id.to_string()),
+            Err(_) => slugs.lock().unwrap().get(&slugify(key)).cloned()
+        }
+    }
+
+    //Similar names can share a slug, later ones get a number on the end. Slugs that
+    //look like ids are numbered too, so they can't be mistaken for another room's id.
+    fn unique_slug(&self, name: &str) -> String {
+        let base = slugify(name);
+        let slugs = self.slugs.lock().unwrap();
+        let mut slug = base.clone();
+        let mut n = 2;
+        while slugs.contains_key(&slug) || Uuid::parse_str(&slug).is_ok() {
+            slug = format!("{}-{}", base, n);
+            n += 1;
+        }
+        slug
+    }
+
     fn name_is_unavailable(&self, name: &String) -> bool {
         let name_filter = |data: &ChatData| {
           data.name().to_lowercase() == name.to_lowercase()
@@ -138,18 +651,34 @@ impl ChatManager {
     }
 
     fn try_to_delete_room(&mut self, room_id: String, owner_id: String) -> Result<(), Error> {
-        let key = self.rooms.lock().unwrap().keys().find(|d|{
-            d.id().eq(&room_id)
-        }).unwrap().clone();
-        if key.is_owner(&owner_id) {
-            let sender = self.rooms.lock().unwrap().remove(&key).unwrap();
-            drop(sender);
+        //Co-owners manage the room but only its owner can close it.
+        let is_owner = self.rooms.lock().unwrap().get(&room_id).map_or(false, |(data, _)| data.is_owner(&owner_id));
+        if is_owner {
+            ChatManager::remove_room(&self.rooms, &self.slugs, &self.services, &room_id);
             Ok(())
         } else {
             Err(Error::NotOwner)
         }
     }
 
+    //Dropping the room's sender ends its thread, which closes every connection to it.
+    fn remove_room(rooms: &RoomDirectory, slugs: &SlugIndex, services: &RoomServices, room_id: &str) {
+        if let Some((data, sender)) = rooms.lock().unwrap().remove(room_id) {
+            slugs.lock().unwrap().remove(&data.slug());
+            drop(sender);
+        }
+        if let Some(storage) = services.storage.as_ref() {
+            if let Err(e) = storage.delete_room(room_id) {
+                warn!("Unable to remove deleted room from storage: {}", e);
+            }
+        }
+        if let Some(db) = services.user_db.as_ref() {
+            if let Err(e) = db.remove_room_from_favorites(String::from(room_id)) {
+                warn!("Unable to clear favorites for deleted room: {}", e);
+            }
+        }
+    }
+
     fn id_filter(room_id: &String) -> impl FnMut(&ChatData) -> bool {
         let clone = room_id.clone();
         move |data: &ChatData| {
@@ -158,20 +687,41 @@ impl ChatManager {
     }
 
     fn room_exists_based_on_predicate<F>(&self, filter: F) -> bool where F: FnMut(&ChatData) -> bool,  {
-        self.rooms.lock().unwrap().keys().any(filter)
+        self.rooms.lock().unwrap().values().map(|(data, _)| data).any(filter)
     }
 
     fn too_many_rooms(&mut self) -> bool {
-        self.rooms.lock().unwrap().len() >= ROOM_LIMIT
+        self.rooms.lock().unwrap().len() >= self.limits.max_rooms
+    }
+}
+
+fn minutes(seconds: i64) -> String {
+    match (seconds + 59) / 60 {
+        1 => String::from("a minute"),
+        n => format!("{} minutes", n)
+    }
+}
+
+//Trims the value, an empty one clears the field.
+fn set_field(field: &mut Option<String>, value: Option<String>, label: &str, max_len: usize) -> Result<(), Error> {
+    if let Some(value) = value {
+        let value = value.trim();
+        if value.chars().count() > max_len {
+            return Err(Error::Invalid(format!("{} can't be longer than {} characters.", label, max_len)));
+        }
+        *field = if value.is_empty() { None } else { Some(String::from(value)) };
     }
+    Ok(())
 }
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum Error {
     TooManyRooms,
+    QuotaExceeded,
     RoomNotFound,
     NameTaken,
-    NotOwner
+    NotOwner,
+    Invalid(String)
 }
 
 impl std::error::Error for Error{}
@@ -179,18 +729,27 @@ impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match *self {
             Error::TooManyRooms => write!(f, "Too many rooms running."),
+            Error::QuotaExceeded => write!(f, "You already own as many rooms as you're allowed."),
             Error::RoomNotFound => write!(f, "Room doesn't exist."),
             Error::NameTaken => write!(f, "Name is already in use."),
-            Error::NotOwner => write!(f, "Not authorized to delete room.")
+            Error::NotOwner => write!(f, "Not authorized to manage room."),
+            Error::Invalid(ref e) => write!(f, "{}", e)
         }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::chat::chat_manager::ChatManager;
+    use crate::chat::chat_manager::{ChatManager, RoomLimits, DEFAULT_GRACE, MAX_CO_OWNERS};
     use crate::chat::chat_manager::Error;
     use std::net::{SocketAddr, IpAddr};
+    use crate::user::{User, IUser};
+    use crate::chat::chat_room::RoomServices;
+    use crate::storage::Storage;
+    use crate::storage::sqlite::SqliteStorage;
+    use crate::chat::chat_data::{RoomPatch, TopicChanged, OwnershipAction, RoomQuota};
+    use std::sync::{mpsc, Arc};
+    use chrono::Utc;
 
     #[test]
     fn can_create_up_to_ten_chat_rooms() {
@@ -295,4 +854,345 @@ mod test {
         assert_eq!(name, data.name);
         assert!(data.id.len() > 0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn unread_counts_cover_favorite_rooms() {
+        let mut cm = ChatManager::new();
+        cm.create_new_room(String::from("dunmifsys"), String::from("user-a")).unwrap();
+        let room = cm.create_new_room(String::from("bigtuna"), String::from("user-a")).unwrap();
+        let key = cm.rooms.lock().unwrap().get(&room.id).unwrap().0.clone();
+        for _ in 0..5 {
+            key.next_message_id();
+        }
+
+        let mut user = User::new(String::from("jhalpert"));
+        user.add_favorites(vec![room.id.clone(), String::from("closed-room")]);
+        user.set_read_marker(room.id.clone(), 2);
+
+        let counts = cm.unread_counts(&user);
+        assert_eq!(1, counts.len());
+        assert_eq!(room.id, counts[0].room_id);
+        assert_eq!(3, counts[0].unread);
+    }
+
+    #[test]
+    fn favorite_rooms_follow_user_order_and_skip_missing_rooms() {
+        let mut cm = ChatManager::new();
+        let first = cm.create_new_room(String::from("dunmifsys"), String::from("user-a")).unwrap();
+        let second = cm.create_new_room(String::from("bigtuna"), String::from("user-a")).unwrap();
+
+        let mut user = User::new(String::from("jhalpert"));
+        user.add_favorites(vec![second.id.clone(), String::from("closed-room"), first.id.clone()]);
+
+        let favorites = cm.favorite_rooms(&user);
+        assert_eq!(vec![second.id, first.id], favorites.iter().map(|f| f.room_id.clone()).collect::<Vec<String>>());
+        assert_eq!("bigtuna", favorites[0].name);
+    }
+
+    #[test]
+    fn saved_rooms_come_back_after_a_restart() {
+        let storage: Arc<dyn Storage> = Arc::new(SqliteStorage::in_memory().unwrap());
+        let services = RoomServices {
+            storage: Some(storage.clone()),
+            ..RoomServices::default()
+        };
+        let mut cm = ChatManager::with_services(services.clone());
+        let created = cm.create_new_room(String::from("dunmifsys"), String::from("user-a")).unwrap();
+
+        let mut restarted = ChatManager::with_services(services);
+        assert_eq!(1, restarted.restore_rooms().unwrap());
+        assert!(restarted.room_exists(&created.id));
+        assert!(!restarted.name_is_available(&String::from("DunMifSys")));
+
+        restarted.delete_room(created.id, String::from("user-a")).unwrap();
+        assert!(storage.rooms().unwrap().is_empty());
+    }
+
+    #[test]
+    fn owner_edits_are_broadcast_and_kept() {
+        let storage: Arc<dyn Storage> = Arc::new(SqliteStorage::in_memory().unwrap());
+        let services = RoomServices {
+            storage: Some(storage.clone()),
+            ..RoomServices::default()
+        };
+        let mut cm = ChatManager::with_services(services.clone());
+        let room = cm.create_new_room(String::from("annex"), String::from("user-a")).unwrap();
+        cm.create_new_room(String::from("sales"), String::from("user-a")).unwrap();
+        let (tx, rx) = mpsc::channel();
+        cm.rooms.lock().unwrap().get_mut(&room.id).unwrap().0.add_user(String::from("kkapoor"), None, tx);
+
+        let patch = RoomPatch {
+            name: Some(String::from(" Party Planning ")),
+            topic: Some(String::from("Toby's goodbye party")),
+            ..RoomPatch::default()
+        };
+        let info = cm.update_room(&room.id, "user-a", "mscott", patch).unwrap();
+        assert_eq!("Party Planning", info.name);
+        assert_eq!(Some(String::from("Toby's goodbye party")), info.topic);
+        assert!(!cm.name_is_available(&String::from("party planning")));
+        assert!(cm.name_is_available(&String::from("annex")));
+
+        let event: TopicChanged = serde_json::from_str(rx.try_recv().unwrap().to_text().unwrap()).unwrap();
+        assert_eq!(room.id, event.room_id);
+        assert_eq!("mscott", event.changed_by);
+        assert_eq!(Some(String::from("Toby's goodbye party")), event.topic);
+
+        let mut restarted = ChatManager::with_services(services);
+        restarted.restore_rooms().unwrap();
+        let clear = RoomPatch { topic: Some(String::new()), ..RoomPatch::default() };
+        let info = restarted.update_room(&room.id, "user-a", "mscott", clear).unwrap();
+        assert_eq!("Party Planning", info.name);
+        assert_eq!(None, info.topic);
+    }
+
+    #[test]
+    fn room_edits_are_checked() {
+        let mut cm = ChatManager::new();
+        let room = cm.create_new_room(String::from("annex"), String::from("user-a")).unwrap();
+        cm.create_new_room(String::from("sales"), String::from("user-a")).unwrap();
+        let rename = |name: &str| RoomPatch { name: Some(String::from(name)), ..RoomPatch::default() };
+
+        assert_eq!(Err(Error::NotOwner), cm.update_room(&room.id, "user-b", "dschrute", rename("warehouse")).map(|_| ()));
+        assert_eq!(Err(Error::RoomNotFound), cm.update_room("nowhere", "user-a", "mscott", rename("warehouse")).map(|_| ()));
+        assert_eq!(Err(Error::NameTaken), cm.update_room(&room.id, "user-a", "mscott", rename("SALES")).map(|_| ()));
+        assert!(matches!(cm.update_room(&room.id, "user-a", "mscott", rename("  ")), Err(Error::Invalid(_))));
+        let long_topic = RoomPatch { topic: Some("a".repeat(251)), ..RoomPatch::default() };
+        assert!(matches!(cm.update_room(&room.id, "user-a", "mscott", long_topic), Err(Error::Invalid(_))));
+        assert!(cm.update_room(&room.id, "user-a", "mscott", rename("Annex")).is_ok());
+    }
+
+    #[test]
+    fn room_capacity_is_checked_and_kept() {
+        let storage: Arc<dyn Storage> = Arc::new(SqliteStorage::in_memory().unwrap());
+        let services = RoomServices {
+            storage: Some(storage.clone()),
+            ..RoomServices::default()
+        };
+        let mut cm = ChatManager::with_services(services.clone());
+        let room = cm.create_new_room(String::from("annex"), String::from("user-a")).unwrap();
+        let capacity = |capacity| RoomPatch { capacity: Some(capacity), ..RoomPatch::default() };
+
+        assert!(matches!(cm.update_room(&room.id, "user-a", "mscott", capacity(1001)), Err(Error::Invalid(_))));
+        assert_eq!(Some(25), cm.update_room(&room.id, "user-a", "mscott", capacity(25)).unwrap().capacity);
+        assert_eq!(Some(25), cm.update_room(&room.id, "user-a", "mscott", RoomPatch::default()).unwrap().capacity);
+
+        let mut restarted = ChatManager::with_services(services);
+        restarted.restore_rooms().unwrap();
+        assert_eq!(Some(25), restarted.rooms.lock().unwrap().get(&room.id).unwrap().0.meta().capacity);
+        assert_eq!(None, restarted.update_room(&room.id, "user-a", "mscott", capacity(0)).unwrap().capacity);
+    }
+
+    #[test]
+    fn rooms_are_found_by_id_or_slug() {
+        let mut cm = ChatManager::new();
+        let sales = cm.create_new_room(String::from("Sales Team"), String::from("user-a")).unwrap();
+        let other = cm.create_new_room(String::from("sales  team!"), String::from("user-a")).unwrap();
+        assert_eq!("sales-team", sales.slug);
+        assert_eq!("room/sales-team", sales.path);
+        assert_eq!("sales-team-2", other.slug);
+
+        assert_eq!(Some(sales.id.clone()), cm.find_room(&sales.id));
+        assert_eq!(Some(sales.id.clone()), cm.find_room("sales-team"));
+        assert_eq!(Some(sales.id.clone()), cm.find_room("Sales Team"));
+        assert_eq!(Some(other.id), cm.find_room("sales-team-2"));
+        assert_eq!(None, cm.find_room("annex"));
+
+        cm.delete_room(sales.id, String::from("user-a")).unwrap();
+        assert_eq!(None, cm.find_room("sales-team"));
+    }
+
+    #[test]
+    fn slug_is_kept_across_renames_and_restarts() {
+        let storage: Arc<dyn Storage> = Arc::new(SqliteStorage::in_memory().unwrap());
+        let services = RoomServices {
+            storage: Some(storage.clone()),
+            ..RoomServices::default()
+        };
+        let mut cm = ChatManager::with_services(services.clone());
+        let room = cm.create_new_room(String::from("annex"), String::from("user-a")).unwrap();
+        let rename = RoomPatch { name: Some(String::from("Party Planning")), ..RoomPatch::default() };
+        assert_eq!("annex", cm.update_room(&room.id, "user-a", "mscott", rename).unwrap().slug);
+
+        let mut restarted = ChatManager::with_services(services);
+        restarted.restore_rooms().unwrap();
+        assert_eq!(Some(room.id), restarted.find_room("annex"));
+        assert_eq!("annex", storage.rooms().unwrap()[0].slug);
+    }
+
+    #[test]
+    fn ownership_moves_and_is_logged() {
+        let storage: Arc<dyn Storage> = Arc::new(SqliteStorage::in_memory().unwrap());
+        let services = RoomServices {
+            storage: Some(storage.clone()),
+            ..RoomServices::default()
+        };
+        let mut cm = ChatManager::with_services(services.clone());
+        let room = cm.create_new_room(String::from("annex"), String::from("user-a")).unwrap();
+
+        assert_eq!(Err(Error::NotOwner), cm.add_co_owner(&room.id, "user-b", String::from("user-c")).map(|_| ()));
+        cm.add_co_owner(&room.id, "user-a", String::from("user-b")).unwrap();
+        assert!(matches!(cm.add_co_owner(&room.id, "user-a", String::from("user-b")), Err(Error::Invalid(_))));
+        let patch = RoomPatch { topic: Some(String::from("Bears")), ..RoomPatch::default() };
+        assert!(cm.update_room(&room.id, "user-b", "dschrute", patch).is_ok());
+        assert_eq!(Err(Error::NotOwner), cm.transfer_ownership(&room.id, "user-b", String::from("user-b")).map(|_| ()));
+
+        let owners = cm.transfer_ownership(&room.id, "user-a", String::from("user-b")).unwrap();
+        assert_eq!("user-b", owners.owner_id);
+        assert_eq!(vec![String::from("user-a")], owners.co_owners);
+        let owners = cm.remove_co_owner(&room.id, "user-a", "user-a").unwrap();
+        assert!(owners.co_owners.is_empty());
+        let actions: Vec<OwnershipAction> = owners.history.iter().map(|c| c.action).collect();
+        assert_eq!(vec![OwnershipAction::CoOwnerAdded, OwnershipAction::Transferred, OwnershipAction::CoOwnerRemoved], actions);
+        assert_eq!(Err(Error::NotOwner), cm.owners(&room.id, "user-a").map(|_| ()));
+
+        let mut restarted = ChatManager::with_services(services);
+        restarted.restore_rooms().unwrap();
+        assert_eq!("user-b", restarted.owners(&room.id, "user-b").unwrap().owner_id);
+    }
+
+    #[test]
+    fn co_owners_cannot_delete_and_transfers_keep_the_co_owner_limit() {
+        let mut cm = ChatManager::new();
+        let room = cm.create_new_room(String::from("annex"), String::from("user-a")).unwrap();
+        for n in 0..MAX_CO_OWNERS {
+            cm.add_co_owner(&room.id, "user-a", format!("co-owner-{}", n)).unwrap();
+        }
+        assert_eq!(Err(Error::NotOwner), cm.delete_room(room.id.clone(), String::from("co-owner-0")));
+
+        assert!(matches!(cm.transfer_ownership(&room.id, "user-a", String::from("user-b")), Err(Error::Invalid(_))));
+        assert_eq!("user-a", cm.owners(&room.id, "user-a").unwrap().owner_id);
+        let owners = cm.transfer_ownership(&room.id, "user-a", String::from("co-owner-3")).unwrap();
+        assert_eq!("co-owner-3", owners.owner_id);
+        assert_eq!(MAX_CO_OWNERS, owners.co_owners.len());
+        assert_eq!("user-a", owners.co_owners[0]);
+
+        assert!(cm.delete_room(room.id, String::from("co-owner-3")).is_ok());
+    }
+
+    #[test]
+    fn deleted_owners_hand_their_rooms_on() {
+        let mut cm = ChatManager::new();
+        let shared = cm.create_new_room(String::from("annex"), String::from("user-a")).unwrap();
+        let alone = cm.create_new_room(String::from("sales"), String::from("user-a")).unwrap();
+        let other = cm.create_new_room(String::from("warehouse"), String::from("user-c")).unwrap();
+        cm.add_co_owner(&shared.id, "user-a", String::from("user-b")).unwrap();
+        cm.add_co_owner(&shared.id, "user-a", String::from("user-c")).unwrap();
+        cm.add_co_owner(&other.id, "user-c", String::from("user-a")).unwrap();
+
+        cm.hand_over_rooms("user-a");
+        let owners = cm.owners(&shared.id, "user-b").unwrap();
+        assert_eq!("user-b", owners.owner_id);
+        assert_eq!(vec![String::from("user-c")], owners.co_owners);
+        assert!(!cm.room_exists(&alone.id));
+        assert!(cm.owners(&other.id, "user-c").unwrap().co_owners.is_empty());
+    }
+
+    #[test]
+    fn quota_counts_owned_rooms_except_for_admins() {
+        let limits = RoomLimits {
+            max_rooms: 4,
+            per_user: Some(2),
+            admins: vec![String::from("admin")]
+        };
+        let mut cm = ChatManager::new().with_limits(limits);
+        let first = cm.create_new_room(String::from("annex"), String::from("user-a")).unwrap();
+        cm.create_new_room(String::from("sales"), String::from("user-a")).unwrap();
+        assert_eq!(Err(Error::QuotaExceeded), cm.create_new_room(String::from("warehouse"), String::from("user-a")).map(|_| ()));
+        assert_eq!(RoomQuota { owned: 2, limit: Some(2), remaining: 0, server_remaining: 2 }, cm.quota("user-a"));
+
+        cm.add_co_owner(&first.id, "user-a", String::from("user-b")).unwrap();
+        assert_eq!(0, cm.quota("user-b").owned);
+        cm.delete_room(first.id, String::from("user-a")).unwrap();
+        assert!(cm.create_new_room(String::from("warehouse"), String::from("user-a")).is_ok());
+
+        cm.create_new_room(String::from("accounting"), String::from("admin")).unwrap();
+        assert_eq!(RoomQuota { owned: 1, limit: None, remaining: 1, server_remaining: 1 }, cm.quota("admin"));
+        cm.create_new_room(String::from("party"), String::from("admin")).unwrap();
+        assert_eq!(Err(Error::TooManyRooms), cm.create_new_room(String::from("reception"), String::from("admin")).map(|_| ()));
+        assert_eq!(0, cm.quota("admin").remaining);
+    }
+
+    #[test]
+    fn temporary_rooms_close_once_empty_for_their_grace_period() {
+        let storage: Arc<dyn Storage> = Arc::new(SqliteStorage::in_memory().unwrap());
+        let services = RoomServices {
+            storage: Some(storage.clone()),
+            ..RoomServices::default()
+        };
+        let mut cm = ChatManager::with_services(services);
+        let room = cm.create_temporary_room(String::from("annex"), String::from("user-a"), None, None).unwrap();
+        cm.create_new_room(String::from("sales"), String::from("user-a")).unwrap();
+        assert_eq!(Some(DEFAULT_GRACE), room.grace);
+        assert_eq!(Some(DEFAULT_GRACE), storage.rooms().unwrap()[0].grace);
+
+        let now = Utc::now().timestamp();
+        ChatManager::expire_rooms(&cm.rooms, &cm.slugs, &cm.services, now);
+        ChatManager::expire_rooms(&cm.rooms, &cm.slugs, &cm.services, now + DEFAULT_GRACE - 1);
+        assert!(cm.room_exists(&room.id));
+        ChatManager::expire_rooms(&cm.rooms, &cm.slugs, &cm.services, now + DEFAULT_GRACE);
+        assert!(!cm.room_exists(&room.id));
+        assert_eq!(None, cm.find_room("annex"));
+        assert_eq!(vec![String::from("sales")], storage.rooms().unwrap().into_iter().map(|r| r.name).collect::<Vec<String>>());
+    }
+
+    #[test]
+    fn only_owned_and_joined_rooms_are_accessible() {
+        let mut cm = ChatManager::new();
+        let annex = cm.create_new_room(String::from("annex"), String::from("user-a")).unwrap();
+        let sales = cm.create_new_room(String::from("sales"), String::from("user-b")).unwrap();
+        cm.create_new_room(String::from("warehouse"), String::from("user-b")).unwrap();
+        cm.add_co_owner(&annex.id, "user-a", String::from("user-c")).unwrap();
+        let (tx, _rx) = mpsc::channel();
+        cm.rooms.lock().unwrap().get_mut(&sales.id).unwrap().0.add_user(String::from("kkapoor"), Some(String::from("user-c")), tx);
+
+        assert_eq!(vec![annex.id.clone()], cm.accessible_rooms("user-a"));
+        let mut accessible = cm.accessible_rooms("user-c");
+        accessible.sort();
+        let mut expected = vec![annex.id, sales.id];
+        expected.sort();
+        assert_eq!(expected, accessible);
+        assert!(cm.accessible_rooms("user-d").is_empty());
+    }
+
+    #[test]
+    fn users_are_warned_before_a_room_expires() {
+        let mut cm = ChatManager::new();
+        let expires_at = Utc::now().timestamp() + 3600;
+        let room = cm.create_temporary_room(String::from("annex"), String::from("user-a"), None, Some(expires_at)).unwrap();
+        assert_eq!(None, room.grace);
+        let (tx, rx) = mpsc::channel();
+        cm.rooms.lock().unwrap().get_mut(&room.id).unwrap().0.add_user(String::from("kkapoor"), None, tx);
+
+        ChatManager::expire_rooms(&cm.rooms, &cm.slugs, &cm.services, expires_at - 120);
+        let warning = rx.try_recv().unwrap().to_text().unwrap().to_string();
+        assert!(warning.contains("closes in 2 minutes"));
+        ChatManager::expire_rooms(&cm.rooms, &cm.slugs, &cm.services, expires_at - 100);
+        assert!(rx.try_recv().is_err());
+
+        ChatManager::expire_rooms(&cm.rooms, &cm.slugs, &cm.services, expires_at);
+        assert!(rx.try_recv().unwrap().to_text().unwrap().contains("expired"));
+        assert!(!cm.room_exists(&room.id));
+    }
+
+    #[test]
+    fn temporary_room_settings_are_checked_and_kept() {
+        let storage: Arc<dyn Storage> = Arc::new(SqliteStorage::in_memory().unwrap());
+        let services = RoomServices {
+            storage: Some(storage.clone()),
+            ..RoomServices::default()
+        };
+        let mut cm = ChatManager::with_services(services.clone());
+        let now = Utc::now().timestamp();
+        let owner = || String::from("user-a");
+        assert!(matches!(cm.create_temporary_room(String::from("annex"), owner(), Some(-1), None), Err(Error::Invalid(_))));
+        assert!(matches!(cm.create_temporary_room(String::from("annex"), owner(), None, Some(now - 1)), Err(Error::Invalid(_))));
+        assert!(matches!(cm.create_temporary_room(String::from("annex"), owner(), None, Some(now + 31 * 24 * 3600)), Err(Error::Invalid(_))));
+        let room = cm.create_temporary_room(String::from("annex"), owner(), Some(60), Some(now + 600)).unwrap();
+
+        let mut restarted = ChatManager::with_services(services);
+        restarted.restore_rooms().unwrap();
+        let expiry = restarted.rooms.lock().unwrap().get(&room.id).unwrap().0.expiry().unwrap();
+        assert_eq!(Some(60), expiry.grace);
+        assert_eq!(Some(now + 600), expiry.expires_at);
+    }
+}
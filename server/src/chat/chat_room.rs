@@ -1,121 +1,531 @@
 pub mod room_data;
+pub mod expiry;
+mod mentions;
+mod typing;
 
 use std::sync::{Arc, Mutex, mpsc, RwLock};
 use std::net::TcpStream;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use crate::chat::chat_user::User;
-use crate::chat::chat_data::{ChatUser, ChatMessage};
-use tungstenite::Message;
-use std::sync::mpsc::{Receiver, Sender};
+use crate::chat::chat_data::{ChatUser, ChatMessage, TypingEvent, ReadReceipt, Mention, MentionNotification, LinkPreview, PresenceEvent, PresenceStatus,
+                             RoomFull, QueuePosition};
+use crate::chat::link_preview::{PreviewFetcher, extract_urls};
+use crate::chat::formatting;
+use crate::chat::presence::{self, PresenceTracker};
+use tungstenite::{Message, WebSocket};
+use tungstenite::protocol::CloseFrame;
+use tungstenite::protocol::frame::coding::CloseCode;
+use std::sync::mpsc::{Receiver, Sender, RecvTimeoutError};
 use std::thread;
-use log::info;
+use log::{info, warn};
 use std::hash::{Hash, Hasher};
-use crate::chat::chat_room::room_data::ChatData;
+use crate::chat::chat_room::room_data::{ChatData, Waiting};
+use crate::chat::chat_room::typing::TypingTracker;
+use crate::chat::chat_room::mentions::parse_mentions;
+use std::time::{Duration, Instant};
+use crate::user::user_db_service::UserDbService;
+use crate::chat::message_store::{MessageStore, StoredMessage};
+use crate::storage::Storage;
+use crate::routes::auth::USER_COOKIE;
+use tungstenite::handshake::server::{Request, Response};
+use chrono::Utc;
+use std::borrow::Cow;
+
+const TYPING_SWEEP_INTERVAL: Duration = Duration::from_millis(500);
+const MAX_WAITING: usize = 50;
+
+//Running rooms by room id, with the channel that hands them new connections.
+pub type RoomDirectory = Arc<Mutex<HashMap<String, (ChatData, Sender<RoomRequest>)>>>;
+
+//What a room's own thread is asked to do.
+pub enum RoomRequest {
+    Join(TcpStream),
+    //Sent after the capacity is raised or removed, so the room lets in whoever is queued.
+    AdmitWaiting
+}
+
+//Shared services a room uses, any can be absent when a room runs on its own.
+#[derive(Clone, Default)]
+pub struct RoomServices {
+    pub user_db: Option<Arc<UserDbService>>,
+    pub message_store: Option<Arc<Mutex<MessageStore>>>,
+    pub previews: Option<Arc<PreviewFetcher>>,
+    pub rooms: Option<RoomDirectory>,
+    pub presence: Option<Arc<PresenceTracker>>,
+    //Keeps the room list, so rooms come back after a restart.
+    pub storage: Option<Arc<dyn Storage>>
+}
 
 pub struct ChatRoom {
    data: ChatData,
-   tx: Option<Sender<Message>>
+   tx: Option<Sender<Message>>,
+   services: RoomServices
 }
 
 impl ChatRoom {
     pub fn new(data: ChatData) -> Self {
         ChatRoom {
             data,
-            tx: None
+            tx: None,
+            services: RoomServices::default()
         }
     }
 
-    pub fn run_room(&mut self, new_client: Receiver<TcpStream>) {
+    pub fn set_services(&mut self, services: RoomServices) {
+        self.services = services;
+    }
+
+    pub fn run_room(&mut self, new_client: Receiver<RoomRequest>) {
         let (tx, rx) = mpsc::channel();
-        ChatRoom::run_receiver(self.data.clone(), rx);
+        ChatRoom::run_receiver(self.data.clone(), self.services.clone(), rx);
         self.tx = Some(tx.clone());
         self.process_new_clients(new_client, tx);
     }
 
-    fn process_new_clients(&mut self, new_client: Receiver<TcpStream>, room_tx: Sender<Message>) {
+    fn process_new_clients(&mut self, new_client: Receiver<RoomRequest>, room_tx: Sender<Message>) {
         loop {
-            if let Ok(client) = new_client.recv() {
-                info!("Accepting new user into the room.");
-                self.join_room(client, room_tx.clone());
+            if let Ok(request) = new_client.recv() {
+                match request {
+                    RoomRequest::Join(client) => {
+                        info!("Accepting new user into the room.");
+                        self.join_room(client, room_tx.clone());
+                    },
+                    RoomRequest::AdmitWaiting => ChatRoom::admit_waiting(&self.data, &self.services)
+                }
             } else {
                 info!("Sending end closed, terminating room");
+                for mut waiting in self.data.waiting().lock().unwrap().drain(..) {
+                    let _ = waiting.ws.close(None);
+                    let _ = waiting.ws.write_pending();
+                }
                 self.tx.as_ref().unwrap().send(Message::Close(None));
                 break;
             }
         }
     }
 
-    fn run_receiver(mut room_data: ChatData, rx: Receiver<Message>) {
+    fn run_receiver(mut room_data: ChatData, services: RoomServices, rx: Receiver<Message>) {
         thread::Builder::new()
             .name(format!("{}-Receiver", room_data.name()))
             .spawn(move || {
                 info!("Running receiver thread");
+                let mut typing = TypingTracker::new();
                 loop {
-                    if let Ok(msg) = rx.recv() {
-                        room_data.add_message(msg.clone());
-                        info!("Message received");
-                        match msg {
-                            Message::Text(txt) => {
-                                if let Ok(chat_msg) = serde_json::from_str::<ChatMessage>(&txt) {
-                                    ChatRoom::send_msg_to_users(
-                                        room_data.users(), Some(&chat_msg.from),
-                                        Message::text(txt));
+                    match rx.recv_timeout(TYPING_SWEEP_INTERVAL) {
+                        Ok(Message::Text(txt)) => {
+                            if let Ok(event) = serde_json::from_str::<TypingEvent>(&txt) {
+                                ChatRoom::user_active(&room_data, &services, &event.from);
+                                if let Some(event) = typing.update(&event.from, event.typing, Instant::now()) {
+                                    ChatRoom::send_typing_event(&room_data, &services, event);
                                 }
-                                info!("Message sent");
-                            },
-                            Message::Close(frame) => {
-                                ChatRoom::send_msg_to_users(
-                                    room_data.users(), None, Message::Close(frame));
-                                info!("Close frame sent to users, receiver shutting down.");
-                                break;
-                            },
-                            Message::Binary(bin) => (),
-                            _ => ()
-                        }
+                                continue;
+                            }
+                            if let Ok(receipt) = serde_json::from_str::<ReadReceipt>(&txt) {
+                                ChatRoom::mark_read(&room_data, &services, receipt);
+                                continue;
+                            }
+                            info!("Message received");
+                            if let Ok(chat_msg) = serde_json::from_str::<ChatMessage>(&txt) {
+                                ChatRoom::user_active(&room_data, &services, &chat_msg.from);
+                                if let Some(event) = typing.stopped(&chat_msg.from, Instant::now()) {
+                                    ChatRoom::send_typing_event(&room_data, &services, event);
+                                }
+                                ChatRoom::handle_chat_message(&mut room_data, &services, chat_msg);
+                            } else {
+                                room_data.add_message(Message::Text(txt));
+                            }
+                            info!("Message sent");
+                        },
+                        Ok(Message::Close(frame)) => {
+                            room_data.add_message(Message::Close(frame.clone()));
+                            ChatRoom::send_msg_to_users(
+                                &room_data, None, &[], Message::Close(frame));
+                            info!("Close frame sent to users, receiver shutting down.");
+                            break;
+                        },
+                        Ok(msg) => {
+                            room_data.add_message(msg);
+                        },
+                        Err(RecvTimeoutError::Timeout) => {
+                            for event in typing.expire(Instant::now()) {
+                                ChatRoom::send_typing_event(&room_data, &services, event);
+                            }
+                        },
+                        Err(RecvTimeoutError::Disconnected) => break
                     }
                 }
             });
     }
 
+    fn handle_chat_message(room_data: &mut ChatData, services: &RoomServices, mut chat_msg: ChatMessage) {
+        chat_msg.id = Some(room_data.next_message_id());
+        chat_msg.html = Some(formatting::render(&chat_msg.msg));
+        chat_msg.mentions = ChatRoom::resolve_mentions(room_data, services.user_db.as_ref(), &chat_msg.msg);
+        chat_msg.attachments = ChatRoom::room_attachments(room_data, services.message_store.as_ref(), &chat_msg.attachments);
+        let stamped = Message::text(serde_json::to_string(&chat_msg).unwrap());
+        room_data.add_message(stamped.clone());
+        ChatRoom::persist_message(room_data, services.message_store.as_ref(), &chat_msg);
+        let blocked_by = ChatRoom::blocked_by(room_data, services, &chat_msg.from);
+        ChatRoom::send_msg_to_users(room_data, Some(&chat_msg.from), &blocked_by, stamped);
+        if let Some(rooms) = services.rooms.as_ref() {
+            ChatRoom::notify_mentioned_users(room_data, rooms, &chat_msg, &blocked_by);
+        }
+        if let Some(previews) = services.previews.as_ref() {
+            ChatRoom::send_link_previews(room_data, previews.clone(), &chat_msg, blocked_by);
+        }
+    }
+
+    //Pages are fetched off the receiver thread, previews follow the message once they're ready.
+    fn send_link_previews(room_data: &ChatData, previews: Arc<PreviewFetcher>, chat_msg: &ChatMessage, blocked_by: Vec<String>) {
+        let urls = extract_urls(&chat_msg.msg);
+        if urls.is_empty() {
+            return;
+        }
+        let room_data = room_data.clone();
+        let message_id = chat_msg.id.unwrap_or(0);
+        let fetcher = previews.clone();
+        let queued = fetcher.run_later(move || {
+            for url in urls {
+                if let Some(page) = previews.preview(&url) {
+                    let preview = LinkPreview {
+                        message_id,
+                        url,
+                        title: page.title,
+                        description: page.description,
+                        image: page.image
+                    };
+                    let json = serde_json::to_string(&preview).unwrap();
+                    ChatRoom::send_msg_to_users(&room_data, None, &blocked_by, Message::text(json));
+                }
+            }
+        });
+        if !queued {
+            info!("Too many link previews waiting, skipping the ones for message {}.", message_id);
+        }
+    }
+
+    fn resolve_mentions(room_data: &ChatData, user_db: Option<&Arc<UserDbService>>, text: &str) -> Vec<Mention> {
+        let mut mentions = vec![];
+        for name in parse_mentions(text) {
+            if let Some(member) = room_data.member_named(&name) {
+                let registered = room_data.user_id_of(&member).is_some();
+                mentions.push(Mention { name: member, registered });
+            } else if let Some(db) = user_db {
+                if let Ok(user) = db.find_user_by_name(name) {
+                    if user.user_id().is_some() {
+                        mentions.push(Mention { name: user.user_name().clone(), registered: true });
+                    }
+                }
+            }
+        }
+        mentions
+    }
+
+    //Drops attachment ids that weren't uploaded to this room.
+    fn room_attachments(room_data: &ChatData, store: Option<&Arc<Mutex<MessageStore>>>, ids: &[String]) -> Vec<String> {
+        match store {
+            Some(store) => {
+                let store = store.lock().unwrap();
+                ids.iter()
+                    .filter(|id| match store.find_attachment(id) {
+                        Ok(Some(attachment)) => attachment.room_id == room_data.id(),
+                        _ => false
+                    })
+                    .cloned()
+                    .collect()
+            },
+            None => vec![]
+        }
+    }
+
+    //Mentioned users connected to other rooms still get told, members of this room already have the message.
+    fn notify_mentioned_users(room_data: &ChatData, rooms: &RoomDirectory, chat_msg: &ChatMessage, blocked_by: &[String]) {
+        for mention in chat_msg.mentions.iter() {
+            if room_data.member_named(&mention.name).is_some() {
+                continue;
+            }
+            let notification = MentionNotification {
+                mentioned: mention.name.clone(),
+                room_id: room_data.id(),
+                room: room_data.name(),
+                message_id: chat_msg.id.unwrap_or(0),
+                from: chat_msg.from.clone(),
+                msg: chat_msg.msg.clone()
+            };
+            let json = Message::text(serde_json::to_string(&notification).unwrap());
+            for (room, _) in rooms.lock().unwrap().values() {
+                if let Some(member) = room.member_named(&mention.name) {
+                    if room.user_id_of(&member).map_or(false, |id| blocked_by.contains(&id)) {
+                        continue;
+                    }
+                    if let Some(tx) = room.users().lock().unwrap().get(&member) {
+                        let _ = tx.send(json.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    fn persist_message(room_data: &ChatData, store: Option<&Arc<Mutex<MessageStore>>>, chat_msg: &ChatMessage) {
+        if let Some(store) = store {
+            let msg = StoredMessage {
+                room_id: room_data.id(),
+                message_id: chat_msg.id.unwrap_or(0),
+                from: chat_msg.from.clone(),
+                sender_id: room_data.user_id_of(&chat_msg.from),
+                msg: chat_msg.msg.clone(),
+                rendered: chat_msg.html.clone().unwrap_or_default(),
+                mentions: chat_msg.mentions.iter().map(|m| m.name.clone()).collect(),
+                attachments: chat_msg.attachments.clone(),
+                sent_at: Utc::now().timestamp()
+            };
+            if let Err(e) = store.lock().unwrap().save(&msg) {
+                warn!("Unable to persist message from {}: {}", chat_msg.from, e);
+            }
+        }
+    }
+
+    //The receipt's from is the name its connection joined under, so only that member's marker moves.
+    fn mark_read(room_data: &ChatData, services: &RoomServices, receipt: ReadReceipt) {
+        if receipt.read > room_data.last_message_id() {
+            return;
+        }
+        if let (Some(db), Some(user_id)) = (services.user_db.as_ref(), room_data.user_id_of(&receipt.from)) {
+            if let Err(e) = db.update_read_marker(user_id, room_data.id(), receipt.read) {
+                warn!("Unable to persist read marker for {}: {}", receipt.from, e);
+            }
+        }
+        let json = serde_json::to_string(&receipt).unwrap();
+        let blocked_by = ChatRoom::blocked_by(room_data, services, &receipt.from);
+        ChatRoom::send_msg_to_users(room_data, Some(&receipt.from), &blocked_by, Message::text(json));
+    }
+
     fn join_room(&mut self, stream: TcpStream, tx: Sender<Message>) {
-        if let Ok(mut ws) = tungstenite::accept(stream) {
+        let mut cookie = None;
+        let accepted = tungstenite::accept_hdr(stream, |request: &Request, response: Response| {
+            cookie = user_cookie(request);
+            Ok(response)
+        });
+        if let Ok(mut ws) = accepted {
             ws.write_message(Message::text(String::from("Enter user info"))).unwrap();
             let data = ws.read_message().unwrap().into_text().unwrap();
-            let json: ChatUser = serde_json::from_str(&data).unwrap();
+            let mut user: ChatUser = serde_json::from_str(&data).unwrap();
+            user.user_id = ChatRoom::connection_user_id(&self.services, cookie);
 
-            let mut new_user = User::new(json.name);
-            self.new_user_joined_msg(new_user.name());
+            //Admitting only ever happens with the queue locked, so two joins can't both take the last place.
+            let waiting = self.data.waiting();
+            let mut queue = waiting.lock().unwrap();
+            if self.data.is_full() || !queue.is_empty() {
+                self.wait_or_turn_away(&mut queue, ws, user, tx);
+            } else {
+                ChatRoom::admit(&self.data, &self.services, ws, user, tx);
+            }
+        }
+    }
 
-            let (user_tx, user_rx) = mpsc::channel();
-            self.data.add_user(new_user.name(), user_tx);
+    //Without a user database there's nothing to check the cookie against, rooms running on their
+    //own take it as it is.
+    fn connection_user_id(services: &RoomServices, cookie: Option<String>) -> Option<String> {
+        let user_id = cookie?;
+        match services.user_db.as_ref() {
+            Some(db) => match db.retrieve_user_by_id(user_id.clone()) {
+                Ok(user) if user.user_id().is_some() => {
+                    let _ = db.guest_seen(user.as_ref());
+                    Some(user_id)
+                },
+                Ok(_) => None,
+                Err(e) => {
+                    warn!("Unable to check the user joining the room: {}", e);
+                    None
+                }
+            },
+            None => Some(user_id)
+        }
+    }
 
-            thread::spawn(move || {
-                new_user.run_user(ws, tx, user_rx);
-            });
+    //Only users who asked to wait are queued, and only while the queue has room,
+    //everyone else gets a close frame saying the room is full.
+    fn wait_or_turn_away(&self, queue: &mut VecDeque<Waiting>, mut ws: WebSocket<TcpStream>, user: ChatUser, room_tx: Sender<Message>) {
+        let capacity = self.data.meta().capacity.unwrap_or(0);
+        if user.wait && queue.len() < MAX_WAITING {
+            let position = QueuePosition { position: queue.len() + 1, capacity };
+            if ws.write_message(Message::text(serde_json::to_string(&position).unwrap())).is_ok() {
+                info!("Room is full, {} is waiting at position {}.", user.name, position.position);
+                queue.push_back(Waiting { ws, user, room_tx });
+            }
+            return;
+        }
+        let reason = RoomFull { error: String::from("room_full"), capacity };
+        let _ = ws.close(Some(CloseFrame {
+            code: CloseCode::Again,
+            reason: Cow::Owned(serde_json::to_string(&reason).unwrap())
+        }));
+        let _ = ws.write_pending();
+    }
+
+    fn admit(room_data: &ChatData, services: &RoomServices, ws: WebSocket<TcpStream>, user: ChatUser, tx: Sender<Message>) {
+        let mut room_data = room_data.clone();
+        let new_user = User::new(user.name);
+        let user_id = user.user_id;
+        ChatRoom::new_user_joined_msg(&room_data, new_user.name());
+        ChatRoom::presence_changed(&room_data, services, user_id.as_deref(), true);
+        let presence = ChatRoom::presence_of(services, new_user.name(), user_id.clone());
+        ChatRoom::send_msg_to_users(&room_data, None, &[], Message::text(serde_json::to_string(&presence).unwrap()));
+
+        //The newcomer hears about everyone already here before any new messages.
+        let (user_tx, user_rx) = mpsc::channel();
+        for (name, user_id) in room_data.members() {
+            let presence = ChatRoom::presence_of(services, name, user_id);
+            let _ = user_tx.send(Message::text(serde_json::to_string(&presence).unwrap()));
+        }
+        room_data.add_user(new_user.name(), user_id.clone(), user_tx);
+
+        let services = services.clone();
+        thread::spawn(move || {
+            let room_tx = tx.clone();
+            new_user.run_user(ws, tx, user_rx);
+            room_data.remove_user(&new_user.name());
+            //Leaving stops any typing, the receiver then forgets them once that has gone out.
+            let stopped = TypingEvent { from: new_user.name(), typing: false };
+            let _ = room_tx.send(Message::text(serde_json::to_string(&stopped).unwrap()));
+            ChatRoom::presence_changed(&room_data, &services, user_id.as_deref(), false);
+            let mut presence = ChatRoom::presence_of(&services, new_user.name(), user_id);
+            presence.online = false;
+            presence.profile = None;
+            ChatRoom::send_msg_to_users(&room_data, None, &[], Message::text(serde_json::to_string(&presence).unwrap()));
+            ChatRoom::admit_waiting(&room_data, &services);
+        });
+    }
+
+    //Lets queued users in, in order, while there's space, then tells the rest where they stand.
+    //The queue stays locked throughout so a newcomer can't slip in ahead of them.
+    pub fn admit_waiting(room_data: &ChatData, services: &RoomServices) {
+        let waiting = room_data.waiting();
+        let mut queue = waiting.lock().unwrap();
+        let mut admitted = false;
+        while !room_data.is_full() {
+            match queue.pop_front() {
+                Some(next) => {
+                    ChatRoom::admit(room_data, services, next.ws, next.user, next.room_tx);
+                    admitted = true;
+                },
+                None => break
+            }
+        }
+        if !admitted {
+            return;
+        }
+        let capacity = room_data.meta().capacity.unwrap_or(0);
+        let mut position = 0;
+        //Anyone whose socket has gone away since they joined the queue is dropped from it.
+        queue.retain_mut(|waiting| {
+            let update = QueuePosition { position: position + 1, capacity };
+            let sent = waiting.ws.write_message(Message::text(serde_json::to_string(&update).unwrap())).is_ok();
+            if sent {
+                position += 1;
+            }
+            sent
+        });
+    }
+
+    //Updates the global status when a registered user connects or leaves, other rooms
+    //hear about it from here, this room gets its own join or leave event.
+    fn presence_changed(room_data: &ChatData, services: &RoomServices, user_id: Option<&str>, connected: bool) {
+        if let (Some(tracker), Some(user_id)) = (services.presence.as_ref(), user_id) {
+            let now = Instant::now();
+            let change = if connected {
+                tracker.connected(user_id, &room_data.id(), now)
+            } else {
+                tracker.disconnected(user_id, &room_data.id(), now)
+            };
+            if let (Some(status), Some(rooms)) = (change, services.rooms.as_ref()) {
+                presence::announce(rooms, user_id, status, Some(&room_data.id()));
+            }
         }
     }
 
-    fn new_user_joined_msg(&self, name: String) {
+    //Sending a message or typing counts as activity and brings an idle user back from away.
+    fn user_active(room_data: &ChatData, services: &RoomServices, user_name: &str) {
+        if let (Some(tracker), Some(user_id)) = (services.presence.as_ref(), room_data.user_id_of(user_name)) {
+            if let (Some(status), Some(rooms)) = (tracker.active(&user_id, Instant::now()), services.rooms.as_ref()) {
+                presence::announce(rooms, &user_id, status, None);
+            }
+        }
+    }
+
+    fn presence_of(services: &RoomServices, name: String, user_id: Option<String>) -> PresenceEvent {
+        let status = match (services.presence.as_ref(), user_id.as_ref()) {
+            (Some(tracker), Some(id)) => tracker.status(id, Instant::now()),
+            _ => PresenceStatus::Online
+        };
+        let profile = match (services.user_db.as_ref(), user_id.clone()) {
+            (Some(db), Some(id)) => match db.retrieve_user_by_id(id) {
+                Ok(user) if user.user_id().is_some() => Some(user.to_user().profile),
+                Ok(_) => None,
+                Err(e) => {
+                    warn!("Unable to load the profile of {}: {}", name, e);
+                    None
+                }
+            },
+            _ => None
+        };
+        PresenceEvent {
+            name,
+            registered: user_id.is_some(),
+            online: true,
+            status,
+            profile
+        }
+    }
+
+    fn new_user_joined_msg(room_data: &ChatData, name: String) {
+        ChatRoom::system_message(room_data, format!("New user, {}, joined the chat!", name));
+    }
+
+    //An unnumbered message from the server, it isn't kept with the room's history.
+    pub fn system_message(room_data: &ChatData, text: String) {
         let msg = ChatMessage {
+            id: None,
             from: String::from("Admin"),
-            msg: format!("New user, {}, joined the chat!", name)
+            html: Some(formatting::render(&text)),
+            msg: text,
+            mentions: vec![],
+            attachments: vec![]
         };
         let json = serde_json::to_string(&msg).unwrap();
         let msg= Message::text(json);
-        ChatRoom::send_msg_to_users(self.data.users(), None, msg)
+        ChatRoom::send_msg_to_users(room_data, None, &[], msg)
     }
 
-    fn send_msg_to_users(users: Arc<Mutex<HashMap<String, Sender<Message>>>>, excluded_user: Option<&str>, msg: Message) {
-        let name = match excluded_user {
-            Some(s) => s,
-            None => ""
-        };
-        for (user_name, tx) in users.lock().unwrap().iter() {
-            if !user_name.eq(name) {
-                tx.send(msg.clone());
+    //Everyone but excluded_user gets the message, apart from users who blocked its sender.
+    fn send_msg_to_users(room_data: &ChatData, excluded_user: Option<&str>, blocked_by: &[String], msg: Message) {
+        let skipped: Vec<String> = room_data.members().into_iter()
+            .filter(|(name, id)| excluded_user == Some(name.as_str()) || id.as_ref().map_or(false, |id| blocked_by.contains(id)))
+            .map(|(name, _)| name)
+            .collect();
+        for (user_name, tx) in room_data.users().lock().unwrap().iter() {
+            if !skipped.contains(user_name) {
+                let _ = tx.send(msg.clone());
             }
         }
     }
+
+    //Senders without an account can't be blocked, there is no id to block.
+    fn blocked_by(room_data: &ChatData, services: &RoomServices, sender: &str) -> Vec<String> {
+        match (services.user_db.as_ref(), room_data.user_id_of(sender)) {
+            (Some(db), Some(user_id)) => db.blocked_by(&user_id).unwrap_or_else(|e| {
+                warn!("Unable to load who blocked {}: {}", sender, e);
+                vec![]
+            }),
+            _ => vec![]
+        }
+    }
+
+    fn send_typing_event(room_data: &ChatData, services: &RoomServices, event: TypingEvent) {
+        let json = serde_json::to_string(&event).unwrap();
+        let blocked_by = ChatRoom::blocked_by(room_data, services, &event.from);
+        ChatRoom::send_msg_to_users(room_data, Some(&event.from), &blocked_by, Message::text(json));
+    }
 }
 
 pub trait Extractor {
@@ -123,13 +533,36 @@ pub trait Extractor {
     fn handle_users(&mut self, users: std::slice::Iter<String>);
 }
 
+//Browsers send the site's cookies with the websocket handshake, the user id one says who is connecting.
+fn user_cookie(request: &Request) -> Option<String> {
+    request.headers().get_all("cookie").iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == USER_COOKIE)
+        .map(|(_, value)| String::from(value))
+}
+
 #[cfg(test)]
 mod test {
     use std::sync::mpsc;
-    use crate::chat::chat_room::ChatRoom;
+    use tungstenite::Message;
+    use crate::chat::chat_room::{ChatRoom, RoomServices, RoomRequest};
     use crate::chat::chat_room::room_data::ChatData;
+    use crate::chat::chat_data::PresenceStatus;
+    use crate::chat::presence::PresenceTracker;
     use std::thread::{sleep, spawn};
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
+    use std::sync::Arc;
+    use crate::user::{User, ProfilePatch};
+    use crate::user::user_db_service::UserDbService;
+    use crate::chat::chat_room::room_data::RoomMeta;
+    use crate::chat::chat_data::QueuePosition;
+    use std::net::{TcpListener, TcpStream};
+    use tungstenite::WebSocket;
+    use tungstenite::protocol::frame::coding::CloseCode;
+    use tungstenite::handshake::client::Request;
+    use crate::chat::chat_data::ChatMessage;
 
     #[test]
     fn closing_sender_closes_room() {
@@ -146,4 +579,212 @@ mod test {
         // doesn't run infinitely the thread closed and we are good.
         room.run_room(rx);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn presence_carries_the_profile_of_registered_users() {
+        let db = Arc::new(UserDbService::new());
+        let user = db.create_user(Box::new(User::new(String::from("jhalpert")))).unwrap();
+        let user_id = user.user_id().unwrap().clone();
+        db.update_profile(user_id.clone(), ProfilePatch {
+            display_name: Some(String::from("Jim")),
+            ..ProfilePatch::default()
+        }).unwrap();
+
+        let tracker = Arc::new(PresenceTracker::new());
+        tracker.connected(&user_id, "room-1", Instant::now());
+        tracker.set_manual(&user_id, PresenceStatus::Dnd, Instant::now());
+        let services = RoomServices {
+            user_db: Some(db),
+            presence: Some(tracker),
+            ..RoomServices::default()
+        };
+
+        let presence = ChatRoom::presence_of(&services, String::from("jhalpert"), Some(user_id.clone()));
+        assert!(presence.online);
+        assert!(presence.registered);
+        assert!(!serde_json::to_string(&presence).unwrap().contains(&user_id));
+        assert_eq!(PresenceStatus::Dnd, presence.status);
+        assert_eq!(Some(String::from("Jim")), presence.profile.unwrap().display_name);
+
+        let guest = ChatRoom::presence_of(&services, String::from("guest"), None);
+        assert_eq!(PresenceStatus::Online, guest.status);
+        assert!(!guest.registered);
+        assert!(guest.profile.is_none());
+        let unknown = ChatRoom::presence_of(&services, String::from("ghost"), Some(String::from("nobody")));
+        assert!(unknown.profile.is_none());
+    }
+
+    #[test]
+    fn users_who_blocked_the_sender_are_skipped() {
+        let mut data = ChatData::new(String::from("room"), String::from("owner"));
+        let (jim_tx, jim_rx) = mpsc::channel();
+        let (dwight_tx, dwight_rx) = mpsc::channel();
+        let (guest_tx, guest_rx) = mpsc::channel();
+        data.add_user(String::from("jhalpert"), Some(String::from("abcd-1234")), jim_tx);
+        data.add_user(String::from("dschrute"), Some(String::from("cdef-3456")), dwight_tx);
+        data.add_user(String::from("guest"), None, guest_tx);
+
+        let blocked_by = vec![String::from("abcd-1234")];
+        ChatRoom::send_msg_to_users(&data, Some("dschrute"), &blocked_by, Message::text("Bears. Beets."));
+
+        assert!(jim_rx.try_recv().is_err());
+        assert!(dwight_rx.try_recv().is_err());
+        assert_eq!(Message::text("Bears. Beets."), guest_rx.try_recv().unwrap());
+    }
+
+    fn start_room(data: &ChatData, services: RoomServices) -> String {
+        start_room_with_requests(data, services).0
+    }
+
+    fn start_room_with_requests(data: &ChatData, services: RoomServices) -> (String, mpsc::Sender<RoomRequest>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (tx, rx) = mpsc::channel();
+        let requests = tx.clone();
+        spawn(move || {
+            for stream in listener.incoming() {
+                tx.send(RoomRequest::Join(stream.unwrap())).unwrap();
+            }
+        });
+        let mut room = ChatRoom::new(data.clone());
+        room.set_services(services);
+        spawn(move || room.run_room(rx));
+        (addr, requests)
+    }
+
+    fn join(addr: &str, name: &str, wait: bool) -> WebSocket<TcpStream> {
+        join_as(addr, name, None, wait)
+    }
+
+    fn join_as(addr: &str, name: &str, user_id: Option<&str>, wait: bool) -> WebSocket<TcpStream> {
+        let mut request = Request::builder().uri(format!("ws://{}/room", addr));
+        if let Some(user_id) = user_id {
+            request = request.header("Cookie", format!("theme=dark; user-id={}", user_id));
+        }
+        let stream = TcpStream::connect(addr).unwrap();
+        let (mut ws, _) = tungstenite::client(request.body(()).unwrap(), stream).unwrap();
+        ws.read_message().unwrap();
+        let json = format!("{{\"name\":\"{}\",\"wait\":{}}}", name, wait);
+        ws.write_message(Message::text(json)).unwrap();
+        ws
+    }
+
+    //Everything the socket gets until it goes quiet.
+    fn received(ws: &mut WebSocket<TcpStream>) -> Vec<String> {
+        ws.get_mut().set_read_timeout(Some(Duration::from_millis(300))).unwrap();
+        let mut texts = vec![];
+        while let Ok(msg) = ws.read_message() {
+            texts.push(msg.into_text().unwrap());
+        }
+        texts
+    }
+
+    fn wait_until(done: impl Fn() -> bool) {
+        for _ in 0..100 {
+            if done() {
+                return;
+            }
+            sleep(Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn full_room_turns_users_away_or_queues_them() {
+        let data = ChatData::new(String::from("annex"), String::from("owner"));
+        data.set_meta(RoomMeta { capacity: Some(1), ..data.meta() });
+        let addr = start_room(&data, RoomServices::default());
+
+        let mut jim = join(&addr, "jhalpert", false);
+        let mut dwight = join(&addr, "dschrute", false);
+        match dwight.read_message().unwrap() {
+            Message::Close(Some(frame)) => {
+                assert_eq!(CloseCode::Again, frame.code);
+                assert_eq!(r#"{"error":"room_full","capacity":1}"#, frame.reason);
+            },
+            other => panic!("expected a close frame, got {:?}", other)
+        }
+
+        let mut pam = join(&addr, "pbeesly", true);
+        let position: QueuePosition = serde_json::from_str(pam.read_message().unwrap().to_text().unwrap()).unwrap();
+        assert_eq!(1, position.position);
+        wait_until(|| data.waiting_count() == 1);
+        assert_eq!(1, data.waiting_count());
+
+        jim.get_mut().shutdown(std::net::Shutdown::Both).unwrap();
+        wait_until(|| data.waiting_count() == 0);
+        assert_eq!(0, data.waiting_count());
+        assert_eq!(vec![(String::from("pbeesly"), None)], data.members());
+    }
+
+    #[test]
+    fn raised_capacity_lets_the_queue_in_from_the_room_thread() {
+        let data = ChatData::new(String::from("annex"), String::from("owner"));
+        data.set_meta(RoomMeta { capacity: Some(1), ..data.meta() });
+        let (addr, requests) = start_room_with_requests(&data, RoomServices::default());
+
+        let _jim = join(&addr, "jhalpert", false);
+        let _pam = join(&addr, "pbeesly", true);
+        let _kevin = join(&addr, "kmalone", true);
+        wait_until(|| data.waiting_count() == 2);
+        assert_eq!(1, data.members().len());
+
+        data.set_meta(RoomMeta { capacity: Some(2), ..data.meta() });
+        requests.send(RoomRequest::AdmitWaiting).unwrap();
+        wait_until(|| data.waiting_count() == 1);
+        assert_eq!(1, data.waiting_count());
+        let mut names: Vec<String> = data.members().into_iter().map(|(name, _)| name).collect();
+        names.sort();
+        assert_eq!(vec![String::from("jhalpert"), String::from("pbeesly")], names);
+    }
+
+    #[test]
+    fn blocks_hold_against_a_spoofed_sender_or_user_id() {
+        let db = Arc::new(UserDbService::new());
+        let jim = db.create_user(Box::new(User::new(String::from("jhalpert")))).unwrap().user_id().cloned().unwrap();
+        let dwight = db.create_user(Box::new(User::new(String::from("dschrute")))).unwrap().user_id().cloned().unwrap();
+        db.block_user(jim.clone(), dwight.clone()).unwrap();
+        let data = ChatData::new(String::from("annex"), String::from("owner"));
+        let addr = start_room(&data, RoomServices { user_db: Some(db), ..RoomServices::default() });
+
+        let mut jim_ws = join_as(&addr, "jhalpert", Some(&jim), false);
+        let mut pam_ws = join(&addr, "pbeesly", false);
+        let mut dwight_ws = join_as(&addr, "dschrute", Some(&dwight), false);
+        let mut stranger_ws = join_as(&addr, "stranger", Some("made-up-id"), false);
+        wait_until(|| data.members().len() == 4);
+        assert_eq!(Some(dwight), data.user_id_of("dschrute"));
+        assert_eq!(None, data.user_id_of("stranger"));
+        received(&mut jim_ws);
+        received(&mut pam_ws);
+
+        dwight_ws.write_message(Message::text(r#"{"from":"pbeesly","msg":"Bears. Beets."}"#)).unwrap();
+        stranger_ws.write_message(Message::text(r#"{"from":"dschrute","msg":"Battlestar Galactica."}"#)).unwrap();
+        let for_pam: Vec<ChatMessage> = received(&mut pam_ws).iter()
+            .filter_map(|text| serde_json::from_str(text).ok())
+            .collect();
+        assert_eq!(vec![("dschrute", "Bears. Beets."), ("stranger", "Battlestar Galactica.")],
+                   for_pam.iter().map(|m| (m.from.as_str(), m.msg.as_str())).collect::<Vec<_>>());
+        let for_jim = received(&mut jim_ws);
+        assert!(!for_jim.iter().any(|text| text.contains("Bears")));
+        assert!(for_jim.iter().any(|text| text.contains("Battlestar")));
+    }
+
+    #[test]
+    fn read_receipts_only_move_the_senders_marker() {
+        let db = Arc::new(UserDbService::new());
+        let jim = db.create_user(Box::new(User::new(String::from("jhalpert")))).unwrap().user_id().cloned().unwrap();
+        let dwight = db.create_user(Box::new(User::new(String::from("dschrute")))).unwrap().user_id().cloned().unwrap();
+        let data = ChatData::new(String::from("annex"), String::from("owner"));
+        data.next_message_id();
+        let addr = start_room(&data, RoomServices { user_db: Some(db.clone()), ..RoomServices::default() });
+
+        let _jim_ws = join_as(&addr, "jhalpert", Some(&jim), false);
+        let mut dwight_ws = join_as(&addr, "dschrute", Some(&dwight), false);
+        wait_until(|| data.members().len() == 2);
+        dwight_ws.write_message(Message::text(r#"{"from":"jhalpert","read":1}"#)).unwrap();
+
+        let marker = |user_id: &str| db.retrieve_user_by_id(String::from(user_id)).unwrap().read_marker(&data.id());
+        wait_until(|| marker(&dwight) == 1);
+        assert_eq!(1, marker(&dwight));
+        assert_eq!(0, marker(&jim));
+    }
+}
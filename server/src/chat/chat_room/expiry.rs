@@ -0,0 +1,90 @@
+//Seconds before a fixed expiry that users still in the room get warned.
+pub const EXPIRY_WARNINGS: [i64; 2] = [300, 60];
+
+#[derive(Debug, PartialEq)]
+pub enum ExpiryCheck {
+    Keep,
+    Warn(i64),
+    Close
+}
+
+//When a temporary room closes: grace seconds after its last user leaves, at expires_at,
+//or whichever comes first. Times are unix seconds so they can be saved with the room.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expiry {
+    pub grace: Option<i64>,
+    pub expires_at: Option<i64>,
+    empty_since: Option<i64>,
+    warned: Option<i64>
+}
+
+impl Expiry {
+    pub fn new(grace: Option<i64>, expires_at: Option<i64>) -> Self {
+        Expiry {
+            grace,
+            expires_at,
+            empty_since: None,
+            warned: None
+        }
+    }
+
+    //An empty room starts its grace period the first time it's checked.
+    pub fn check(&mut self, occupied: bool, now: i64) -> ExpiryCheck {
+        if occupied {
+            self.empty_since = None;
+        } else if self.empty_since.is_none() {
+            self.empty_since = Some(now);
+        }
+        if let (Some(grace), Some(empty_since)) = (self.grace, self.empty_since) {
+            if now >= empty_since + grace {
+                return ExpiryCheck::Close;
+            }
+        }
+        match self.expires_at {
+            Some(expires_at) if now >= expires_at => ExpiryCheck::Close,
+            Some(expires_at) if occupied => self.warning(expires_at - now),
+            _ => ExpiryCheck::Keep
+        }
+    }
+
+    //Each warning goes out once, a room created close to its expiry skips the earlier ones.
+    fn warning(&mut self, left: i64) -> ExpiryCheck {
+        let due = EXPIRY_WARNINGS.iter()
+            .filter(|warning| left <= **warning && self.warned.map_or(true, |warned| **warning < warned))
+            .min();
+        match due {
+            Some(warning) => {
+                self.warned = Some(*warning);
+                ExpiryCheck::Warn(left)
+            },
+            None => ExpiryCheck::Keep
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chat::chat_room::expiry::{Expiry, ExpiryCheck};
+
+    #[test]
+    fn empty_room_closes_after_its_grace_period() {
+        let mut expiry = Expiry::new(Some(60), None);
+        assert_eq!(ExpiryCheck::Keep, expiry.check(false, 1000));
+        assert_eq!(ExpiryCheck::Keep, expiry.check(true, 1050));
+        assert_eq!(ExpiryCheck::Keep, expiry.check(false, 1100));
+        assert_eq!(ExpiryCheck::Keep, expiry.check(false, 1159));
+        assert_eq!(ExpiryCheck::Close, expiry.check(false, 1160));
+    }
+
+    #[test]
+    fn users_are_warned_once_before_a_fixed_expiry() {
+        let mut expiry = Expiry::new(None, Some(1000));
+        assert_eq!(ExpiryCheck::Keep, expiry.check(true, 600));
+        assert_eq!(ExpiryCheck::Warn(300), expiry.check(true, 700));
+        assert_eq!(ExpiryCheck::Keep, expiry.check(true, 800));
+        assert_eq!(ExpiryCheck::Warn(50), expiry.check(true, 950));
+        assert_eq!(ExpiryCheck::Keep, expiry.check(true, 960));
+        assert_eq!(ExpiryCheck::Keep, expiry.check(false, 990));
+        assert_eq!(ExpiryCheck::Close, expiry.check(true, 1000));
+    }
+}
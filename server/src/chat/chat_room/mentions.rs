@@ -0,0 +1,62 @@
+//Finds @name tokens, an @ only starts a mention at the beginning of a word so emails are skipped.
+pub fn parse_mentions(text: &str) -> Vec<String> {
+    let mut names: Vec<String> = vec![];
+    let mut previous: Option<char> = None;
+    let mut chars = text.char_indices().peekable();
+    while let Some((idx, c)) = chars.next() {
+        let starts_word = previous.map_or(true, |p| !is_name_char(p));
+        previous = Some(c);
+        if c != '@' || !starts_word {
+            continue;
+        }
+        let start = idx + 1;
+        let mut end = start;
+        while let Some((i, next)) = chars.peek() {
+            if is_name_char(*next) {
+                end = i + next.len_utf8();
+                previous = Some(*next);
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        let name = text[start..end].trim_end_matches(|c| c == '.' || c == '-');
+        if !name.is_empty() && !names.iter().any(|n| n.eq_ignore_ascii_case(name)) {
+            names.push(String::from(name));
+        }
+    }
+    names
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '.'
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chat::chat_room::mentions::parse_mentions;
+
+    #[test]
+    fn finds_mentions_in_text() {
+        let names = parse_mentions("@jhalpert can you ask @dschrute about the beets?");
+        assert_eq!(vec!["jhalpert", "dschrute"], names);
+    }
+
+    #[test]
+    fn trailing_punctuation_is_not_part_of_name() {
+        let names = parse_mentions("Thanks @pbeesly.");
+        assert_eq!(vec!["pbeesly"], names);
+    }
+
+    #[test]
+    fn email_addresses_are_not_mentions() {
+        let names = parse_mentions("mail mscott@dundermifflin.com");
+        assert!(names.is_empty());
+    }
+
+    #[test]
+    fn duplicate_mentions_are_collapsed() {
+        let names = parse_mentions("@kmalone @KMalone @ @kmalone");
+        assert_eq!(vec!["kmalone"], names);
+    }
+}
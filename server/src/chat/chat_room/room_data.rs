@@ -1,57 +1,236 @@
 use std::sync::{Arc, Mutex, RwLock};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::net::TcpStream;
 use std::sync::mpsc::Sender;
-use tungstenite::Message;
+use tungstenite::{Message, WebSocket};
+use crate::chat::chat_data::ChatUser;
 use crate::chat::chat_room::Extractor;
-use std::hash::{Hash, Hasher};
+use crate::chat::slug::slugify;
+use crate::chat::chat_room::expiry::{Expiry, ExpiryCheck};
 use uuid::Uuid;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+//The parts of a room its owner can edit.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RoomMeta {
+    pub name: String,
+    pub topic: Option<String>,
+    pub description: Option<String>,
+    //Most users connected at once, None for no limit.
+    pub capacity: Option<usize>
+}
+
+impl RoomMeta {
+    pub fn named(name: String) -> Self {
+        RoomMeta {
+            name,
+            ..RoomMeta::default()
+        }
+    }
+}
+
+//Co-owners can edit the room, only the owner deletes it, hands it on or picks co-owners.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Owners {
+    pub owner_id: String,
+    pub co_owners: Vec<String>
+}
+
+impl Owners {
+    pub fn only(owner_id: String) -> Self {
+        Owners {
+            owner_id,
+            co_owners: vec![]
+        }
+    }
+
+    pub fn includes(&self, user_id: &str) -> bool {
+        self.owner_id == user_id || self.co_owners.iter().any(|id| id == user_id)
+    }
+}
+
+//Someone who asked to wait for a place in a full room, with the channel to the room
+//they'll talk on once they're let in.
+pub struct Waiting {
+    pub ws: WebSocket<TcpStream>,
+    pub user: ChatUser,
+    pub room_tx: Sender<Message>
+}
 
 #[derive(Clone)]
 pub struct ChatData{
     room_id: Uuid,
-    room_name: String,
-    owner_id: String,
+    //Shared by every clone, so an edit reaches the room's threads too.
+    meta: Arc<RwLock<RoomMeta>>,
+    //Fixed when the room is created, so links keep working after a rename.
+    slug: String,
+    owners: Arc<RwLock<Owners>>,
     users: Arc<Mutex<HashMap<String, Sender<Message>>>>,
-    history: Arc<RwLock<Vec<Message>>>
+    user_ids: Arc<Mutex<HashMap<String, String>>>,
+    history: Arc<RwLock<Vec<Message>>>,
+    last_message_id: Arc<AtomicU64>,
+    //Only temporary rooms have one.
+    expiry: Option<Arc<Mutex<Expiry>>>,
+    waiting: Arc<Mutex<VecDeque<Waiting>>>
 }
 
 impl ChatData {
     pub fn new(name: String, owner_id: String) -> Self {
         ChatData {
             room_id: Uuid::new_v4(),
-            room_name: name,
-            owner_id,
+            slug: slugify(&name),
+            meta: Arc::new(RwLock::new(RoomMeta::named(name))),
+            owners: Arc::new(RwLock::new(Owners::only(owner_id))),
             users: Arc::new(Mutex::new(HashMap::new())),
-            history: Arc::new(RwLock::new(Vec::new()))
+            user_ids: Arc::new(Mutex::new(HashMap::new())),
+            history: Arc::new(RwLock::new(Vec::new())),
+            last_message_id: Arc::new(AtomicU64::new(0)),
+            expiry: None,
+            waiting: Arc::new(Mutex::new(VecDeque::new()))
+        }
+    }
+
+    //Brings back a saved room, numbering carries on after its last saved message.
+    pub fn restore(room_id: Uuid, meta: RoomMeta, slug: String, owners: Owners, last_message_id: u64) -> Self {
+        let data = ChatData::new(String::new(), String::new());
+        data.set_meta(meta);
+        data.set_owners(owners);
+        data.last_message_id.store(last_message_id, Ordering::SeqCst);
+        ChatData {
+            room_id,
+            slug,
+            ..data
+        }
+    }
+
+    //The manager picks the slug when the one made from the name is already in use.
+    pub fn with_slug(self, slug: String) -> Self {
+        ChatData {
+            slug,
+            ..self
         }
     }
 
+    pub fn with_expiry(self, expiry: Expiry) -> Self {
+        ChatData {
+            expiry: Some(Arc::new(Mutex::new(expiry))),
+            ..self
+        }
+    }
+
+    pub fn expiry(&self) -> Option<Expiry> {
+        self.expiry.as_ref().map(|expiry| expiry.lock().unwrap().clone())
+    }
+
+    //None for rooms that don't expire.
+    pub fn check_expiry(&self, now: i64) -> Option<ExpiryCheck> {
+        let occupied = !self.users.lock().unwrap().is_empty();
+        self.expiry.as_ref().map(|expiry| expiry.lock().unwrap().check(occupied, now))
+    }
+
     pub fn id(&self) ->  String {
         self.room_id.to_string()
     }
 
     pub fn name(&self) -> String {
-        self.room_name.clone()
+        self.meta.read().unwrap().name.clone()
+    }
+
+    pub fn slug(&self) -> String {
+        self.slug.clone()
+    }
+
+    pub fn meta(&self) -> RoomMeta {
+        self.meta.read().unwrap().clone()
+    }
+
+    pub fn set_meta(&self, meta: RoomMeta) {
+        *self.meta.write().unwrap() = meta;
     }
 
     pub fn add_message(&mut self, new_msg: Message) {
         self.history.write().unwrap().push(new_msg);
     }
 
+    //Message ids start at 1 so a read marker of 0 means nothing has been read.
+    pub fn next_message_id(&self) -> u64 {
+        self.last_message_id.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    pub fn last_message_id(&self) -> u64 {
+        self.last_message_id.load(Ordering::SeqCst)
+    }
+
     pub fn users(&self) -> Arc<Mutex<HashMap<String, Sender<Message>>>> {
         self.users.clone()
     }
 
-    pub fn add_user(&mut self, user_name: String, tx: Sender<Message>) {
+    pub fn add_user(&mut self, user_name: String, user_id: Option<String>, tx: Sender<Message>) {
+        if let Some(id) = user_id {
+            self.user_ids.lock().unwrap().insert(user_name.clone(), id);
+        }
         self.users.lock().unwrap().insert(user_name, tx);
     }
 
-    pub fn is_owner(&self, owner_id: &String) -> bool {
-        self.owner_id.eq(owner_id)
+    pub fn remove_user(&mut self, user_name: &str) {
+        self.user_ids.lock().unwrap().remove(user_name);
+        self.users.lock().unwrap().remove(user_name);
+    }
+
+    pub fn member_named(&self, user_name: &str) -> Option<String> {
+        self.users.lock().unwrap().keys()
+            .find(|name| name.to_lowercase() == user_name.to_lowercase())
+            .cloned()
+    }
+
+    pub fn user_id_of(&self, user_name: &str) -> Option<String> {
+        self.user_ids.lock().unwrap().get(user_name).cloned()
+    }
+
+    //Everyone in the room with their user id, anyone who joined without an account has none.
+    pub fn members(&self) -> Vec<(String, Option<String>)> {
+        let user_ids = self.user_ids.lock().unwrap();
+        self.users.lock().unwrap().keys()
+            .map(|name| (name.clone(), user_ids.get(name).cloned()))
+            .collect()
+    }
+
+    //Lowering the capacity doesn't remove anyone, the room just takes nobody new until it has space.
+    pub fn is_full(&self) -> bool {
+        match self.meta.read().unwrap().capacity {
+            Some(capacity) => self.users.lock().unwrap().len() >= capacity,
+            None => false
+        }
+    }
+
+    #[cfg(test)]
+    pub fn waiting_count(&self) -> usize {
+        self.waiting.lock().unwrap().len()
+    }
+
+    pub fn waiting(&self) -> Arc<Mutex<VecDeque<Waiting>>> {
+        self.waiting.clone()
+    }
+
+    pub fn owners(&self) -> Owners {
+        self.owners.read().unwrap().clone()
+    }
+
+    pub fn set_owners(&self, owners: Owners) {
+        *self.owners.write().unwrap() = owners;
+    }
+
+    pub fn is_owner(&self, owner_id: &str) -> bool {
+        self.owners.read().unwrap().owner_id == owner_id
+    }
+
+    //The owner or a co-owner.
+    pub fn can_manage(&self, user_id: &str) -> bool {
+        self.owners.read().unwrap().includes(user_id)
     }
 
     pub fn extract_room_data<T: Extractor>(&self, extractor: &mut T) {
-        extractor.pass_name(self.room_name.clone());
+        extractor.pass_name(self.name());
         let mut users = vec![];
         for key in self.users.lock().unwrap().keys() {
             users.push(key.clone());
@@ -60,30 +239,93 @@ impl ChatData {
     }
 }
 
-impl PartialEq for ChatData {
-    fn eq(&self, other: &Self) -> bool {
-        return self.room_name.eq(&other.room_name)
+#[cfg(test)]
+mod tests {
+    use crate::chat::chat_room::room_data::{ChatData, RoomMeta, Owners};
+    use std::sync::mpsc;
+
+    #[test]
+    fn message_ids_start_at_one_and_increase() {
+        let data = ChatData::new(String::from("room"), String::from("owner"));
+        assert_eq!(0, data.last_message_id());
+        assert_eq!(1, data.next_message_id());
+        assert_eq!(2, data.next_message_id());
+        assert_eq!(2, data.last_message_id());
     }
 
-    fn ne(&self, other: &Self) -> bool {
-        return !self.eq(other)
+    #[test]
+    fn user_id_is_remembered_for_registered_users() {
+        let mut data = ChatData::new(String::from("room"), String::from("owner"));
+        let (tx, _rx) = mpsc::channel();
+        data.add_user(String::from("jhalpert"), Some(String::from("abcd-1234")), tx.clone());
+        data.add_user(String::from("guest"), None, tx);
+
+        assert_eq!(Some(String::from("abcd-1234")), data.user_id_of("jhalpert"));
+        assert_eq!(None, data.user_id_of("guest"));
     }
-}
 
-impl Eq for ChatData {}
+    #[test]
+    fn removed_user_is_no_longer_a_member() {
+        let mut data = ChatData::new(String::from("room"), String::from("owner"));
+        let (tx, _rx) = mpsc::channel();
+        data.add_user(String::from("jhalpert"), Some(String::from("abcd-1234")), tx);
+        data.remove_user("jhalpert");
 
-impl Hash for ChatData {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.room_name.hash(state);
+        assert!(data.members().is_empty());
+        assert_eq!(None, data.user_id_of("jhalpert"));
     }
 
-    fn hash_slice<H: Hasher>(data: &[Self], state: &mut H) where
-        Self: Sized, {
-        unimplemented!()
+    #[test]
+    fn room_is_full_at_its_capacity() {
+        let mut data = ChatData::new(String::from("room"), String::from("owner"));
+        let (tx, _rx) = mpsc::channel();
+        data.add_user(String::from("jhalpert"), None, tx.clone());
+        data.add_user(String::from("pbeesly"), None, tx);
+        assert!(!data.is_full());
+
+        data.set_meta(RoomMeta { capacity: Some(2), ..data.meta() });
+        assert!(data.is_full());
+        data.remove_user("pbeesly");
+        assert!(!data.is_full());
     }
-}
 
-#[cfg(test)]
-mod tests {
+    #[test]
+    fn meta_changes_are_seen_by_every_clone() {
+        let data = ChatData::new(String::from("room"), String::from("owner"));
+        let clone = data.clone();
+        data.set_meta(RoomMeta {
+            name: String::from("annex"),
+            topic: Some(String::from("Party planning")),
+            description: None,
+            capacity: None
+        });
+
+        assert_eq!("annex", clone.name());
+        assert_eq!(Some(String::from("Party planning")), clone.meta().topic);
+    }
+
+    #[test]
+    fn members_are_found_ignoring_case() {
+        let mut data = ChatData::new(String::from("room"), String::from("owner"));
+        let (tx, _rx) = mpsc::channel();
+        data.add_user(String::from("JHalpert"), None, tx);
 
-}
\ No newline at end of file
+        assert_eq!(Some(String::from("JHalpert")), data.member_named("jhalpert"));
+        assert_eq!(None, data.member_named("dschrute"));
+    }
+
+    #[test]
+    fn co_owners_can_manage_but_only_the_owner_owns() {
+        let data = ChatData::new(String::from("room"), String::from("owner"));
+        data.set_owners(Owners {
+            owner_id: String::from("owner"),
+            co_owners: vec![String::from("co-owner")]
+        });
+
+        assert!(data.can_manage("owner"));
+        assert!(data.can_manage("co-owner"));
+        assert!(!data.can_manage("stranger"));
+        assert!(data.is_owner("owner"));
+        assert!(!data.is_owner("co-owner"));
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use crate::chat::chat_data::TypingEvent;
+
+pub const TYPING_TIMEOUT: Duration = Duration::from_secs(5);
+pub const TYPING_RATE_LIMIT: Duration = Duration::from_secs(1);
+
+//Tracks who is composing a message, typing state is never persisted.
+pub struct TypingTracker {
+    typing: HashMap<String, Instant>,
+    //When each user's state last went out and what it was, dropped once they're shown as stopped.
+    last_broadcast: HashMap<String, (Instant, bool)>
+}
+
+impl TypingTracker {
+    pub fn new() -> Self {
+        TypingTracker {
+            typing: HashMap::new(),
+            last_broadcast: HashMap::new()
+        }
+    }
+
+    pub fn update(&mut self, user: &str, typing: bool, now: Instant) -> Option<TypingEvent> {
+        if typing {
+            self.typing.insert(user.to_string(), now + TYPING_TIMEOUT);
+        } else {
+            self.typing.remove(user);
+        }
+        self.broadcast(user, typing, now)
+    }
+
+    pub fn stopped(&mut self, user: &str, now: Instant) -> Option<TypingEvent> {
+        self.update(user, false, now)
+    }
+
+    //Also sends whatever changes the rate limit held back, so nobody is left showing as typing.
+    pub fn expire(&mut self, now: Instant) -> Vec<TypingEvent> {
+        self.typing.retain(|_, expires| *expires > now);
+        let mut events = vec![];
+        let users: Vec<String> = self.last_broadcast.keys().cloned().collect();
+        for user in users {
+            let (last, shown) = self.last_broadcast[&user];
+            let typing = self.typing.contains_key(&user);
+            if now.duration_since(last) < TYPING_RATE_LIMIT {
+                continue;
+            }
+            if shown != typing {
+                self.last_broadcast.insert(user.clone(), (now, typing));
+                events.push(TypingTracker::event(&user, typing));
+            } else if !typing {
+                self.last_broadcast.remove(&user);
+            }
+        }
+        events
+    }
+
+    //At most one event per user every TYPING_RATE_LIMIT, whether they start or stop.
+    fn broadcast(&mut self, user: &str, typing: bool, now: Instant) -> Option<TypingEvent> {
+        match self.last_broadcast.get(user) {
+            Some((last, _)) if now.duration_since(*last) < TYPING_RATE_LIMIT => return None,
+            Some((_, shown)) if !typing && !shown => return None,
+            None if !typing => return None,
+            _ => {}
+        }
+        self.last_broadcast.insert(user.to_string(), (now, typing));
+        Some(TypingTracker::event(user, typing))
+    }
+
+    fn event(user: &str, typing: bool) -> TypingEvent {
+        TypingEvent {
+            from: user.to_string(),
+            typing
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chat::chat_room::typing::{TypingTracker, TYPING_RATE_LIMIT, TYPING_TIMEOUT};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn first_typing_event_is_broadcast() {
+        let mut tracker = TypingTracker::new();
+        let event = tracker.update("jhalpert", true, Instant::now());
+        assert!(event.unwrap().typing);
+    }
+
+    #[test]
+    fn repeated_typing_events_are_rate_limited() {
+        let mut tracker = TypingTracker::new();
+        let now = Instant::now();
+        tracker.update("jhalpert", true, now);
+        assert!(tracker.update("jhalpert", true, now + Duration::from_millis(100)).is_none());
+        assert!(tracker.update("jhalpert", true, now + TYPING_RATE_LIMIT).is_some());
+    }
+
+    #[test]
+    fn typing_expires_after_timeout() {
+        let mut tracker = TypingTracker::new();
+        let now = Instant::now();
+        tracker.update("jhalpert", true, now);
+        assert!(tracker.expire(now + Duration::from_secs(1)).is_empty());
+
+        let expired = tracker.expire(now + TYPING_TIMEOUT);
+        assert_eq!(1, expired.len());
+        assert_eq!("jhalpert", expired[0].from);
+        assert!(!expired[0].typing);
+    }
+
+    #[test]
+    fn toggling_is_rate_limited_and_the_last_state_follows() {
+        let mut tracker = TypingTracker::new();
+        let now = Instant::now();
+        assert!(tracker.update("jhalpert", true, now).is_some());
+        assert!(tracker.update("jhalpert", false, now + Duration::from_millis(100)).is_none());
+        assert!(tracker.update("jhalpert", true, now + Duration::from_millis(200)).is_none());
+        assert!(tracker.update("jhalpert", false, now + Duration::from_millis(300)).is_none());
+        assert!(tracker.expire(now + Duration::from_millis(500)).is_empty());
+
+        let flushed = tracker.expire(now + TYPING_RATE_LIMIT);
+        assert_eq!(1, flushed.len());
+        assert!(!flushed[0].typing);
+    }
+
+    #[test]
+    fn users_are_forgotten_once_shown_as_stopped() {
+        let mut tracker = TypingTracker::new();
+        let now = Instant::now();
+        tracker.update("jhalpert", true, now);
+        tracker.expire(now + TYPING_TIMEOUT);
+        assert!(!tracker.last_broadcast.is_empty());
+        assert!(tracker.expire(now + TYPING_TIMEOUT + TYPING_RATE_LIMIT).is_empty());
+        assert!(tracker.last_broadcast.is_empty());
+    }
+
+    #[test]
+    fn stopping_without_typing_is_not_broadcast() {
+        let mut tracker = TypingTracker::new();
+        assert!(tracker.update("dschrute", false, Instant::now()).is_none());
+    }
+}
@@ -1,8 +1,14 @@
-use std::sync::mpsc::{Sender, Receiver};
+use std::sync::mpsc::{Sender, Receiver, RecvTimeoutError};
 use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 use std::net::TcpStream;
 use tungstenite::{WebSocket, Message};
 use std::thread;
+use serde_json::Value;
+
+//How often the writer checks whether the reader saw the socket close.
+const CLOSE_CHECK_INTERVAL: Duration = Duration::from_millis(250);
 
 pub struct User {
     name: String,
@@ -19,12 +25,28 @@ impl User {
         self.name.clone()
     }
 
+    //Whatever a frame says in its from field, it came from this connection.
+    fn tag(&self, msg: Message) -> Message {
+        if let Message::Text(text) = &msg {
+            if let Ok(Value::Object(mut frame)) = serde_json::from_str::<Value>(text) {
+                frame.insert(String::from("from"), Value::String(self.name.clone()));
+                return Message::text(Value::Object(frame).to_string());
+            }
+        }
+        msg
+    }
+
     pub fn run_user(&self, mut ws: WebSocket<TcpStream>, room: Sender<Message>, user_rx: Receiver<Message>) {
         let locked = Mutex::new(user_rx);
+        let closed = AtomicBool::new(false);
         unsafe {
             let output = thread::Builder::new().spawn_unchecked(|| {
                 loop {
-                    let msg = locked.lock().unwrap().recv().unwrap();
+                    let msg = match locked.lock().unwrap().recv_timeout(CLOSE_CHECK_INTERVAL) {
+                        Ok(msg) => msg,
+                        Err(RecvTimeoutError::Timeout) if !closed.load(Ordering::SeqCst) => continue,
+                        Err(_) => break
+                    };
                     let result = ws.write_message(msg);
                     match result {
                         Ok(()) => (),
@@ -39,13 +61,14 @@ impl User {
             loop {
                 match ws.read_message() {
                     Ok(msg) => {
-                        room.send(msg);
+                        room.send(self.tag(msg));
                     }
                     Err(_) => {
                         break;
                     }
                 }
             }
+            closed.store(true, Ordering::SeqCst);
             output.join();
         }
     }
@@ -0,0 +1,179 @@
+use url::Url;
+
+const LINK_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+
+//Renders the supported subset (**bold**, *italic*, `code`, ```blocks```, [text](url)) to HTML.
+//Everything else is escaped, so the output is safe to insert into a page as is.
+pub fn render(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("```") {
+        let after = &rest[start + 3..];
+        match after.find("```") {
+            Some(end) => {
+                render_inline(&rest[..start], &mut out);
+                out.push_str("<pre><code>");
+                escape_into(after[..end].trim_start_matches('\n'), &mut out);
+                out.push_str("</code></pre>");
+                rest = &after[end + 3..];
+            },
+            None => break
+        }
+    }
+    render_inline(rest, &mut out);
+    out
+}
+
+fn render_inline(text: &str, out: &mut String) {
+    let mut i = 0;
+    while i < text.len() {
+        let rest = &text[i..];
+        let prev = text[..i].chars().last();
+
+        if let Some(inner) = rest.strip_prefix('`') {
+            if let Some(end) = inner.find('`') {
+                if end > 0 {
+                    out.push_str("<code>");
+                    escape_into(&inner[..end], out);
+                    out.push_str("</code>");
+                    i += end + 2;
+                    continue;
+                }
+            }
+        }
+
+        if let Some(inner) = rest.strip_prefix("**") {
+            if let Some(end) = closing(inner, "**").filter(|_| opens(inner)) {
+                out.push_str("<strong>");
+                render_inline(&inner[..end], out);
+                out.push_str("</strong>");
+                i += end + 4;
+                continue;
+            }
+        }
+
+        if (rest.starts_with('*') || rest.starts_with('_')) && !prev.map_or(false, char::is_alphanumeric) {
+            let marker = &rest[..1];
+            if let Some(end) = closing(&rest[1..], marker).filter(|_| opens(&rest[1..])) {
+                out.push_str("<em>");
+                render_inline(&rest[1..end + 1], out);
+                out.push_str("</em>");
+                i += end + 2;
+                continue;
+            }
+        }
+
+        if rest.starts_with('[') {
+            if let Some((label, url, len)) = link(rest) {
+                out.push_str("<a href=\"");
+                escape_into(&url, out);
+                out.push_str("\" rel=\"nofollow noopener noreferrer\" target=\"_blank\">");
+                escape_into(label, out);
+                out.push_str("</a>");
+                i += len;
+                continue;
+            }
+        }
+
+        let c = rest.chars().next().unwrap();
+        if c == '\n' {
+            out.push_str("<br>");
+        } else {
+            escape_char(c, out);
+        }
+        i += c.len_utf8();
+    }
+}
+
+fn opens(text: &str) -> bool {
+    text.chars().next().map_or(false, |c| !c.is_whitespace())
+}
+
+//Finds a closing marker that isn't followed by a word character, so snake_case stays intact.
+fn closing(text: &str, marker: &str) -> Option<usize> {
+    let mut from = 0;
+    while let Some(pos) = text[from..].find(marker) {
+        let end = from + pos;
+        let next = text[end + marker.len()..].chars().next();
+        if end > 0 && !text[..end].ends_with(char::is_whitespace) && !next.map_or(false, char::is_alphanumeric) {
+            return Some(end);
+        }
+        from = end + marker.len();
+    }
+    None
+}
+
+//Parses [label](url), returning None for unsafe schemes so the text falls back to being escaped.
+fn link(text: &str) -> Option<(&str, String, usize)> {
+    let label_end = text.find("](")?;
+    let label = &text[1..label_end];
+    if label.is_empty() || label.contains('\n') {
+        return None;
+    }
+    let url_start = label_end + 2;
+    let url_end = url_start + text[url_start..].find(')')?;
+    let url = Url::parse(text[url_start..url_end].trim()).ok()?;
+    if !LINK_SCHEMES.contains(&url.scheme()) {
+        return None;
+    }
+    Some((label, url.to_string(), url_end + 1))
+}
+
+fn escape_into(text: &str, out: &mut String) {
+    for c in text.chars() {
+        escape_char(c, out);
+    }
+}
+
+fn escape_char(c: char, out: &mut String) {
+    match c {
+        '&' => out.push_str("&amp;"),
+        '<' => out.push_str("&lt;"),
+        '>' => out.push_str("&gt;"),
+        '"' => out.push_str("&quot;"),
+        '\'' => out.push_str("&#39;"),
+        c if c.is_control() && c != '\n' && c != '\t' => (),
+        c => out.push(c)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chat::formatting::render;
+
+    #[test]
+    fn renders_emphasis_and_code() {
+        assert_eq!("<strong>Bears</strong>. <em>Beets</em>. <em>Battlestar</em> <code>Galactica</code>.",
+                   render("**Bears**. *Beets*. _Battlestar_ `Galactica`."));
+    }
+
+    #[test]
+    fn code_blocks_keep_content_literal() {
+        assert_eq!("run <pre><code>**not bold** &lt;b&gt;\n</code></pre>",
+                   render("run ```\n**not bold** <b>\n```"));
+    }
+
+    #[test]
+    fn markup_is_escaped() {
+        assert_eq!("&lt;script&gt;alert(&quot;hi&quot;)&lt;/script&gt;", render("<script>alert(\"hi\")</script>"));
+        assert_eq!("<strong>&lt;img src=x onerror=alert(1)&gt;</strong>", render("**<img src=x onerror=alert(1)>**"));
+    }
+
+    #[test]
+    fn only_safe_links_are_rendered() {
+        assert_eq!("<a href=\"https://dundermifflin.com/\" rel=\"nofollow noopener noreferrer\" target=\"_blank\">Dunder Mifflin</a>",
+                   render("[Dunder Mifflin](https://dundermifflin.com)"));
+        assert_eq!("[click](javascript:alert(1))", render("[click](javascript:alert(1))"));
+        assert!(render("[x](https://a.com/\"onmouseover=\"alert(1))").contains("%22onmouseover=%22"));
+    }
+
+    #[test]
+    fn unmatched_markers_and_identifiers_stay_literal() {
+        assert_eq!("snake_case_name and 2 * 3 * 4 and **open", render("snake_case_name and 2 * 3 * 4 and **open"));
+    }
+
+    #[test]
+    fn newlines_become_breaks() {
+        assert_eq!("line one<br>line two", render("line one\nline two"));
+    }
+}
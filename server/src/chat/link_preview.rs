@@ -0,0 +1,562 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use threadpool::ThreadPool;
+use url::Url;
+
+pub const PREVIEW_TIMEOUT: Duration = Duration::from_secs(5);
+pub const MAX_PREVIEW_BYTES: usize = 256 * 1024;
+pub const MAX_URLS_PER_MESSAGE: usize = 3;
+const CACHE_TTL: Duration = Duration::from_secs(30 * 60);
+const CACHE_LIMIT: usize = 1000;
+const MAX_REDIRECTS: usize = 3;
+const MAX_TITLE_LEN: usize = 200;
+const MAX_DESCRIPTION_LEN: usize = 500;
+const PREVIEW_WORKERS: usize = 4;
+const MAX_QUEUED_PREVIEWS: usize = 100;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageMetadata {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub image: Option<String>
+}
+
+//Fetches OpenGraph data for links, refusing anything that resolves to a private address.
+pub struct PreviewFetcher {
+    cache: Mutex<HashMap<String, (Instant, Option<PageMetadata>)>>,
+    allow_private: bool,
+    timeout: Duration,
+    max_bytes: usize,
+    workers: Mutex<ThreadPool>,
+    max_queued: usize
+}
+
+impl PreviewFetcher {
+    pub fn new() -> Self {
+        PreviewFetcher {
+            cache: Mutex::new(HashMap::new()),
+            allow_private: false,
+            timeout: PREVIEW_TIMEOUT,
+            max_bytes: MAX_PREVIEW_BYTES,
+            workers: Mutex::new(ThreadPool::new(PREVIEW_WORKERS)),
+            max_queued: MAX_QUEUED_PREVIEWS
+        }
+    }
+
+    //Runs the job on one of a few preview workers. When the queue is already full the job is
+    //dropped and false returned, a flood of links then goes without previews instead of piling up.
+    pub fn run_later<F: FnOnce() + Send + 'static>(&self, job: F) -> bool {
+        let workers = self.workers.lock().unwrap();
+        if workers.queued_count() >= self.max_queued {
+            return false;
+        }
+        workers.execute(job);
+        true
+    }
+
+    pub fn preview(&self, url: &str) -> Option<PageMetadata> {
+        if let Some((fetched, cached)) = self.cache.lock().unwrap().get(url) {
+            if fetched.elapsed() < CACHE_TTL {
+                return cached.clone();
+            }
+        }
+
+        let result = Url::parse(url).ok()
+            .and_then(|parsed| self.fetch(parsed, MAX_REDIRECTS).ok());
+        self.remember(url, result.clone());
+        result
+    }
+
+    fn remember(&self, url: &str, result: Option<PageMetadata>) {
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= CACHE_LIMIT {
+            cache.retain(|_, (fetched, _)| fetched.elapsed() < CACHE_TTL);
+            if cache.len() >= CACHE_LIMIT {
+                cache.clear();
+            }
+        }
+        cache.insert(url.to_string(), (Instant::now(), result));
+    }
+
+    fn fetch(&self, url: Url, redirects_left: usize) -> Result<PageMetadata, PreviewError> {
+        let addr = self.resolve(&url)?;
+        let response = self.request(addr, &url)?;
+        match response.status {
+            200 => {
+                if !response.content_type.starts_with("text/html") {
+                    return Err(PreviewError::NotHtml);
+                }
+                let metadata = parse_metadata(&String::from_utf8_lossy(&response.body), &url);
+                if metadata.title.is_none() && metadata.description.is_none() && metadata.image.is_none() {
+                    Err(PreviewError::NoMetadata)
+                } else {
+                    Ok(metadata)
+                }
+            },
+            301 | 302 | 303 | 307 | 308 if redirects_left > 0 => {
+                let location = response.location.ok_or(PreviewError::BadResponse)?;
+                let next = url.join(&location).map_err(|_| PreviewError::BadUrl)?;
+                self.fetch(next, redirects_left - 1)
+            },
+            _ => Err(PreviewError::BadResponse)
+        }
+    }
+
+    //Every address the host resolves to is checked, then we connect to a vetted one so
+    //a second lookup can't swap in a private address.
+    fn resolve(&self, url: &Url) -> Result<SocketAddr, PreviewError> {
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return Err(PreviewError::BadUrl);
+        }
+        let host = url.host_str().ok_or(PreviewError::BadUrl)?;
+        let port = url.port_or_known_default().ok_or(PreviewError::BadUrl)?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let addrs: Vec<SocketAddr> = (host, port).to_socket_addrs()
+            .map_err(|_| PreviewError::Unreachable)?
+            .collect();
+        if addrs.is_empty() {
+            return Err(PreviewError::Unreachable);
+        }
+        if !self.allow_private && addrs.iter().any(|a| is_blocked(a.ip())) {
+            return Err(PreviewError::Blocked);
+        }
+        Ok(addrs[0])
+    }
+
+    fn request(&self, addr: SocketAddr, url: &Url) -> Result<HttpResponse, PreviewError> {
+        let stream = TcpStream::connect_timeout(&addr, self.timeout).map_err(|_| PreviewError::Unreachable)?;
+        stream.set_read_timeout(Some(self.timeout)).map_err(|_| PreviewError::Unreachable)?;
+        stream.set_write_timeout(Some(self.timeout)).map_err(|_| PreviewError::Unreachable)?;
+        let host = url.host_str().ok_or(PreviewError::BadUrl)?;
+
+        if url.scheme() == "https" {
+            let connector = native_tls::TlsConnector::new().map_err(|_| PreviewError::Unreachable)?;
+            let tls = connector.connect(host, stream).map_err(|_| PreviewError::Unreachable)?;
+            self.exchange(tls, url)
+        } else {
+            self.exchange(stream, url)
+        }
+    }
+
+    //HTTP/1.0 keeps the response unchunked and lets the server close the connection.
+    fn exchange<S: Read + Write>(&self, mut stream: S, url: &Url) -> Result<HttpResponse, PreviewError> {
+        let mut path = String::from(url.path());
+        if let Some(query) = url.query() {
+            path.push('?');
+            path.push_str(query);
+        }
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or(""), port),
+            None => url.host_str().unwrap_or("").to_string()
+        };
+        let request = format!("GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: CrabbyChat-LinkPreview\r\nAccept: text/html\r\n\r\n", path, host);
+        stream.write_all(request.as_bytes()).map_err(|_| PreviewError::Unreachable)?;
+
+        let started = Instant::now();
+        let mut raw = vec![];
+        let mut buff = [0; 8192];
+        while raw.len() < self.max_bytes && started.elapsed() < self.timeout {
+            match stream.read(&mut buff) {
+                Ok(0) => break,
+                Ok(n) => raw.extend_from_slice(&buff[..n]),
+                Err(_) => break
+            }
+        }
+        raw.truncate(self.max_bytes);
+        HttpResponse::parse(raw)
+    }
+}
+
+struct HttpResponse {
+    status: u16,
+    content_type: String,
+    location: Option<String>,
+    body: Vec<u8>
+}
+
+impl HttpResponse {
+    fn parse(raw: Vec<u8>) -> Result<HttpResponse, PreviewError> {
+        let header_end = raw.windows(4).position(|w| w == b"\r\n\r\n").ok_or(PreviewError::BadResponse)?;
+        let head = String::from_utf8_lossy(&raw[..header_end]).to_string();
+        let mut lines = head.split("\r\n");
+        let status = lines.next()
+            .and_then(|l| l.split_whitespace().nth(1))
+            .and_then(|s| s.parse::<u16>().ok())
+            .ok_or(PreviewError::BadResponse)?;
+        let mut content_type = String::new();
+        let mut location = None;
+        for line in lines {
+            if let Some(idx) = line.find(':') {
+                let (name, value) = line.split_at(idx);
+                let value = value[1..].trim();
+                match name.trim().to_ascii_lowercase().as_str() {
+                    "content-type" => content_type = value.to_ascii_lowercase(),
+                    "location" => location = Some(value.to_string()),
+                    _ => ()
+                }
+            }
+        }
+        Ok(HttpResponse {
+            status,
+            content_type,
+            location,
+            body: raw[header_end + 4..].to_vec()
+        })
+    }
+}
+
+pub fn extract_urls(text: &str) -> Vec<String> {
+    let mut urls: Vec<String> = vec![];
+    for word in text.split_whitespace() {
+        let candidate = word.trim_start_matches(|c| c == '(' || c == '<')
+            .trim_end_matches(|c: char| ".,;:!?)'\">".contains(c));
+        if (candidate.starts_with("http://") || candidate.starts_with("https://"))
+            && Url::parse(candidate).is_ok() && !urls.iter().any(|u| u == candidate) {
+            urls.push(candidate.to_string());
+        }
+        if urls.len() == MAX_URLS_PER_MESSAGE {
+            break;
+        }
+    }
+    urls
+}
+
+pub fn is_blocked(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => is_blocked_v4(v4),
+        IpAddr::V6(v6) => is_blocked_v6(v6)
+    }
+}
+
+fn is_blocked_v4(ip: Ipv4Addr) -> bool {
+    let o = ip.octets();
+    ip.is_unspecified() || ip.is_loopback() || ip.is_private() || ip.is_link_local()
+        || ip.is_broadcast() || ip.is_multicast() || ip.is_documentation()
+        || o[0] == 0
+        || (o[0] == 100 && (o[1] & 0xc0) == 64)
+        || (o[0] == 192 && o[1] == 0 && o[2] == 0)
+        || (o[0] == 198 && (o[1] & 0xfe) == 18)
+        || o[0] >= 240
+}
+
+fn is_blocked_v6(ip: Ipv6Addr) -> bool {
+    let s = ip.segments();
+    if let Some(v4) = embedded_v4(&ip) {
+        return is_blocked_v4(v4);
+    }
+    ip.is_unspecified() || ip.is_loopback() || ip.is_multicast()
+        || (s[0] & 0xfe00) == 0xfc00
+        || (s[0] & 0xffc0) == 0xfe80
+        || (s[0] == 0x2001 && s[1] == 0x0db8)
+}
+
+//IPv4-mapped, IPv4-compatible, NAT64 and 6to4 addresses reach IPv4 hosts, so they are judged
+//by the IPv4 rules. 6to4 carries the address right after its 2002 prefix, the others at the end.
+fn embedded_v4(ip: &Ipv6Addr) -> Option<Ipv4Addr> {
+    let s = ip.segments();
+    let mapped = s[..5].iter().all(|x| *x == 0) && s[5] == 0xffff;
+    let compatible = s[..6].iter().all(|x| *x == 0);
+    let nat64 = s[0] == 0x64 && s[1] == 0xff9b && s[2..6].iter().all(|x| *x == 0);
+    if mapped || compatible || nat64 {
+        Some(v4_from_segments(s[6], s[7]))
+    } else if s[0] == 0x2002 {
+        Some(v4_from_segments(s[1], s[2]))
+    } else {
+        None
+    }
+}
+
+fn v4_from_segments(high: u16, low: u16) -> Ipv4Addr {
+    Ipv4Addr::new((high >> 8) as u8, high as u8, (low >> 8) as u8, low as u8)
+}
+
+pub fn parse_metadata(html: &str, base: &Url) -> PageMetadata {
+    let lower = html.to_ascii_lowercase();
+    let mut title = None;
+    let mut description = None;
+    let mut image = None;
+    let mut fallback_description = None;
+
+    let mut pos = 0;
+    while let Some(start) = lower[pos..].find("<meta") {
+        let tag_start = pos + start;
+        let tag_end = match lower[tag_start..].find('>') {
+            Some(end) => tag_start + end,
+            None => break
+        };
+        let attrs = parse_attributes(&html[tag_start + 5..tag_end]);
+        let key = attrs.get("property").or_else(|| attrs.get("name")).map(|k| k.to_ascii_lowercase());
+        if let (Some(key), Some(content)) = (key, attrs.get("content")) {
+            match key.as_str() {
+                "og:title" => title = Some(content.clone()),
+                "og:description" => description = Some(content.clone()),
+                "og:image" => image = Some(content.clone()),
+                "description" => fallback_description = Some(content.clone()),
+                _ => ()
+            }
+        }
+        pos = tag_end;
+    }
+
+    if title.is_none() {
+        if let Some(start) = lower.find("<title") {
+            let open_end = lower[start..].find('>').map(|e| start + e + 1);
+            let close = lower.find("</title>");
+            if let (Some(open_end), Some(close)) = (open_end, close) {
+                if close > open_end {
+                    title = Some(html[open_end..close].to_string());
+                }
+            }
+        }
+    }
+
+    PageMetadata {
+        title: clean(title, MAX_TITLE_LEN),
+        description: clean(description.or(fallback_description), MAX_DESCRIPTION_LEN),
+        image: image
+            .and_then(|i| base.join(decode_entities(i.trim()).as_str()).ok())
+            .filter(|u| u.scheme() == "http" || u.scheme() == "https")
+            .map(|u| u.to_string())
+    }
+}
+
+fn parse_attributes(tag: &str) -> HashMap<String, String> {
+    let mut attrs = HashMap::new();
+    let chars: Vec<char> = tag.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        while i < chars.len() && (chars[i].is_whitespace() || chars[i] == '/') {
+            i += 1;
+        }
+        let name_start = i;
+        while i < chars.len() && !chars[i].is_whitespace() && chars[i] != '=' {
+            i += 1;
+        }
+        let name: String = chars[name_start..i].iter().collect::<String>().to_ascii_lowercase();
+        while i < chars.len() && chars[i].is_whitespace() {
+            i += 1;
+        }
+        if i >= chars.len() || chars[i] != '=' {
+            continue;
+        }
+        i += 1;
+        while i < chars.len() && chars[i].is_whitespace() {
+            i += 1;
+        }
+        let value: String = if i < chars.len() && (chars[i] == '"' || chars[i] == '\'') {
+            let quote = chars[i];
+            i += 1;
+            let value_start = i;
+            while i < chars.len() && chars[i] != quote {
+                i += 1;
+            }
+            let value = chars[value_start..i].iter().collect();
+            i += 1;
+            value
+        } else {
+            let value_start = i;
+            while i < chars.len() && !chars[i].is_whitespace() {
+                i += 1;
+            }
+            chars[value_start..i].iter().collect()
+        };
+        if !name.is_empty() {
+            attrs.insert(name, value);
+        }
+    }
+    attrs
+}
+
+fn clean(value: Option<String>, max_len: usize) -> Option<String> {
+    value.map(|v| decode_entities(v.split_whitespace().collect::<Vec<&str>>().join(" ").as_str()))
+        .filter(|v| !v.is_empty())
+        .map(|v| v.chars().take(max_len).collect())
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&amp;", "&")
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum PreviewError {
+    BadUrl,
+    Blocked,
+    Unreachable,
+    BadResponse,
+    NotHtml,
+    NoMetadata
+}
+
+impl std::error::Error for PreviewError {}
+impl Display for PreviewError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            PreviewError::BadUrl => write!(f, "Link isn't a valid web address."),
+            PreviewError::Blocked => write!(f, "Link resolves to a private address."),
+            PreviewError::Unreachable => write!(f, "Unable to reach linked site."),
+            PreviewError::BadResponse => write!(f, "Linked site sent an unusable response."),
+            PreviewError::NotHtml => write!(f, "Linked content isn't a web page."),
+            PreviewError::NoMetadata => write!(f, "Linked page has nothing to preview.")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chat::link_preview::*;
+    use std::io::{Read, Write};
+    use std::net::{IpAddr, TcpListener, SocketAddr};
+    use std::sync::{mpsc, Mutex};
+    use std::thread;
+    use threadpool::ThreadPool;
+    use std::time::{Duration, Instant};
+    use url::Url;
+
+    const PAGE: &str = "<html><head><title>Ignored</title>\
+        <meta property=\"og:title\" content=\"Schrute Farms\">\
+        <meta property='og:description' content='Beets &amp; bed and breakfast'>\
+        <meta property=\"og:image\" content=\"/img/barn.png\"></head></html>";
+
+    //Stand-in web server answering a single request with the given response.
+    fn serve_once(response: String) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            if let Ok((mut stream, _)) = listener.accept() {
+                let mut buff = [0; 1024];
+                let _ = stream.read(&mut buff);
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        addr
+    }
+
+    fn html_response(body: &str) -> String {
+        format!("HTTP/1.0 200 OK\r\nContent-Type: text/html; charset=utf-8\r\n\r\n{}", body)
+    }
+
+    fn local_fetcher() -> PreviewFetcher {
+        let mut fetcher = PreviewFetcher::new();
+        fetcher.allow_private = true;
+        fetcher.timeout = Duration::from_millis(500);
+        fetcher
+    }
+
+    #[test]
+    fn fetches_open_graph_metadata() {
+        let addr = serve_once(html_response(PAGE));
+        let preview = local_fetcher().preview(&format!("http://{}/farm", addr)).unwrap();
+        assert_eq!(Some(String::from("Schrute Farms")), preview.title);
+        assert_eq!(Some(String::from("Beets & bed and breakfast")), preview.description);
+        assert_eq!(Some(format!("http://{}/img/barn.png", addr)), preview.image);
+    }
+
+    #[test]
+    fn private_addresses_are_blocked_by_default() {
+        let addr = serve_once(html_response(PAGE));
+        assert!(PreviewFetcher::new().preview(&format!("http://{}/farm", addr)).is_none());
+        assert!(PreviewFetcher::new().preview("http://localhost/").is_none());
+    }
+
+    #[test]
+    fn results_are_cached() {
+        let addr = serve_once(html_response(PAGE));
+        let fetcher = local_fetcher();
+        let url = format!("http://{}/farm", addr);
+        let first = fetcher.preview(&url);
+        let second = fetcher.preview(&url);
+        assert!(first.is_some());
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn slow_servers_time_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let _held = listener.accept();
+            thread::sleep(Duration::from_secs(3));
+        });
+        let started = Instant::now();
+        assert!(local_fetcher().preview(&format!("http://{}/", addr)).is_none());
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn only_the_first_bytes_are_read() {
+        let body = format!("<html><head>{}<meta property=\"og:title\" content=\"Too late\"></head></html>", " ".repeat(4096));
+        let addr = serve_once(html_response(&body));
+        let mut fetcher = local_fetcher();
+        fetcher.max_bytes = 1024;
+        assert!(fetcher.preview(&format!("http://{}/", addr)).is_none());
+    }
+
+    #[test]
+    fn non_html_responses_are_ignored() {
+        let addr = serve_once(String::from("HTTP/1.0 200 OK\r\nContent-Type: image/png\r\n\r\n\u{89}PNG"));
+        assert!(local_fetcher().preview(&format!("http://{}/", addr)).is_none());
+    }
+
+    #[test]
+    fn jobs_past_the_queue_limit_are_dropped() {
+        let mut fetcher = local_fetcher();
+        fetcher.workers = Mutex::new(ThreadPool::new(1));
+        fetcher.max_queued = 1;
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        assert!(fetcher.run_later(move || {
+            started_tx.send(()).unwrap();
+            let _ = release_rx.recv();
+        }));
+        started_rx.recv().unwrap();
+
+        let (done_tx, done_rx) = mpsc::channel();
+        assert!(fetcher.run_later(move || done_tx.send(()).unwrap()));
+        assert!(!fetcher.run_later(|| ()));
+        release_tx.send(()).unwrap();
+        done_rx.recv_timeout(Duration::from_secs(1)).unwrap();
+    }
+
+    #[test]
+    fn private_ranges_are_recognized() {
+        for ip in ["127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254",
+                   "100.64.0.1", "0.0.0.0", "::1", "::", "fd00::1", "fe80::1", "::ffff:10.0.0.1", "::127.0.0.1",
+                   "::a9fe:a9fe", "64:ff9b::192.168.0.1", "2002:7f00:1::", "2002:a9fe:a9fe::1"].iter() {
+            assert!(is_blocked(ip.parse::<IpAddr>().unwrap()), "{} should be blocked", ip);
+        }
+        for ip in ["93.184.216.34", "2606:4700::1111", "::93.184.216.34", "2002:5db8:d822::1"].iter() {
+            assert!(!is_blocked(ip.parse::<IpAddr>().unwrap()), "{} should be allowed", ip);
+        }
+    }
+
+    #[test]
+    fn urls_are_extracted_from_text() {
+        let urls = extract_urls("See (https://dundermifflin.com/paper), and http://schrutefarms.com. Not ftp://x.y");
+        assert_eq!(vec!["https://dundermifflin.com/paper", "http://schrutefarms.com"], urls);
+    }
+
+    #[test]
+    fn title_tag_is_used_without_open_graph() {
+        let base = Url::parse("http://example.com/").unwrap();
+        let metadata = parse_metadata("<html><head><title> Michael  Scott Paper Company </title></head></html>", &base);
+        assert_eq!(Some(String::from("Michael Scott Paper Company")), metadata.title);
+        assert!(metadata.image.is_none());
+    }
+
+    #[test]
+    fn non_web_images_are_dropped() {
+        let base = Url::parse("http://example.com/").unwrap();
+        let metadata = parse_metadata("<meta property=\"og:image\" content=\"javascript:alert(1)\">", &base);
+        assert!(metadata.image.is_none());
+    }
+}
@@ -0,0 +1,170 @@
+use std::sync::Arc;
+use crate::chat::chat_data::SearchHit;
+use crate::chat::attachments::Attachment;
+use crate::storage::Storage;
+#[cfg(test)]
+use crate::storage::sqlite::SqliteStorage;
+use crate::user::user_db_service::DbServiceError;
+
+pub struct MessageStore {
+    storage: Arc<dyn Storage>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredMessage {
+    pub room_id: String,
+    pub message_id: u64,
+    pub from: String,
+    //The account it was sent from, names change and can be taken by someone else later.
+    pub sender_id: Option<String>,
+    pub msg: String,
+    pub rendered: String,
+    pub mentions: Vec<String>,
+    pub attachments: Vec<String>,
+    pub sent_at: i64
+}
+
+pub struct SearchQuery {
+    pub text: String,
+    pub rooms: Vec<String>,
+    pub from: Option<String>,
+    pub after: Option<i64>,
+    pub before: Option<i64>
+}
+
+impl MessageStore {
+    #[cfg(test)]
+    pub fn new() -> Self {
+        MessageStore::with_storage(Arc::new(SqliteStorage::in_memory().unwrap()))
+    }
+
+    pub fn with_storage(storage: Arc<dyn Storage>) -> Self {
+        MessageStore {
+            storage
+        }
+    }
+
+    pub fn save(&self, msg: &StoredMessage) -> Result<(), DbServiceError> {
+        self.storage.save_message(msg)
+    }
+
+    pub fn save_attachment(&self, attachment: &Attachment) -> Result<(), DbServiceError> {
+        self.storage.save_attachment(attachment)
+    }
+
+    pub fn find_attachment(&self, id: &str) -> Result<Option<Attachment>, DbServiceError> {
+        self.storage.find_attachment(id)
+    }
+
+    pub fn search(&self, query: &SearchQuery) -> Result<Vec<SearchHit>, DbServiceError> {
+        self.storage.search(query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chat::message_store::{MessageStore, StoredMessage, SearchQuery};
+
+    fn message(room_id: &str, message_id: u64, from: &str, msg: &str, sent_at: i64) -> StoredMessage {
+        StoredMessage {
+            room_id: String::from(room_id),
+            message_id,
+            from: String::from(from),
+            sender_id: None,
+            msg: String::from(msg),
+            rendered: String::from(msg),
+            mentions: vec![],
+            attachments: vec![],
+            sent_at
+        }
+    }
+
+    fn query(text: &str, rooms: Vec<&str>) -> SearchQuery {
+        SearchQuery {
+            text: String::from(text),
+            rooms: rooms.iter().map(|r| String::from(*r)).collect(),
+            from: None,
+            after: None,
+            before: None
+        }
+    }
+
+    fn setup() -> MessageStore {
+        let store = MessageStore::new();
+        store.save(&message("sales", 1, "jhalpert", "Pranking Dwight with jello", 100)).unwrap();
+        store.save(&message("sales", 2, "dschrute", "Who put my stapler in jello?", 200)).unwrap();
+        store.save(&message("accounting", 1, "kmalone", "Famous chili day tomorrow", 300)).unwrap();
+        store.save(&message("annex", 1, "kkapoor", "jello shots at the party", 400)).unwrap();
+        store
+    }
+
+    #[test]
+    fn finds_messages_containing_term() {
+        let store = setup();
+        let hits = store.search(&query("jello", vec!["sales"])).unwrap();
+        assert_eq!(2, hits.len());
+    }
+
+    #[test]
+    fn results_are_scoped_to_accessible_rooms() {
+        let store = setup();
+        let hits = store.search(&query("jello", vec!["sales", "accounting"])).unwrap();
+        assert!(hits.iter().all(|h| h.room_id == "sales"));
+
+        let hits = store.search(&query("jello", vec![])).unwrap();
+        assert!(hits.is_empty());
+    }
+
+    #[test]
+    fn can_filter_by_sender_and_date() {
+        let store = setup();
+        let mut by_sender = query("jello", vec!["sales", "annex"]);
+        by_sender.from = Some(String::from("dschrute"));
+        let hits = store.search(&by_sender).unwrap();
+        assert_eq!(1, hits.len());
+        assert_eq!(2, hits[0].message_id);
+
+        let mut by_date = query("jello", vec!["sales", "annex"]);
+        by_date.after = Some(150);
+        by_date.before = Some(350);
+        let hits = store.search(&by_date).unwrap();
+        assert_eq!(1, hits.len());
+        assert_eq!("dschrute", hits[0].from);
+    }
+
+    #[test]
+    fn snippets_highlight_matches_and_escape_markup() {
+        let store = MessageStore::new();
+        store.save(&message("sales", 1, "rhoward", "<b>beets</b> & bears", 100)).unwrap();
+        let hits = store.search(&query("beets", vec!["sales"])).unwrap();
+        assert_eq!("&lt;b&gt;<mark>beets</mark>&lt;/b&gt; &amp; bears", hits[0].snippet);
+    }
+
+    #[test]
+    fn mentions_are_saved_with_message() {
+        let store = MessageStore::new();
+        let mut msg = message("sales", 1, "mscott", "@dschrute @jhalpert conference room", 100);
+        msg.mentions = vec![String::from("dschrute"), String::from("jhalpert")];
+        store.save(&msg).unwrap();
+        let saved = store.storage.room_messages("sales").unwrap();
+        assert_eq!(vec![String::from("dschrute"), String::from("jhalpert")], saved[0].mentions);
+    }
+
+    #[test]
+    fn raw_and_rendered_text_are_both_kept() {
+        let store = MessageStore::new();
+        let mut msg = message("sales", 1, "mscott", "**World's best boss**", 100);
+        msg.rendered = String::from("<strong>World&#39;s best boss</strong>");
+        store.save(&msg).unwrap();
+        let saved = store.storage.room_messages("sales").unwrap();
+        assert_eq!("**World's best boss**", saved[0].msg);
+        assert_eq!("<strong>World&#39;s best boss</strong>", saved[0].rendered);
+    }
+
+    #[test]
+    fn query_syntax_is_treated_as_text() {
+        let store = setup();
+        let hits = store.search(&query("chili\" OR \"jello", vec!["accounting"])).unwrap();
+        assert!(hits.is_empty());
+    }
+}
@@ -1,33 +1,54 @@
 use std::net::TcpStream;
-use std::borrow::Cow;
+use percent_encoding::percent_decode_str;
 
-//parse room name from raw HTTP headers
-pub fn get_room_name(new_stream: &mut TcpStream) -> Option<String> {
+const ROOM_PATH: &str = "/room/";
+
+//Reads the room key, an id or a slug, from the raw HTTP request without consuming it.
+pub fn get_room_key(new_stream: &mut TcpStream) -> Option<String> {
     let mut buff = [0; 1024];
-    new_stream.peek(&mut buff);
-    let incoming = String::from_utf8_lossy(&buff);
-    if incoming.starts_with("GET /room/") {
-        Some(extract_name(incoming))
-    } else {
-        None
-    }
+    let read = new_stream.peek(&mut buff).ok()?;
+    room_key(&String::from_utf8_lossy(&buff[..read]))
 }
 
-fn extract_name(http_req: Cow<str>) -> String {
-    let idx = http_req.find("HTTP");
-    let (right, _) = http_req.split_at(idx.unwrap());
-    let name_start_idx = right.rfind("/").unwrap() + 1;
-    let (_, name) =right.split_at(name_start_idx);
-    String::from(name.trim())
+//Only GET /room/<key> is accepted, the query string is dropped and the key percent-decoded.
+fn room_key(http_req: &str) -> Option<String> {
+    let mut request_line = http_req.lines().next()?.split_whitespace();
+    if request_line.next()? != "GET" {
+        return None;
+    }
+    let target = request_line.next()?;
+    let path = target.split(|c| c == '?' || c == '#').next()?;
+    let encoded = path.strip_prefix(ROOM_PATH)?.trim_end_matches('/');
+    if encoded.is_empty() || encoded.contains('/') {
+        return None;
+    }
+    let key = percent_decode_str(encoded).decode_utf8().ok()?;
+    Some(key.into_owned())
 }
 
 #[cfg(test)]
 mod tests {
     use crate::chat::name_extractor::*;
+
+    #[test]
+    fn can_parse_room_key() {
+        assert_eq!(Some(String::from("hello")), room_key("GET /room/hello HTTP/1.1\r\nHost: 127.0.0.1:8080"));
+    }
+
+    #[test]
+    fn key_is_decoded_without_the_query_string() {
+        let request = "GET /room/Party%20Planning%2FQ3?token=abc HTTP/1.1\r\nHost: 127.0.0.1:8080";
+        assert_eq!(Some(String::from("Party Planning/Q3")), room_key(request));
+        let request = "GET /room/%E4%BC%9A%E8%AD%B0/ HTTP/1.1\r\n";
+        assert_eq!(Some(String::from("会議")), room_key(request));
+    }
+
     #[test]
-    fn can_parse_room_name() {
-        let s = String::from_utf8_lossy(b"GET /room/hello HTTP/1.1\nHost: 127.0.0.1:8080");
-        let name = extract_name(s);
-        assert_eq!("hello", name);
+    fn other_requests_have_no_key() {
+        assert_eq!(None, room_key("GET /room/ HTTP/1.1\r\n"));
+        assert_eq!(None, room_key("GET /room/a/b HTTP/1.1\r\n"));
+        assert_eq!(None, room_key("POST /room/hello HTTP/1.1\r\n"));
+        assert_eq!(None, room_key("GET /user/me HTTP/1.1\r\n"));
+        assert_eq!(None, room_key("GET /room/%FF HTTP/1.1\r\n"));
     }
-}
\ No newline at end of file
+}
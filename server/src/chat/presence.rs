@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tungstenite::Message;
+use crate::chat::chat_data::{PresenceEvent, PresenceStatus};
+use crate::chat::chat_room::RoomDirectory;
+
+pub const AWAY_AFTER: Duration = Duration::from_secs(5 * 60);
+pub const PRESENCE_SWEEP_INTERVAL: Duration = Duration::from_secs(15);
+
+struct UserState {
+    //Open connections per room id, a user can have several tabs in one room.
+    connections: HashMap<String, usize>,
+    manual: Option<PresenceStatus>,
+    last_active: Instant,
+    //What the rooms were last told, so only real changes are announced.
+    reported: PresenceStatus
+}
+
+impl UserState {
+    fn status(&self, now: Instant) -> PresenceStatus {
+        if self.connections.is_empty() {
+            PresenceStatus::Offline
+        } else if let Some(manual) = self.manual {
+            manual
+        } else if now.duration_since(self.last_active) >= AWAY_AFTER {
+            PresenceStatus::Away
+        } else {
+            PresenceStatus::Online
+        }
+    }
+}
+
+//Status across every room for users with an id. People who join without an account only
+//exist inside a room, so they aren't tracked here. Nothing is persisted.
+pub struct PresenceTracker {
+    users: Mutex<HashMap<String, UserState>>
+}
+
+impl PresenceTracker {
+    pub fn new() -> Self {
+        PresenceTracker {
+            users: Mutex::new(HashMap::new())
+        }
+    }
+
+    //Each of these returns the new status when it changed and should be announced.
+    pub fn connected(&self, user_id: &str, room_id: &str, now: Instant) -> Option<PresenceStatus> {
+        self.update(user_id, now, |p| {
+            *p.connections.entry(room_id.to_string()).or_insert(0) += 1;
+            p.last_active = now;
+        })
+    }
+
+    pub fn disconnected(&self, user_id: &str, room_id: &str, now: Instant) -> Option<PresenceStatus> {
+        self.update(user_id, now, |p| {
+            if let Some(count) = p.connections.get_mut(room_id) {
+                *count -= 1;
+                if *count == 0 {
+                    p.connections.remove(room_id);
+                }
+            }
+        })
+    }
+
+    pub fn active(&self, user_id: &str, now: Instant) -> Option<PresenceStatus> {
+        self.update(user_id, now, |p| p.last_active = now)
+    }
+
+    //Away and DND stick until cleared, Online clears them.
+    pub fn set_manual(&self, user_id: &str, status: PresenceStatus, now: Instant) -> Option<PresenceStatus> {
+        self.update(user_id, now, |p| {
+            p.manual = match status {
+                PresenceStatus::Online | PresenceStatus::Offline => None,
+                other => Some(other)
+            };
+        })
+    }
+
+    pub fn status(&self, user_id: &str, now: Instant) -> PresenceStatus {
+        match self.users.lock().unwrap().get(user_id) {
+            Some(p) => p.status(now),
+            None => PresenceStatus::Offline
+        }
+    }
+
+    //Finds users who went idle since the last sweep.
+    pub fn sweep(&self, now: Instant) -> Vec<(String, PresenceStatus)> {
+        let mut changes = vec![];
+        for (user_id, p) in self.users.lock().unwrap().iter_mut() {
+            let status = p.status(now);
+            if status != p.reported {
+                p.reported = status;
+                changes.push((user_id.clone(), status));
+            }
+        }
+        changes
+    }
+
+    fn update<F>(&self, user_id: &str, now: Instant, change: F) -> Option<PresenceStatus> where F: FnOnce(&mut UserState) {
+        let mut users = self.users.lock().unwrap();
+        let p = users.entry(user_id.to_string()).or_insert_with(|| UserState {
+            connections: HashMap::new(),
+            manual: None,
+            last_active: now,
+            reported: PresenceStatus::Offline
+        });
+        change(p);
+        let status = p.status(now);
+        let changed = status != p.reported;
+        p.reported = status;
+        if p.connections.is_empty() && p.manual.is_none() {
+            users.remove(user_id);
+        }
+        if changed {
+            Some(status)
+        } else {
+            None
+        }
+    }
+}
+
+//Tells every room the user is in about their new status, skipping except_room.
+pub fn announce(rooms: &RoomDirectory, user_id: &str, status: PresenceStatus, except_room: Option<&str>) {
+    for (room, _) in rooms.lock().unwrap().values() {
+        if except_room == Some(room.id().as_str()) {
+            continue;
+        }
+        for (name, id) in room.members() {
+            if id.as_deref() != Some(user_id) {
+                continue;
+            }
+            let event = PresenceEvent {
+                name,
+                registered: true,
+                online: true,
+                status,
+                profile: None
+            };
+            let msg = Message::text(serde_json::to_string(&event).unwrap());
+            for tx in room.users().lock().unwrap().values() {
+                let _ = tx.send(msg.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chat::presence::{self, PresenceTracker, AWAY_AFTER};
+    use crate::chat::chat_data::{PresenceEvent, PresenceStatus};
+    use crate::chat::chat_room::room_data::ChatData;
+    use std::collections::HashMap;
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn status_follows_connections_across_rooms() {
+        let tracker = PresenceTracker::new();
+        let now = Instant::now();
+        assert_eq!(Some(PresenceStatus::Online), tracker.connected("abcd-1234", "sales", now));
+        assert_eq!(None, tracker.connected("abcd-1234", "accounting", now));
+        assert_eq!(None, tracker.disconnected("abcd-1234", "sales", now));
+        assert_eq!(PresenceStatus::Online, tracker.status("abcd-1234", now));
+        assert_eq!(Some(PresenceStatus::Offline), tracker.disconnected("abcd-1234", "accounting", now));
+        assert_eq!(PresenceStatus::Offline, tracker.status("abcd-1234", now));
+    }
+
+    #[test]
+    fn idle_users_are_swept_to_away_and_come_back_when_active() {
+        let tracker = PresenceTracker::new();
+        let now = Instant::now();
+        tracker.connected("abcd-1234", "sales", now);
+        assert!(tracker.sweep(now + Duration::from_secs(1)).is_empty());
+
+        let later = now + AWAY_AFTER;
+        assert_eq!(vec![(String::from("abcd-1234"), PresenceStatus::Away)], tracker.sweep(later));
+        assert!(tracker.sweep(later).is_empty());
+        assert_eq!(Some(PresenceStatus::Online), tracker.active("abcd-1234", later));
+    }
+
+    #[test]
+    fn manual_status_wins_until_cleared() {
+        let tracker = PresenceTracker::new();
+        let now = Instant::now();
+        tracker.connected("abcd-1234", "sales", now);
+        assert_eq!(Some(PresenceStatus::Dnd), tracker.set_manual("abcd-1234", PresenceStatus::Dnd, now));
+        assert_eq!(None, tracker.active("abcd-1234", now));
+        assert!(tracker.sweep(now + AWAY_AFTER).is_empty());
+
+        assert_eq!(Some(PresenceStatus::Offline), tracker.disconnected("abcd-1234", "sales", now));
+        assert_eq!(Some(PresenceStatus::Dnd), tracker.connected("abcd-1234", "sales", now));
+        assert_eq!(Some(PresenceStatus::Online), tracker.set_manual("abcd-1234", PresenceStatus::Online, now));
+    }
+
+    #[test]
+    fn status_changes_name_the_member_without_their_user_id() {
+        let mut data = ChatData::new(String::from("sales"), String::from("owner"));
+        let (jim_tx, _jim_rx) = mpsc::channel();
+        let (dwight_tx, dwight_rx) = mpsc::channel();
+        data.add_user(String::from("jhalpert"), Some(String::from("abcd-1234")), jim_tx);
+        data.add_user(String::from("dschrute"), None, dwight_tx);
+        let (room_tx, _) = mpsc::channel();
+        let mut rooms = HashMap::new();
+        rooms.insert(data.id(), (data, room_tx));
+
+        presence::announce(&Arc::new(Mutex::new(rooms)), "abcd-1234", PresenceStatus::Away, None);
+        let json = dwight_rx.try_recv().unwrap().into_text().unwrap();
+        assert!(!json.contains("abcd-1234"));
+        let event: PresenceEvent = serde_json::from_str(&json).unwrap();
+        assert_eq!("jhalpert", event.name);
+        assert!(event.registered);
+        assert_eq!(PresenceStatus::Away, event.status);
+    }
+}
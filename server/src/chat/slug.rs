@@ -0,0 +1,47 @@
+const MAX_SLUG: usize = 48;
+//Used when a name has nothing URL safe in it, e.g. one written entirely in another script.
+const FALLBACK_SLUG: &str = "room";
+
+//Lowercase ASCII letters and digits, every other run of characters becomes one dash.
+pub fn slugify(name: &str) -> String {
+    let mut slug = String::new();
+    for c in name.chars().flat_map(char::to_lowercase) {
+        if slug.len() >= MAX_SLUG {
+            break;
+        }
+        if c.is_ascii_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        String::from(FALLBACK_SLUG)
+    } else {
+        String::from(slug)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chat::slug::slugify;
+
+    #[test]
+    fn names_become_lowercase_dashed_slugs() {
+        assert_eq!("party-planning", slugify("Party Planning"));
+        assert_eq!("sales-q3-2021", slugify("  Sales / Q3 -- 2021?! "));
+        assert_eq!("caf-talk", slugify("Café talk"));
+    }
+
+    #[test]
+    fn names_without_url_safe_characters_fall_back() {
+        assert_eq!("room", slugify("会議室"));
+        assert_eq!("room", slugify("!!!"));
+    }
+
+    #[test]
+    fn long_names_are_cut_short() {
+        assert_eq!(48, slugify(&"a".repeat(100)).len());
+    }
+}
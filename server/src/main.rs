@@ -2,39 +2,141 @@
 #![feature(thread_spawn_unchecked)]
 
 use std::net::{IpAddr, SocketAddr};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
 use rocket_contrib::serve::StaticFiles;
 
-use chat::chat_manager::ChatManager;
+use chat::chat_manager::{ChatManager, RoomLimits, DEFAULT_ROOM_LIMIT};
+use chat::chat_room::RoomServices;
+use chat::message_store::MessageStore;
+use chat::attachments::{AttachmentService, LocalDiskStorage};
+use chat::link_preview::PreviewFetcher;
+use chat::presence::PresenceTracker;
+use chat::attachments::AttachmentStorage;
+use routes::guest_limiter::GuestLimiter;
 use crate::user::user_db_service::UserDbService;
+use crate::storage::{Storage, split_list};
+use crate::storage::sqlite::SqliteStorage;
+#[cfg(feature = "postgres-backend")]
+use crate::storage::postgres::PostgresStorage;
+use log::{info, warn};
+use chrono::Utc;
 use std::path::Path;
 
 mod routes;
 mod chat;
 mod user;
+mod storage;
 
 
 #[macro_use]
 extern crate rocket;
 
+const DEFAULT_ROOMS_PER_USER: usize = 3;
+const GUEST_SWEEP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
 fn main() {
     log4rs::init_file("config/log4rs.yml", Default::default()).unwrap();
 
-    let mut cm = ChatManager::new();
-    cm.run(SocketAddr::new(IpAddr::from([127,0,0,1]), 8080));
+    let storage = open_storage();
+    let user_db = Arc::new(UserDbService::with_storage(storage.clone()));
+    let message_store = Arc::new(Mutex::new(MessageStore::with_storage(storage.clone())));
+
+    let disk = LocalDiskStorage::new(Path::new("./attachments").to_path_buf()).unwrap();
+    start_guest_sweep(user_db.clone(), LocalDiskStorage::new(Path::new("./attachments").to_path_buf()).unwrap());
+    let attachments = AttachmentService::new(Box::new(disk), message_store.clone());
+
+    let presence = Arc::new(PresenceTracker::new());
 
-    let file = std::fs::File::open(Path::new("./test/test_data.sql")).unwrap();;
-    let user_db = UserDbService::from_file(file).unwrap();
+    let mut cm = ChatManager::with_services(RoomServices {
+        user_db: Some(user_db.clone()),
+        message_store: Some(message_store.clone()),
+        previews: Some(Arc::new(PreviewFetcher::new())),
+        presence: Some(presence.clone()),
+        storage: Some(storage),
+        ..RoomServices::default()
+    }).with_limits(room_limits());
+    match cm.restore_rooms() {
+        Ok(count) => info!("Restored {} saved rooms.", count),
+        Err(e) => warn!("Unable to restore saved rooms: {}", e)
+    }
+    cm.run(SocketAddr::new(IpAddr::from([127,0,0,1]), 8080));
 
     rocket::ignite()
         .manage(Mutex::new(cm))
-        .manage(Mutex::new(user_db))
+        .manage(user_db)
+        .manage(message_store)
+        .manage(attachments)
+        .manage(presence)
+        .manage(GuestLimiter::default())
         .mount("/room", routes![
-        chat::chat_routes::create_room, chat::chat_routes::get_rooms, chat::chat_routes::check_name,
-        chat::chat_routes::delete_room])
-        .mount("/user", routes![routes::user_routes::register,
-        routes::user_routes::add_favorite])
+        chat::chat_routes::create_room, chat::chat_routes::get_rooms, chat::chat_routes::quota, chat::chat_routes::check_name,
+        chat::chat_routes::delete_room, chat::chat_routes::update_room, chat::chat_routes::owners,
+        chat::chat_routes::transfer_ownership, chat::chat_routes::add_co_owner, chat::chat_routes::remove_co_owner])
+        .mount("/attachment", routes![routes::attachment_routes::upload,
+        routes::attachment_routes::download])
+        .mount("/search", routes![chat::chat_routes::search])
+        .mount("/user", routes![routes::user_routes::register, routes::user_routes::check_name,
+        routes::user_routes::add_favorite, routes::user_routes::favorites, routes::user_routes::replace_favorites,
+        routes::user_routes::remove_favorite, routes::user_routes::unread, routes::user_routes::me,
+        routes::user_routes::get_user, routes::user_routes::update_user, routes::user_routes::delete_user,
+        routes::user_routes::profile, routes::user_routes::update_profile, routes::user_routes::avatar,
+        routes::user_routes::upload_avatar, routes::user_routes::remove_avatar, routes::user_routes::presence,
+        routes::user_routes::set_presence, routes::user_routes::blocked, routes::user_routes::block_user,
+        routes::user_routes::unblock_user, routes::user_routes::export, routes::user_routes::guest,
+        routes::user_routes::upgrade])
+        .register(catchers![routes::api_error::unauthorized])
         .mount("/", StaticFiles::from("static"))
         .launch();
 }
+
+//Deletes guests nobody has used for a while, the files of their avatars go with them.
+fn start_guest_sweep(user_db: Arc<UserDbService>, files: LocalDiskStorage) {
+    thread::spawn(move || loop {
+        thread::sleep(GUEST_SWEEP_INTERVAL);
+        match user_db.expire_guests(Utc::now().timestamp()) {
+            Ok(avatars) => for avatar in avatars {
+                let _ = files.remove(&avatar);
+            },
+            Err(e) => warn!("Unable to expire guests: {}", e)
+        }
+    });
+}
+
+//Setting DATABASE_URL switches to PostgreSQL, otherwise an in-memory SQLite database is seeded with test data.
+#[cfg(feature = "postgres-backend")]
+fn open_storage() -> Arc<dyn Storage> {
+    match std::env::var("DATABASE_URL") {
+        Ok(url) => Arc::new(PostgresStorage::connect(&url).unwrap()),
+        Err(_) => sqlite_storage()
+    }
+}
+
+//MAX_ROOMS caps the rooms running at once, ROOMS_PER_USER how many one user can own
+//and ADMIN_USERS lists the user ids, comma separated, who can own any number.
+fn room_limits() -> RoomLimits {
+    let number = |key: &str, default: usize| match std::env::var(key) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            warn!("Ignoring {}={}, it isn't a number.", key, value);
+            default
+        }),
+        Err(_) => default
+    };
+    RoomLimits {
+        max_rooms: number("MAX_ROOMS", DEFAULT_ROOM_LIMIT),
+        per_user: Some(number("ROOMS_PER_USER", DEFAULT_ROOMS_PER_USER)),
+        admins: split_list(&std::env::var("ADMIN_USERS").unwrap_or_default())
+    }
+}
+
+#[cfg(not(feature = "postgres-backend"))]
+fn open_storage() -> Arc<dyn Storage> {
+    sqlite_storage()
+}
+
+fn sqlite_storage() -> Arc<dyn Storage> {
+    let file = std::fs::File::open(Path::new("./test/test_data.sql")).unwrap();
+    Arc::new(SqliteStorage::from_file(file).unwrap())
+}
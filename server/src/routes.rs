@@ -1 +1,5 @@
-pub mod user_routes;
\ No newline at end of file
+pub mod api_error;
+pub mod auth;
+pub mod guest_limiter;
+pub mod user_routes;
+pub mod attachment_routes;
\ No newline at end of file
@@ -0,0 +1,134 @@
+use rocket::{Request, Response};
+use rocket::http::Status;
+use rocket::response::{Responder, Result as ResponseResult};
+use rocket_contrib::json::Json;
+use serde::{Deserialize, Serialize};
+use crate::user::user_db_service::DbServiceError;
+use crate::chat::chat_manager::Error as ChatError;
+use crate::chat::attachments::AttachmentError;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ErrorBody {
+    pub code: String,
+    pub message: String
+}
+
+//A status paired with a JSON body, so API clients get more than an HTML error page.
+#[derive(Debug)]
+pub struct ApiError {
+    status: Status,
+    body: ErrorBody
+}
+
+impl ApiError {
+    pub fn new(status: Status, code: &str, message: String) -> Self {
+        ApiError {
+            status,
+            body: ErrorBody {
+                code: String::from(code),
+                message
+            }
+        }
+    }
+
+    pub fn unauthorized() -> Self {
+        ApiError::new(Status::Unauthorized, "unauthorized", String::from("You must be signed in."))
+    }
+
+    pub fn forbidden(message: &str) -> Self {
+        ApiError::new(Status::Forbidden, "forbidden", String::from(message))
+    }
+
+    pub fn not_found(message: String) -> Self {
+        ApiError::new(Status::NotFound, "not_found", message)
+    }
+}
+
+impl<'r> Responder<'r> for ApiError {
+    fn respond_to(self, req: &Request) -> ResponseResult<'r> {
+        Response::build_from(Json(self.body).respond_to(req)?)
+            .status(self.status)
+            .ok()
+    }
+}
+
+impl From<DbServiceError> for ApiError {
+    fn from(e: DbServiceError) -> Self {
+        let message = e.to_string();
+        match e {
+            DbServiceError::NotFound(_) => ApiError::new(Status::NotFound, "not_found", message),
+            DbServiceError::Conflict(_) => ApiError::new(Status::Conflict, "conflict", message),
+            DbServiceError::Validation(_) => ApiError::new(Status::BadRequest, "validation", message),
+            DbServiceError::EmptyFile | DbServiceError::Storage(_) => ApiError::new(Status::InternalServerError, "storage", message)
+        }
+    }
+}
+
+impl<'r> Responder<'r> for DbServiceError {
+    fn respond_to(self, req: &Request) -> ResponseResult<'r> {
+        ApiError::from(self).respond_to(req)
+    }
+}
+
+impl From<ChatError> for ApiError {
+    fn from(e: ChatError) -> Self {
+        let message = e.to_string();
+        match e {
+            ChatError::TooManyRooms => ApiError::new(Status::TooManyRequests, "too_many_rooms", message),
+            ChatError::QuotaExceeded => ApiError::new(Status::TooManyRequests, "quota_exceeded", message),
+            ChatError::NameTaken => ApiError::new(Status::Conflict, "name_taken", message),
+            ChatError::NotOwner => ApiError::new(Status::Forbidden, "not_owner", message),
+            ChatError::RoomNotFound => ApiError::new(Status::NotFound, "room_not_found", message),
+            ChatError::Invalid(_) => ApiError::new(Status::BadRequest, "validation", message)
+        }
+    }
+}
+
+impl From<AttachmentError> for ApiError {
+    fn from(e: AttachmentError) -> Self {
+        let message = e.to_string();
+        match e {
+            AttachmentError::TooLarge => ApiError::new(Status::PayloadTooLarge, "too_large", message),
+            AttachmentError::Empty => ApiError::new(Status::BadRequest, "validation", message),
+            AttachmentError::UnsupportedType => ApiError::new(Status::UnsupportedMediaType, "unsupported_type", message),
+            AttachmentError::Storage(_) => ApiError::new(Status::InternalServerError, "storage", message)
+        }
+    }
+}
+
+impl<'r> Responder<'r> for ChatError {
+    fn respond_to(self, req: &Request) -> ResponseResult<'r> {
+        ApiError::from(self).respond_to(req)
+    }
+}
+
+#[catch(401)]
+pub fn unauthorized() -> Json<ErrorBody> {
+    Json(ApiError::unauthorized().body)
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::Status;
+    use crate::chat::chat_manager::Error as ChatError;
+    use crate::routes::api_error::ApiError;
+
+    #[test]
+    fn chat_errors_map_to_statuses_and_codes() {
+        let cases = vec![
+            (ChatError::TooManyRooms, Status::TooManyRequests, "too_many_rooms"),
+            (ChatError::QuotaExceeded, Status::TooManyRequests, "quota_exceeded"),
+            (ChatError::NameTaken, Status::Conflict, "name_taken"),
+            (ChatError::NotOwner, Status::Forbidden, "not_owner"),
+            (ChatError::RoomNotFound, Status::NotFound, "room_not_found"),
+            (ChatError::Invalid(String::from("Room name can't be empty.")), Status::BadRequest, "validation")
+        ];
+        for (error, status, code) in cases {
+            let message = error.to_string();
+            let api_error = ApiError::from(error);
+            assert_eq!(status, api_error.status);
+            assert_eq!(code, api_error.body.code);
+            assert_eq!(message, api_error.body.message);
+        }
+    }
+}
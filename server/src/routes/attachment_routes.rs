@@ -0,0 +1,173 @@
+use rocket::{State, Data, Request, Response};
+use rocket::http::{ContentType, Status};
+use rocket::response::{Responder, Result as ResponseResult};
+use rocket_contrib::json::Json;
+use std::io::{Cursor, Read};
+use std::sync::Mutex;
+use crate::chat::attachments::{Attachment, AttachmentService, MAX_ATTACHMENT_SIZE};
+use crate::chat::chat_data::AttachmentCreated;
+use crate::chat::chat_manager::{ChatManager, Error as ChatError};
+use crate::routes::api_error::ApiError;
+use crate::routes::auth::AuthUser;
+
+pub struct AttachmentDownload {
+    attachment: Attachment,
+    bytes: Vec<u8>
+}
+
+impl AttachmentDownload {
+    pub fn new(attachment: Attachment, bytes: Vec<u8>) -> Self {
+        AttachmentDownload {
+            attachment,
+            bytes
+        }
+    }
+}
+
+impl<'r> Responder<'r> for AttachmentDownload {
+    fn respond_to(self, _: &Request) -> ResponseResult<'r> {
+        let content_type = ContentType::parse_flexible(&self.attachment.content_type)
+            .unwrap_or(ContentType::Binary);
+        Response::build()
+            .header(content_type)
+            .raw_header("Content-Disposition", format!("attachment; filename=\"{}\"", self.attachment.file_name))
+            .raw_header("X-Content-Type-Options", "nosniff")
+            .sized_body(Cursor::new(self.bytes))
+            .ok()
+    }
+}
+
+#[post("/<room_id>?<name>", data = "<data>")]
+pub fn upload(cm: State<Mutex<ChatManager>>, attachments: State<AttachmentService>, auth: AuthUser,
+              content_type: &ContentType, room_id: String, name: Option<String>, data: Data) -> Result<Json<AttachmentCreated>, ApiError> {
+    can_reach(&cm, &auth, &room_id)?;
+
+    let mut bytes = vec![];
+    if data.open().take(MAX_ATTACHMENT_SIZE + 1).read_to_end(&mut bytes).is_err() {
+        return Err(ApiError::new(Status::BadRequest, "validation", String::from("Unable to read the upload.")));
+    }
+    let media_type = format!("{}/{}", content_type.top(), content_type.sub());
+    let attachment = Attachment::new(room_id, auth.user_id().clone(), file_name(name), media_type, bytes.len() as u64);
+    let a = attachments.upload(attachment, &bytes)?;
+    Ok(Json(AttachmentCreated {
+        id: a.id,
+        name: a.file_name,
+        content_type: a.content_type,
+        size: a.size
+    }))
+}
+
+#[get("/<attachment_id>")]
+pub fn download(cm: State<Mutex<ChatManager>>, attachments: State<AttachmentService>, auth: AuthUser,
+                attachment_id: String) -> Result<AttachmentDownload, ApiError> {
+    let attachment = attachments.find(&attachment_id).ok_or_else(attachment_not_found)?;
+    can_reach(&cm, &auth, &attachment.room_id)?;
+    match attachments.download(&attachment) {
+        Ok(bytes) => Ok(AttachmentDownload::new(attachment, bytes)),
+        Err(_) => Err(attachment_not_found())
+    }
+}
+
+//Rooms the user can't reach look the same as ones that don't exist.
+fn can_reach(cm: &Mutex<ChatManager>, auth: &AuthUser, room_id: &str) -> Result<(), ApiError> {
+    if cm.lock().unwrap().accessible_rooms(auth.user_id()).iter().any(|id| id == room_id) {
+        Ok(())
+    } else {
+        Err(ChatError::RoomNotFound.into())
+    }
+}
+
+fn attachment_not_found() -> ApiError {
+    ApiError::not_found(String::from("Attachment doesn't exist."))
+}
+
+//Keeps the name safe to echo back inside a Content-Disposition header.
+fn file_name(name: Option<String>) -> String {
+    let cleaned: String = name.unwrap_or_default().chars()
+        .filter(|c| c.is_alphanumeric() || *c == '.' || *c == '-' || *c == '_' || *c == ' ')
+        .collect();
+    if cleaned.trim().is_empty() {
+        String::from("attachment")
+    } else {
+        cleaned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::routes::attachment_routes::file_name;
+    use crate::chat::attachments::{AttachmentService, LocalDiskStorage};
+    use crate::chat::chat_data::AttachmentCreated;
+    use crate::chat::chat_manager::ChatManager;
+    use crate::chat::message_store::MessageStore;
+    use crate::routes::api_error;
+    use crate::user::user_db_service::UserDbService;
+    use rocket::local::{Client, LocalResponse};
+    use rocket::http::{ContentType, Cookie, Status};
+    use std::sync::{Arc, Mutex};
+    use uuid::Uuid;
+
+    //The annex belongs to jhalpert, mscott has an account but nothing to do with it.
+    fn client() -> (Client, String) {
+        let file = std::fs::File::open("./test/test_data.sql").unwrap();
+        let db = Arc::new(UserDbService::from_file(file).unwrap());
+        let root = std::env::temp_dir().join(format!("crabby-attachments-{}", Uuid::new_v4()));
+        let attachments = AttachmentService::new(Box::new(LocalDiskStorage::new(root).unwrap()),
+                                                 Arc::new(Mutex::new(MessageStore::new())));
+        let mut cm = ChatManager::new();
+        let room_id = cm.create_new_room(String::from("annex"), String::from("abcd-1234")).unwrap().id;
+        let rocket = rocket::ignite()
+            .manage(db)
+            .manage(attachments)
+            .manage(Mutex::new(cm))
+            .mount("/attachment", routes![super::upload, super::download])
+            .register(catchers![api_error::unauthorized]);
+        (Client::new(rocket).unwrap(), room_id)
+    }
+
+    fn upload<'c>(client: &'c Client, room_id: &str, user_id: Option<&str>) -> LocalResponse<'c> {
+        let mut request = client.post(format!("/attachment/{}?name=notes.txt", room_id))
+            .header(ContentType::Plain)
+            .body("minutes");
+        if let Some(user_id) = user_id {
+            request = request.cookie(Cookie::new("user-id", String::from(user_id)));
+        }
+        request.dispatch()
+    }
+
+    #[test]
+    fn attachments_need_a_known_user() {
+        let (client, room_id) = client();
+
+        assert_eq!(Status::Unauthorized, upload(&client, &room_id, None).status());
+        assert_eq!(Status::Unauthorized, upload(&client, &room_id, Some("nobody")).status());
+        let mut response = upload(&client, &room_id, Some("abcd-1234"));
+        let created: AttachmentCreated = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        let path = format!("/attachment/{}", created.id);
+        assert_eq!(Status::Unauthorized, client.get(&path).dispatch().status());
+        assert_eq!(Status::Unauthorized, client.get(&path).cookie(Cookie::new("user-id", "nobody")).dispatch().status());
+    }
+
+    #[test]
+    fn attachments_stay_in_rooms_the_user_can_reach() {
+        let (client, room_id) = client();
+
+        let response = upload(&client, &room_id, Some("bcde-2345"));
+        assert_eq!(Status::NotFound, response.status());
+        let mut response = upload(&client, &room_id, Some("abcd-1234"));
+        assert_eq!(Status::Ok, response.status());
+        let created: AttachmentCreated = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        let path = format!("/attachment/{}", created.id);
+
+        let mut response = client.get(&path).cookie(Cookie::new("user-id", "abcd-1234")).dispatch();
+        assert_eq!(Some(String::from("minutes")), response.body_string());
+        assert_eq!(Status::NotFound, client.get(&path).cookie(Cookie::new("user-id", "bcde-2345")).dispatch().status());
+    }
+
+    #[test]
+    fn file_names_are_sanitized() {
+        assert_eq!("evil.txt", file_name(Some(String::from("\"evil\r\n.txt"))));
+        assert_eq!("attachment", file_name(None));
+        assert_eq!("attachment", file_name(Some(String::from("///"))));
+    }
+}
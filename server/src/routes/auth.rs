@@ -0,0 +1,45 @@
+use rocket::{Outcome, Request, State};
+use rocket::http::Status;
+use rocket::request::{self, FromRequest};
+use std::sync::Arc;
+use crate::user::IUser;
+use crate::user::user_db_service::UserDbService;
+
+pub const USER_COOKIE: &str = "user-id";
+
+//Request guard for routes that need a signed in user, the cookie must name a user that exists.
+pub struct AuthUser {
+    pub user: Box<dyn IUser>
+}
+
+impl AuthUser {
+    pub fn user_id(&self) -> &String {
+        self.user.user_id().unwrap()
+    }
+
+    pub fn is(&self, user_id: &str) -> bool {
+        self.user_id() == user_id
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for AuthUser {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        let user_id = match request.cookies().get(USER_COOKIE) {
+            Some(c) => c.value().to_string(),
+            None => return Outcome::Failure((Status::Unauthorized, ()))
+        };
+        let db = request.guard::<State<Arc<UserDbService>>>()?;
+        let found = db.retrieve_user_by_id(user_id);
+        match found {
+            Ok(user) if user.user_id().is_some() => {
+                //Failing to record the visit only brings a guest's expiry closer.
+                let _ = db.guest_seen(user.as_ref());
+                Outcome::Success(AuthUser { user })
+            },
+            Ok(_) => Outcome::Failure((Status::Unauthorized, ())),
+            Err(_) => Outcome::Failure((Status::InternalServerError, ()))
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub const GUESTS_PER_WINDOW: usize = 5;
+pub const GUEST_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+//Guests need no sign in, so each address only gets a few of them per window.
+//Requests without a known address share one allowance.
+pub struct GuestLimiter {
+    max: usize,
+    window: Duration,
+    created: Mutex<HashMap<Option<IpAddr>, VecDeque<Instant>>>
+}
+
+impl GuestLimiter {
+    pub fn new(max: usize, window: Duration) -> Self {
+        GuestLimiter {
+            max,
+            window,
+            created: Mutex::new(HashMap::new())
+        }
+    }
+
+    //Counts the guest against the address when it's allowed, addresses whose window
+    //has passed are forgotten along the way.
+    pub fn allow(&self, addr: Option<IpAddr>, now: Instant) -> bool {
+        let mut created = self.created.lock().unwrap();
+        let window = self.window;
+        created.retain(|_, times| {
+            while times.front().map_or(false, |t| now.duration_since(*t) >= window) {
+                times.pop_front();
+            }
+            !times.is_empty()
+        });
+        let times = created.entry(addr).or_default();
+        if times.len() >= self.max {
+            return false;
+        }
+        times.push_back(now);
+        true
+    }
+}
+
+impl Default for GuestLimiter {
+    fn default() -> Self {
+        GuestLimiter::new(GUESTS_PER_WINDOW, GUEST_WINDOW)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::routes::guest_limiter::GuestLimiter;
+    use std::net::IpAddr;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn each_address_gets_its_own_allowance_until_the_window_passes() {
+        let limiter = GuestLimiter::new(2, Duration::from_secs(60));
+        let start = Instant::now();
+        let first = Some(IpAddr::from([10, 0, 0, 1]));
+        let second = Some(IpAddr::from([10, 0, 0, 2]));
+        assert!(limiter.allow(first, start));
+        assert!(limiter.allow(first, start + Duration::from_secs(30)));
+        assert!(!limiter.allow(first, start + Duration::from_secs(40)));
+        assert!(limiter.allow(second, start + Duration::from_secs(40)));
+
+        assert!(limiter.allow(first, start + Duration::from_secs(60)));
+        assert!(!limiter.allow(first, start + Duration::from_secs(61)));
+        assert!(limiter.allow(first, start + Duration::from_secs(90)));
+    }
+}
@@ -1,36 +1,667 @@
 use rocket::request::Form;
-use rocket::State;
-use crate::user::user_db_service::UserDbService;
-use crate::user::{User, NewUserForm, IUser};
-use std::sync::Mutex;
+use rocket::{State, Data};
+use crate::user::user_db_service::{UserDbService, DbServiceError};
+use crate::user::{User, NewUserForm, UserPatch, UserDeleted, UserAvailable, Profile, ProfilePatch, BlockedUser, UserExport};
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::net::SocketAddr;
 
-use std::error::Error;
 use rocket_contrib::json::Json;
-use std::collections::HashSet;
-use rocket::http::Status;
+use crate::chat::chat_manager::ChatManager;
+use crate::chat::chat_data::{UnreadCount, FavoriteRoom, UserPresence, PresenceUpdate, PresenceStatus};
+use crate::chat::presence::PresenceTracker;
+use std::time::Instant;
+use crate::routes::api_error::ApiError;
+use crate::routes::auth::{AuthUser, USER_COOKIE};
+use crate::routes::guest_limiter::GuestLimiter;
+use crate::routes::attachment_routes::AttachmentDownload;
+use crate::chat::attachments::{Attachment, AttachmentService, MAX_ATTACHMENT_SIZE};
+use rocket::http::{ContentType, Cookie, Cookies, Status};
 
 #[post("/register", data = "<new_user>")]
-pub fn register(db: State<Mutex<UserDbService>>, new_user: Form<NewUserForm>) -> Result<Json<User>, Box<dyn Error>> {
+pub fn register(db: State<Arc<UserDbService>>, new_user: Form<NewUserForm>) -> Result<Json<User>, DbServiceError> {
     let user = new_user.into_inner();
     let u = User::from_form(user);
-    let r = db.lock().unwrap().create_user(Box::new(u))?;
+    let r = db.create_user(Box::new(u))?;
     Ok(Json(r.to_user()))
 }
 
+//Signs the guest in right away, there's no password to come back with.
+#[post("/guest")]
+pub fn guest(db: State<Arc<UserDbService>>, limiter: State<GuestLimiter>, remote: Option<SocketAddr>,
+             mut cookies: Cookies) -> Result<Json<User>, ApiError> {
+    if !limiter.allow(remote.map(|addr| addr.ip()), Instant::now()) {
+        return Err(ApiError::new(Status::TooManyRequests, "too_many_guests",
+                                  String::from("Too many guest accounts from this address, try again later.")));
+    }
+    let user = db.create_guest()?.to_user();
+    if let Some(user_id) = user.user_id.clone() {
+        cookies.add(Cookie::build(USER_COOKIE, user_id).path("/").finish());
+    }
+    Ok(Json(user))
+}
+
+#[post("/<user_id>/upgrade", format = "json", data = "<patch>")]
+pub fn upgrade(db: State<Arc<UserDbService>>, auth: AuthUser, user_id: String, patch: Json<UserPatch>) -> Result<Json<User>, ApiError> {
+    own_account(&auth, &user_id)?;
+    let user = db.upgrade_guest(user_id, patch.into_inner().user_name)?;
+    Ok(Json(user.to_user()))
+}
+
+#[get("/available?<names>")]
+pub fn check_name(db: State<Arc<UserDbService>>, names: String) -> Result<Json<Vec<UserAvailable>>, DbServiceError> {
+    let mut response: Vec<UserAvailable> = vec![];
+    for name in names.split(',') {
+        response.push(UserAvailable {
+            available: db.name_is_available(name.trim())?,
+            name: String::from(name)
+        });
+    }
+    Ok(Json(response))
+}
+
 #[post("/<user_id>/favorite", data = "<favorite>")]
-pub fn add_favorite(db: State<Mutex<UserDbService>>, user_id: String, favorite: String) -> Result<Json<User>, Status> {
-    let service = db.lock().unwrap();
-    let user = User {
-        user_id: Some(user_id),
-        user_name: String::new(),
-        favorite_rooms: favorite.split(",").map(|s| String::from(s)).collect::<HashSet<String>>()
-    };
-
-
-    let r = service.update_user(Box::new(user));
-    if let Ok(mut found) = r {
-        Ok(Json(found.to_user()))
+pub fn add_favorite(db: State<Arc<UserDbService>>, cm: State<Mutex<ChatManager>>, auth: AuthUser,
+                    user_id: String, favorite: String) -> Result<Json<User>, ApiError> {
+    own_account(&auth, &user_id)?;
+    let room_ids = room_ids(favorite.split(',').map(String::from).collect());
+    known_rooms(&cm, &room_ids)?;
+    let found = db.add_favorites(user_id, room_ids)?;
+    Ok(Json(found.to_user()))
+}
+
+#[get("/<user_id>/favorites")]
+pub fn favorites(cm: State<Mutex<ChatManager>>, auth: AuthUser, user_id: String) -> Result<Json<Vec<FavoriteRoom>>, ApiError> {
+    own_account(&auth, &user_id)?;
+    Ok(Json(cm.lock().unwrap().favorite_rooms(auth.user.as_ref())))
+}
+
+#[put("/<user_id>/favorites", format = "json", data = "<favorites>")]
+pub fn replace_favorites(db: State<Arc<UserDbService>>, cm: State<Mutex<ChatManager>>, auth: AuthUser,
+                         user_id: String, favorites: Json<Vec<String>>) -> Result<Json<Vec<FavoriteRoom>>, ApiError> {
+    own_account(&auth, &user_id)?;
+    let room_ids = room_ids(favorites.into_inner());
+    known_rooms(&cm, &room_ids)?;
+    let found = db.replace_favorites(user_id, room_ids)?;
+    Ok(Json(cm.lock().unwrap().favorite_rooms(found.as_ref())))
+}
+
+#[delete("/<user_id>/favorites/<room_id>")]
+pub fn remove_favorite(db: State<Arc<UserDbService>>, cm: State<Mutex<ChatManager>>, auth: AuthUser,
+                       user_id: String, room_id: String) -> Result<Json<Vec<FavoriteRoom>>, ApiError> {
+    own_account(&auth, &user_id)?;
+    let found = db.remove_favorite(user_id, room_id)?;
+    Ok(Json(cm.lock().unwrap().favorite_rooms(found.as_ref())))
+}
+
+fn room_ids(values: Vec<String>) -> Vec<String> {
+    values.iter()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+fn known_rooms(cm: &Mutex<ChatManager>, room_ids: &[String]) -> Result<(), ApiError> {
+    let cm = cm.lock().unwrap();
+    match room_ids.iter().find(|id| !cm.room_exists(id)) {
+        Some(id) => Err(ApiError::not_found(format!("Room {} doesn't exist.", id))),
+        None => Ok(())
+    }
+}
+
+#[get("/<user_id>/unread")]
+pub fn unread(cm: State<Mutex<ChatManager>>, auth: AuthUser, user_id: String) -> Result<Json<Vec<UnreadCount>>, ApiError> {
+    own_account(&auth, &user_id)?;
+    Ok(Json(cm.lock().unwrap().unread_counts(&auth.user.to_user())))
+}
+
+#[get("/me")]
+pub fn me(auth: AuthUser) -> Json<User> {
+    Json(auth.user.to_user())
+}
+
+#[get("/me/export")]
+pub fn export(db: State<Arc<UserDbService>>, auth: AuthUser) -> Result<Json<UserExport>, ApiError> {
+    Ok(Json(db.export_user(auth.user_id())?))
+}
+
+#[get("/<user_id>", rank = 2)]
+pub fn get_user(auth: AuthUser, user_id: String) -> Result<Json<User>, ApiError> {
+    own_account(&auth, &user_id)?;
+    Ok(Json(auth.user.to_user()))
+}
+
+#[patch("/<user_id>", format = "json", data = "<patch>", rank = 2)]
+pub fn update_user(db: State<Arc<UserDbService>>, auth: AuthUser, user_id: String, patch: Json<UserPatch>) -> Result<Json<User>, ApiError> {
+    own_account(&auth, &user_id)?;
+    let user = db.rename_user(user_id, patch.into_inner().user_name)?;
+    Ok(Json(user.to_user()))
+}
+
+//The auth guard reads cookies itself, so it has to come before the Cookies guard.
+//Dropping the cookie ends the session, the guard rejects deleted ids anyway.
+#[delete("/<user_id>?<anonymize>", rank = 2)]
+pub fn delete_user(db: State<Arc<UserDbService>>, cm: State<Mutex<ChatManager>>, attachments: State<AttachmentService>, auth: AuthUser,
+                   mut cookies: Cookies, user_id: String, anonymize: Option<bool>) -> Result<Json<UserDeleted>, ApiError> {
+    own_account(&auth, &user_id)?;
+    let avatar = auth.user.to_user().profile.avatar;
+    db.delete_user(auth.user, anonymize.unwrap_or(false))?;
+    cm.lock().unwrap().hand_over_rooms(&user_id);
+    remove_previous_avatar(&attachments, avatar);
+    cookies.remove(Cookie::named(USER_COOKIE));
+    Ok(Json(UserDeleted {
+        user_id
+    }))
+}
+
+//Profiles are public to anyone signed in, so clients can show who is in a room.
+#[get("/<user_id>/profile")]
+pub fn profile(db: State<Arc<UserDbService>>, _auth: AuthUser, user_id: String) -> Result<Json<Profile>, ApiError> {
+    Ok(Json(existing_user(&db, user_id)?.profile))
+}
+
+#[patch("/<user_id>/profile", format = "json", data = "<patch>")]
+pub fn update_profile(db: State<Arc<UserDbService>>, auth: AuthUser, user_id: String, patch: Json<ProfilePatch>) -> Result<Json<Profile>, ApiError> {
+    own_account(&auth, &user_id)?;
+    let user = db.update_profile(user_id, patch.into_inner())?;
+    Ok(Json(user.to_user().profile))
+}
+
+#[get("/<user_id>/avatar")]
+pub fn avatar(db: State<Arc<UserDbService>>, attachments: State<AttachmentService>, _auth: AuthUser,
+              user_id: String) -> Result<AttachmentDownload, ApiError> {
+    let no_avatar = || ApiError::not_found(String::from("User has no avatar."));
+    let avatar_id = existing_user(&db, user_id)?.profile.avatar.ok_or_else(no_avatar)?;
+    let attachment = attachments.find(&avatar_id).ok_or_else(no_avatar)?;
+    let bytes = attachments.download(&attachment)?;
+    Ok(AttachmentDownload::new(attachment, bytes))
+}
+
+//Goes through the attachment checks like any upload, but only images are accepted.
+#[put("/<user_id>/avatar", data = "<data>")]
+pub fn upload_avatar(db: State<Arc<UserDbService>>, attachments: State<AttachmentService>, auth: AuthUser,
+                     content_type: &ContentType, user_id: String, data: Data) -> Result<Json<Profile>, ApiError> {
+    own_account(&auth, &user_id)?;
+    if content_type.top() != "image" {
+        return Err(ApiError::new(Status::UnsupportedMediaType, "unsupported_type", String::from("Avatars must be images.")));
+    }
+    let mut bytes = vec![];
+    if data.open().take(MAX_ATTACHMENT_SIZE + 1).read_to_end(&mut bytes).is_err() {
+        return Err(ApiError::new(Status::BadRequest, "validation", String::from("Unable to read the upload.")));
+    }
+    let media_type = format!("{}/{}", content_type.top(), content_type.sub());
+    //Avatars belong to no room, so the room attachment route never serves them.
+    let attachment = Attachment::new(String::new(), user_id.clone(), String::from("avatar"), media_type, bytes.len() as u64);
+    let attachment = attachments.upload(attachment, &bytes)?;
+    let previous = auth.user.to_user().profile.avatar;
+    let user = db.set_avatar(user_id, Some(attachment.id))?;
+    remove_previous_avatar(&attachments, previous);
+    Ok(Json(user.to_user().profile))
+}
+
+#[delete("/<user_id>/avatar")]
+pub fn remove_avatar(db: State<Arc<UserDbService>>, attachments: State<AttachmentService>, auth: AuthUser,
+                     user_id: String) -> Result<Json<Profile>, ApiError> {
+    own_account(&auth, &user_id)?;
+    let previous = auth.user.to_user().profile.avatar;
+    let user = db.set_avatar(user_id, None)?;
+    remove_previous_avatar(&attachments, previous);
+    Ok(Json(user.to_user().profile))
+}
+
+//The storage drops the old avatar's row along with the profile change, its file goes here.
+fn remove_previous_avatar(attachments: &AttachmentService, previous: Option<String>) {
+    if let Some(previous) = previous {
+        let _ = attachments.remove(&previous);
+    }
+}
+
+#[get("/<user_id>/blocked")]
+pub fn blocked(db: State<Arc<UserDbService>>, auth: AuthUser, user_id: String) -> Result<Json<Vec<BlockedUser>>, ApiError> {
+    own_account(&auth, &user_id)?;
+    Ok(Json(blocked_users(&db, &auth.user.to_user())?))
+}
+
+#[put("/<user_id>/blocked/<user_name>")]
+pub fn block_user(db: State<Arc<UserDbService>>, auth: AuthUser, user_id: String, user_name: String) -> Result<Json<Vec<BlockedUser>>, ApiError> {
+    own_account(&auth, &user_id)?;
+    let blocked_id = user_id_of(&db, user_name)?;
+    let user = db.block_user(user_id, blocked_id)?;
+    Ok(Json(blocked_users(&db, &user.to_user())?))
+}
+
+#[delete("/<user_id>/blocked/<user_name>")]
+pub fn unblock_user(db: State<Arc<UserDbService>>, auth: AuthUser, user_id: String, user_name: String) -> Result<Json<Vec<BlockedUser>>, ApiError> {
+    own_account(&auth, &user_id)?;
+    let blocked_id = user_id_of(&db, user_name)?;
+    let user = db.unblock_user(user_id, blocked_id)?;
+    Ok(Json(blocked_users(&db, &user.to_user())?))
+}
+
+//Names are looked up each time so a renamed user still shows correctly.
+fn blocked_users(db: &UserDbService, user: &User) -> Result<Vec<BlockedUser>, ApiError> {
+    let mut blocked = vec![];
+    for user_id in user.blocked_users.iter() {
+        let found = db.retrieve_user_by_id(user_id.clone())?;
+        blocked.push(BlockedUser {
+            user_name: found.user_name().clone()
+        });
+    }
+    Ok(blocked)
+}
+
+fn user_id_of(db: &UserDbService, user_name: String) -> Result<String, ApiError> {
+    db.find_user_by_name(user_name)?.user_id().cloned()
+        .ok_or_else(|| ApiError::not_found(String::from("User doesn't exist.")))
+}
+
+//Other users are only known by name, their user id never leaves the server.
+#[get("/<user_name>/presence")]
+pub fn presence(db: State<Arc<UserDbService>>, tracker: State<Arc<PresenceTracker>>, _auth: AuthUser,
+                user_name: String) -> Result<Json<UserPresence>, ApiError> {
+    let user = db.find_user_by_name(user_name)?;
+    let user_id = user.user_id().ok_or_else(|| ApiError::not_found(String::from("User doesn't exist.")))?;
+    Ok(Json(UserPresence {
+        status: tracker.status(user_id, Instant::now()),
+        user_name: user.user_name().clone()
+    }))
+}
+
+//Away and DND stay set until the user sends online, offline is only reached by leaving.
+#[put("/<user_id>/presence", format = "json", data = "<update>")]
+pub fn set_presence(cm: State<Mutex<ChatManager>>, tracker: State<Arc<PresenceTracker>>, auth: AuthUser,
+                    user_id: String, update: Json<PresenceUpdate>) -> Result<Json<UserPresence>, ApiError> {
+    own_account(&auth, &user_id)?;
+    if update.status == PresenceStatus::Offline {
+        return Err(ApiError::new(Status::BadRequest, "validation", String::from("Offline can't be set, disconnect instead.")));
+    }
+    let now = Instant::now();
+    if let Some(status) = tracker.set_manual(&user_id, update.status, now) {
+        cm.lock().unwrap().announce_presence(&user_id, status);
+    }
+    Ok(Json(UserPresence {
+        status: tracker.status(&user_id, now),
+        user_name: auth.user.user_name().clone()
+    }))
+}
+
+fn existing_user(db: &UserDbService, user_id: String) -> Result<User, ApiError> {
+    let found = db.retrieve_user_by_id(user_id)?;
+    if found.user_id().is_none() {
+        return Err(ApiError::not_found(String::from("User doesn't exist.")));
+    }
+    Ok(found.to_user())
+}
+
+fn own_account(auth: &AuthUser, user_id: &str) -> Result<(), ApiError> {
+    if auth.is(user_id) {
+        Ok(())
     } else {
-        Err(Status::NotFound)
+        Err(ApiError::forbidden("You can only manage your own account."))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::local::Client;
+    use rocket::http::{ContentType, Cookie, Status};
+    use std::sync::Arc;
+    use crate::user::user_db_service::UserDbService;
+    use crate::routes::api_error::{self, ErrorBody};
+    use crate::user::{User, UserAvailable, Profile, BlockedUser, UserExport};
+    use crate::chat::attachments::{AttachmentService, LocalDiskStorage};
+    use crate::chat::message_store::MessageStore;
+    use crate::chat::chat_manager::ChatManager;
+    use crate::chat::chat_data::{PresenceStatus, UserPresence};
+    use crate::chat::presence::PresenceTracker;
+    use crate::routes::guest_limiter::{GuestLimiter, GUESTS_PER_WINDOW};
+    use std::net::SocketAddr;
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+    use std::time::Instant;
+    use uuid::Uuid;
+
+    fn client() -> Client {
+        client_with_presence(Arc::new(PresenceTracker::new()))
+    }
+
+    fn client_with_presence(tracker: Arc<PresenceTracker>) -> Client {
+        client_with(tracker, avatar_root())
+    }
+
+    fn avatar_root() -> PathBuf {
+        std::env::temp_dir().join(format!("crabby-avatars-{}", Uuid::new_v4()))
+    }
+
+    fn client_with(tracker: Arc<PresenceTracker>, root: PathBuf) -> Client {
+        let file = std::fs::File::open("./test/test_data.sql").unwrap();
+        let db = Arc::new(UserDbService::from_file(file).unwrap());
+        let attachments = AttachmentService::new(Box::new(LocalDiskStorage::new(root).unwrap()),
+                                                 Arc::new(Mutex::new(MessageStore::new())));
+        let rocket = rocket::ignite()
+            .manage(db)
+            .manage(attachments)
+            .manage(tracker)
+            .manage(GuestLimiter::default())
+            .manage(Mutex::new(ChatManager::new()))
+            .mount("/user", routes![super::register, super::check_name, super::me, super::get_user,
+                super::update_user, super::delete_user, super::profile, super::update_profile, super::avatar,
+                super::upload_avatar, super::remove_avatar, super::presence, super::set_presence,
+                super::blocked, super::block_user, super::unblock_user, super::export, super::guest, super::upgrade, super::unread])
+            .register(catchers![api_error::unauthorized]);
+        Client::new(rocket).unwrap()
+    }
+
+    #[test]
+    fn me_requires_a_known_user() {
+        let client = client();
+        let mut response = client.get("/user/me").dispatch();
+        assert_eq!(Status::Unauthorized, response.status());
+        let body: ErrorBody = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!("unauthorized", body.code);
+
+        let response = client.get("/user/me").cookie(Cookie::new("user-id", "nobody")).dispatch();
+        assert_eq!(Status::Unauthorized, response.status());
+
+        let mut response = client.get("/user/me").cookie(Cookie::new("user-id", "abcd-1234")).dispatch();
+        let user: User = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!("jhalpert", user.user_name);
+    }
+
+    #[test]
+    fn users_can_only_manage_their_own_account() {
+        let client = client();
+        let mut response = client.get("/user/bcde-2345").cookie(Cookie::new("user-id", "abcd-1234")).dispatch();
+        assert_eq!(Status::Forbidden, response.status());
+        let body: ErrorBody = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!("forbidden", body.code);
+
+        let response = client.delete("/user/bcde-2345").cookie(Cookie::new("user-id", "abcd-1234")).dispatch();
+        assert_eq!(Status::Forbidden, response.status());
+
+        assert_eq!(Status::Unauthorized, client.get("/user/bcde-2345/unread").dispatch().status());
+        let response = client.get("/user/bcde-2345/unread").cookie(Cookie::new("user-id", "abcd-1234")).dispatch();
+        assert_eq!(Status::Forbidden, response.status());
+        let response = client.get("/user/abcd-1234/unread").cookie(Cookie::new("user-id", "abcd-1234")).dispatch();
+        assert_eq!(Status::Ok, response.status());
+    }
+
+    #[test]
+    fn user_can_rename_and_delete_themselves() {
+        let client = client();
+        let mut response = client.patch("/user/abcd-1234")
+            .header(ContentType::JSON)
+            .cookie(Cookie::new("user-id", "abcd-1234"))
+            .body(r#"{"user_name":"bigtuna"}"#)
+            .dispatch();
+        let user: User = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!("bigtuna", user.user_name);
+
+        let response = client.delete("/user/abcd-1234").cookie(Cookie::new("user-id", "abcd-1234")).dispatch();
+        assert_eq!(Status::Ok, response.status());
+        let response = client.get("/user/me").cookie(Cookie::new("user-id", "abcd-1234")).dispatch();
+        assert_eq!(Status::Unauthorized, response.status());
+    }
+
+    #[test]
+    fn users_export_their_data_and_can_delete_anonymously() {
+        let client = client();
+        let response = client.get("/user/me/export").dispatch();
+        assert_eq!(Status::Unauthorized, response.status());
+
+        let mut response = client.get("/user/me/export").cookie(Cookie::new("user-id", "abcd-1234")).dispatch();
+        let export: UserExport = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!("jhalpert", export.user.user_name);
+        assert_eq!(vec![String::from("dunmifsys"), String::from("bigtuna")], export.user.favorite_rooms);
+
+        let response = client.delete("/user/abcd-1234?anonymize=true").cookie(Cookie::new("user-id", "abcd-1234")).dispatch();
+        assert_eq!(Status::Ok, response.status());
+        let response = client.get("/user/me/export").cookie(Cookie::new("user-id", "abcd-1234")).dispatch();
+        assert_eq!(Status::Unauthorized, response.status());
+    }
+
+    #[test]
+    fn guests_are_limited_per_address() {
+        let client = client();
+        let first = SocketAddr::from(([10, 0, 0, 1], 5000));
+        for _ in 0..GUESTS_PER_WINDOW {
+            assert_eq!(Status::Ok, client.post("/user/guest").remote(first).dispatch().status());
+        }
+        let mut response = client.post("/user/guest").remote(first).dispatch();
+        assert_eq!(Status::TooManyRequests, response.status());
+        let body: ErrorBody = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!("too_many_guests", body.code);
+
+        let second = SocketAddr::from(([10, 0, 0, 2], 5000));
+        assert_eq!(Status::Ok, client.post("/user/guest").remote(second).dispatch().status());
+    }
+
+    #[test]
+    fn guests_are_signed_in_and_can_upgrade() {
+        let client = client();
+        let mut response = client.post("/user/guest").dispatch();
+        let cookie = response.cookies().into_iter().find(|c| c.name() == "user-id").unwrap().value().to_string();
+        let guest: User = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert!(guest.guest);
+        let guest_id = guest.user_id.unwrap();
+        assert_eq!(guest_id, cookie);
+
+        let upgrade = format!("/user/{}/upgrade", guest_id);
+        let response = client.post(&upgrade)
+            .header(ContentType::JSON)
+            .cookie(Cookie::new("user-id", guest_id.clone()))
+            .body(r#"{"user_name":"jhalpert"}"#)
+            .dispatch();
+        assert_eq!(Status::Conflict, response.status());
+
+        let mut response = client.post(&upgrade)
+            .header(ContentType::JSON)
+            .cookie(Cookie::new("user-id", guest_id.clone()))
+            .body(r#"{"user_name":"rhoward"}"#)
+            .dispatch();
+        let user: User = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert!(!user.guest);
+        assert_eq!("rhoward", user.user_name);
+        assert_eq!(Some(guest_id), user.user_id);
+
+        let response = client.post("/user/abcd-1234/upgrade")
+            .header(ContentType::JSON)
+            .cookie(Cookie::new("user-id", "abcd-1234"))
+            .body(r#"{"user_name":"bigtuna"}"#)
+            .dispatch();
+        assert_eq!(Status::BadRequest, response.status());
+    }
+
+    #[test]
+    fn duplicate_names_are_rejected_ignoring_case() {
+        let client = client();
+        let mut response = client.post("/user/register")
+            .header(ContentType::Form)
+            .body("user_name=JHalpert&password=beesly")
+            .dispatch();
+        assert_eq!(Status::Conflict, response.status());
+        let body: ErrorBody = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!("conflict", body.code);
+
+        let response = client.post("/user/register")
+            .header(ContentType::Form)
+            .body("user_name=kmalone&password=chili")
+            .dispatch();
+        assert_eq!(Status::Ok, response.status());
+
+        let response = client.patch("/user/abcd-1234")
+            .header(ContentType::JSON)
+            .cookie(Cookie::new("user-id", "abcd-1234"))
+            .body(r#"{"user_name":"MScott"}"#)
+            .dispatch();
+        assert_eq!(Status::Conflict, response.status());
+    }
+
+    #[test]
+    fn invalid_names_are_validation_errors() {
+        let client = client();
+        let mut response = client.post("/user/register")
+            .header(ContentType::Form)
+            .body("user_name=%20&password=beesly")
+            .dispatch();
+        assert_eq!(Status::BadRequest, response.status());
+        let body: ErrorBody = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!("validation", body.code);
+    }
+
+    #[test]
+    fn availability_is_reported_per_name() {
+        let client = client();
+        let mut response = client.get("/user/available?names=JHALPERT,kmalone").dispatch();
+        let names: Vec<UserAvailable> = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert!(!names[0].available);
+        assert!(names[1].available);
+    }
+
+    #[test]
+    fn profiles_are_edited_by_their_owner_and_seen_by_others() {
+        let client = client();
+        let response = client.patch("/user/bcde-2345/profile")
+            .header(ContentType::JSON)
+            .cookie(Cookie::new("user-id", "abcd-1234"))
+            .body(r#"{"status":"World's best boss"}"#)
+            .dispatch();
+        assert_eq!(Status::Forbidden, response.status());
+
+        let response = client.patch("/user/abcd-1234/profile")
+            .header(ContentType::JSON)
+            .cookie(Cookie::new("user-id", "abcd-1234"))
+            .body(r#"{"display_name":"Jim","status":"Big Tuna","timezone":"Mars/Base"}"#)
+            .dispatch();
+        assert_eq!(Status::Ok, response.status());
+
+        let mut response = client.get("/user/abcd-1234/profile").cookie(Cookie::new("user-id", "bcde-2345")).dispatch();
+        let profile: Profile = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(Some(String::from("Big Tuna")), profile.status);
+        assert_eq!(Some(String::from("Mars/Base")), profile.timezone);
+
+        let response = client.patch("/user/abcd-1234/profile")
+            .header(ContentType::JSON)
+            .cookie(Cookie::new("user-id", "abcd-1234"))
+            .body(r#"{"timezone":"Scranton"}"#)
+            .dispatch();
+        assert_eq!(Status::BadRequest, response.status());
+
+        let response = client.get("/user/nobody/profile").cookie(Cookie::new("user-id", "abcd-1234")).dispatch();
+        assert_eq!(Status::NotFound, response.status());
+    }
+
+    #[test]
+    fn avatars_must_be_images() {
+        let client = client();
+        let response = client.put("/user/abcd-1234/avatar")
+            .header(ContentType::Plain)
+            .cookie(Cookie::new("user-id", "abcd-1234"))
+            .body("not a picture")
+            .dispatch();
+        assert_eq!(Status::UnsupportedMediaType, response.status());
+
+        let png = b"\x89PNG\r\n\x1a\nrest of the image".to_vec();
+        let mut response = client.put("/user/abcd-1234/avatar")
+            .header(ContentType::PNG)
+            .cookie(Cookie::new("user-id", "abcd-1234"))
+            .body(png.clone())
+            .dispatch();
+        let profile: Profile = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert!(profile.avatar.is_some());
+
+        let mut response = client.get("/user/abcd-1234/avatar").cookie(Cookie::new("user-id", "bcde-2345")).dispatch();
+        assert_eq!(Some(ContentType::PNG), response.content_type());
+        assert_eq!(png, response.body_bytes().unwrap());
+
+        client.delete("/user/abcd-1234/avatar").cookie(Cookie::new("user-id", "abcd-1234")).dispatch();
+        let response = client.get("/user/abcd-1234/avatar").cookie(Cookie::new("user-id", "bcde-2345")).dispatch();
+        assert_eq!(Status::NotFound, response.status());
+    }
+
+    #[test]
+    fn replaced_and_removed_avatars_leave_no_files_behind() {
+        let root = avatar_root();
+        let client = client_with(Arc::new(PresenceTracker::new()), root.clone());
+        let files = || std::fs::read_dir(&root).unwrap().count();
+        let upload = || client.put("/user/abcd-1234/avatar")
+            .header(ContentType::PNG)
+            .cookie(Cookie::new("user-id", "abcd-1234"))
+            .body(&b"\x89PNG\r\n\x1a\nrest of the image"[..])
+            .dispatch();
+
+        assert_eq!(Status::Ok, upload().status());
+        assert_eq!(Status::Ok, upload().status());
+        assert_eq!(1, files());
+
+        let response = client.delete("/user/abcd-1234/avatar").cookie(Cookie::new("user-id", "abcd-1234")).dispatch();
+        assert_eq!(Status::Ok, response.status());
+        assert_eq!(0, files());
+    }
+
+    #[test]
+    fn presence_is_reported_and_set_by_the_owner() {
+        let tracker = Arc::new(PresenceTracker::new());
+        let client = client_with_presence(tracker.clone());
+        let presence = |client: &Client| -> UserPresence {
+            let mut response = client.get("/user/jhalpert/presence").cookie(Cookie::new("user-id", "bcde-2345")).dispatch();
+            let body = response.body_string().unwrap();
+            assert!(!body.contains("abcd-1234"));
+            serde_json::from_str(&body).unwrap()
+        };
+        assert_eq!(PresenceStatus::Offline, presence(&client).status);
+        assert_eq!("jhalpert", presence(&client).user_name);
+
+        tracker.connected("abcd-1234", "room-1", Instant::now());
+        assert_eq!(PresenceStatus::Online, presence(&client).status);
+
+        let response = client.put("/user/abcd-1234/presence")
+            .header(ContentType::JSON)
+            .cookie(Cookie::new("user-id", "bcde-2345"))
+            .body(r#"{"status":"away"}"#)
+            .dispatch();
+        assert_eq!(Status::Forbidden, response.status());
+
+        let response = client.put("/user/abcd-1234/presence")
+            .header(ContentType::JSON)
+            .cookie(Cookie::new("user-id", "abcd-1234"))
+            .body(r#"{"status":"offline"}"#)
+            .dispatch();
+        assert_eq!(Status::BadRequest, response.status());
+
+        client.put("/user/abcd-1234/presence")
+            .header(ContentType::JSON)
+            .cookie(Cookie::new("user-id", "abcd-1234"))
+            .body(r#"{"status":"dnd"}"#)
+            .dispatch();
+        assert_eq!(PresenceStatus::Dnd, presence(&client).status);
+
+        let response = client.get("/user/nobody/presence").cookie(Cookie::new("user-id", "abcd-1234")).dispatch();
+        assert_eq!(Status::NotFound, response.status());
+    }
+
+    #[test]
+    fn block_list_is_managed_through_the_api() {
+        let client = client();
+        let response = client.put("/user/bcde-2345/blocked/jhalpert").cookie(Cookie::new("user-id", "abcd-1234")).dispatch();
+        assert_eq!(Status::Forbidden, response.status());
+        let response = client.put("/user/abcd-1234/blocked/jhalpert").cookie(Cookie::new("user-id", "abcd-1234")).dispatch();
+        assert_eq!(Status::BadRequest, response.status());
+        let response = client.put("/user/abcd-1234/blocked/nobody").cookie(Cookie::new("user-id", "abcd-1234")).dispatch();
+        assert_eq!(Status::NotFound, response.status());
+
+        client.put("/user/abcd-1234/blocked/mscott").cookie(Cookie::new("user-id", "abcd-1234")).dispatch();
+        let mut response = client.get("/user/abcd-1234/blocked").cookie(Cookie::new("user-id", "abcd-1234")).dispatch();
+        let body = response.body_string().unwrap();
+        assert!(!body.contains("bcde-2345"));
+        let blocked: Vec<BlockedUser> = serde_json::from_str(&body).unwrap();
+        assert_eq!(1, blocked.len());
+        assert_eq!("mscott", blocked[0].user_name);
+        let mut response = client.get("/user/me").cookie(Cookie::new("user-id", "abcd-1234")).dispatch();
+        assert!(!response.body_string().unwrap().contains("bcde-2345"));
+
+        let mut response = client.delete("/user/abcd-1234/blocked/mscott").cookie(Cookie::new("user-id", "abcd-1234")).dispatch();
+        let blocked: Vec<BlockedUser> = serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert!(blocked.is_empty());
+    }
+}
@@ -0,0 +1,574 @@
+pub mod sqlite;
+#[cfg(feature = "postgres-backend")]
+pub mod postgres;
+
+use crate::user::{User, Profile};
+use crate::user::user_db_service::DbServiceError;
+use crate::chat::message_store::{StoredMessage, SearchQuery};
+use crate::chat::attachments::Attachment;
+use crate::chat::chat_data::{SearchHit, OwnershipChange};
+
+pub const SEARCH_LIMIT: i64 = 50;
+//Private use code points mark matches so the snippet can be escaped before highlighting.
+pub const MATCH_START: char = '\u{E000}';
+pub const MATCH_END: char = '\u{E001}';
+
+//Everything the server keeps between requests. Each call is atomic, so a backend runs
+//it in a single transaction, and per user writes report NotFound for unknown users.
+pub trait Storage: Send + Sync {
+    fn create_user(&self, user: User) -> Result<User, DbServiceError>;
+
+    //Comes back with favorites, read markers, the profile and the block list.
+    fn find_user(&self, user_id: &str) -> Result<Option<User>, DbServiceError>;
+
+    fn find_user_by_name(&self, user_name: &str) -> Result<Option<User>, DbServiceError>;
+
+    //Saves the name and appends any new favorites together.
+    fn update_user(&self, user: &User) -> Result<(), DbServiceError>;
+
+    fn rename_user(&self, user_id: &str, user_name: &str) -> Result<User, DbServiceError>;
+
+    //Registers a guest under a name of their own, the messages they sent as a guest move to the new name.
+    fn upgrade_guest(&self, user_id: &str, user_name: &str) -> Result<User, DbServiceError>;
+
+    //Guests start out seen when they're created, this moves that forward. Registered users are left alone.
+    fn touch_guest(&self, user_id: &str, seen_at: i64) -> Result<(), DbServiceError>;
+
+    //The ids of guests not seen since seen_before, oldest first.
+    fn idle_guests(&self, seen_before: i64) -> Result<Vec<String>, DbServiceError>;
+
+    //Takes every row that belongs to the user. Their messages are kept but no longer tied to the
+    //account, with anonymize_as they and any uploads are credited to that name as well.
+    fn delete_user(&self, user_id: &str, anonymize_as: Option<&str>) -> Result<(), DbServiceError>;
+
+    fn add_favorites(&self, user_id: &str, room_ids: &[String]) -> Result<User, DbServiceError>;
+
+    fn replace_favorites(&self, user_id: &str, room_ids: &[String]) -> Result<User, DbServiceError>;
+
+    fn remove_favorite(&self, user_id: &str, room_id: &str) -> Result<User, DbServiceError>;
+
+    fn remove_room_from_favorites(&self, room_id: &str) -> Result<(), DbServiceError>;
+
+    //Replaces every profile field with the given ones. A replaced or removed avatar takes its attachment row with it.
+    fn update_profile(&self, user_id: &str, profile: &Profile) -> Result<User, DbServiceError>;
+
+    //Blocking twice is the same as once, both users have to exist.
+    fn block_user(&self, user_id: &str, blocked_id: &str) -> Result<User, DbServiceError>;
+
+    fn unblock_user(&self, user_id: &str, blocked_id: &str) -> Result<User, DbServiceError>;
+
+    //The ids of everyone who blocked this user.
+    fn blocked_by(&self, user_id: &str) -> Result<Vec<String>, DbServiceError>;
+
+    //Markers only move forward, an older one is ignored.
+    fn update_read_marker(&self, user_id: &str, room_id: &str, last_read: u64) -> Result<User, DbServiceError>;
+
+    //Adds the room or overwrites what was saved for it, which is how edits are kept.
+    fn save_room(&self, room: &RoomRecord) -> Result<(), DbServiceError>;
+
+    fn delete_room(&self, room_id: &str) -> Result<(), DbServiceError>;
+
+    //last_message_id is worked out from the saved messages.
+    fn rooms(&self) -> Result<Vec<RoomRecord>, DbServiceError>;
+
+    //Saves the room along with the entry explaining why its owners changed.
+    fn change_owners(&self, room: &RoomRecord, change: &OwnershipChange) -> Result<(), DbServiceError>;
+    //Oldest first.
+    fn ownership_changes(&self, room_id: &str) -> Result<Vec<OwnershipChange>, DbServiceError>;
+
+    fn save_message(&self, msg: &StoredMessage) -> Result<(), DbServiceError>;
+
+    fn room_messages(&self, room_id: &str) -> Result<Vec<StoredMessage>, DbServiceError>;
+
+    //Everything sent from the account, whatever name it was under at the time.
+    fn user_messages(&self, user_id: &str) -> Result<Vec<StoredMessage>, DbServiceError>;
+
+    fn save_attachment(&self, attachment: &Attachment) -> Result<(), DbServiceError>;
+
+    fn find_attachment(&self, id: &str) -> Result<Option<Attachment>, DbServiceError>;
+
+    fn user_attachments(&self, user_id: &str) -> Result<Vec<Attachment>, DbServiceError>;
+
+    //Words in the query are matched as plain text, never as the backend's query syntax.
+    fn search(&self, query: &SearchQuery) -> Result<Vec<SearchHit>, DbServiceError>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoomRecord {
+    pub room_id: String,
+    pub name: String,
+    pub owner_id: String,
+    pub last_message_id: u64,
+    pub topic: Option<String>,
+    pub description: Option<String>,
+    pub slug: String,
+    pub co_owners: Vec<String>,
+    //Only set for temporary rooms.
+    pub grace: Option<i64>,
+    pub expires_at: Option<i64>,
+    pub capacity: Option<i64>
+}
+
+pub fn user_not_found() -> DbServiceError {
+    DbServiceError::NotFound(String::from("User doesn't exist."))
+}
+
+//Mentions and attachment ids are stored comma separated.
+pub fn split_list(value: &str) -> Vec<String> {
+    value.split(',').filter(|v| !v.is_empty()).map(String::from).collect()
+}
+
+//Escapes the snippet a backend returns, turning the match markers into <mark> tags.
+pub fn highlight(snippet: &str) -> String {
+    let mut out = String::with_capacity(snippet.len());
+    for c in snippet.chars() {
+        match c {
+            MATCH_START => out.push_str("<mark>"),
+            MATCH_END => out.push_str("</mark>"),
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c)
+        }
+    }
+    out
+}
+
+//The behaviour every backend has to share, each backend runs it through storage_tests!.
+#[cfg(test)]
+pub mod suite {
+    use crate::storage::{Storage, RoomRecord};
+    use crate::chat::chat_data::{OwnershipChange, OwnershipAction};
+    use crate::user::{User, IUser, Profile};
+    use crate::user::user_db_service::DbServiceError;
+    use crate::chat::message_store::{StoredMessage, SearchQuery};
+    use crate::chat::attachments::Attachment;
+    use chrono::Utc;
+
+    fn user(storage: &dyn Storage, name: &str) -> User {
+        let mut user = User::new(String::from(name));
+        user.set_user_id(format!("{}-id", name));
+        storage.create_user(user).unwrap()
+    }
+
+    fn profile() -> Profile {
+        Profile {
+            display_name: Some(String::from("Jim Halpert")),
+            avatar: Some(String::from("2b1f5a0c-3c1e-4b8e-9d7a-5f2f0e6c1a11")),
+            status: Some(String::from("Out on a sales call")),
+            bio: Some(String::from("Salesman, Scranton branch.")),
+            timezone: Some(String::from("America/New_York"))
+        }
+    }
+
+    fn rooms(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| String::from(*id)).collect()
+    }
+
+    fn attachment(room_id: &str, uploader_id: &str) -> Attachment {
+        Attachment::new(String::from(room_id), String::from(uploader_id),
+                        String::from("mug.png"), String::from("image/png"), 42)
+    }
+
+    fn message(room_id: &str, message_id: u64, from: &str, msg: &str, sent_at: i64) -> StoredMessage {
+        StoredMessage {
+            room_id: String::from(room_id),
+            message_id,
+            from: String::from(from),
+            sender_id: Some(format!("{}-id", from)),
+            msg: String::from(msg),
+            rendered: String::from(msg),
+            mentions: vec![],
+            attachments: vec![],
+            sent_at
+        }
+    }
+
+    fn query(text: &str, rooms: Vec<&str>) -> SearchQuery {
+        SearchQuery {
+            text: String::from(text),
+            rooms: rooms.iter().map(|r| String::from(*r)).collect(),
+            from: None,
+            after: None,
+            before: None
+        }
+    }
+
+    pub fn users_round_trip(storage: &dyn Storage) {
+        let created = user(storage, "jhalpert");
+        let found = storage.find_user("jhalpert-id").unwrap().unwrap();
+        assert_eq!(created, found);
+        assert!(storage.find_user("nobody").unwrap().is_none());
+        assert_eq!("jhalpert-id", storage.find_user_by_name("JHalpert").unwrap().unwrap().user_id().unwrap());
+    }
+
+    pub fn user_names_are_unique_ignoring_case(storage: &dyn Storage) {
+        user(storage, "mscott");
+        let mut copy = User::new(String::from("MScott"));
+        copy.set_user_id(String::from("other-id"));
+        assert!(matches!(storage.create_user(copy), Err(DbServiceError::Conflict(_))));
+        user(storage, "dschrute");
+        assert!(matches!(storage.rename_user("dschrute-id", "mscott"), Err(DbServiceError::Conflict(_))));
+    }
+
+    pub fn update_is_all_or_nothing(storage: &dyn Storage) {
+        user(storage, "mscott");
+        let mut jim = user(storage, "jhalpert");
+        jim.set_user_name(String::from("MSCOTT"));
+        jim.add_favorites(rooms(&["sales"]));
+        assert!(storage.update_user(&jim).is_err());
+        let found = storage.find_user("jhalpert-id").unwrap().unwrap();
+        assert_eq!("jhalpert", found.user_name());
+        assert_eq!(0, found.favorites().count());
+
+        jim.set_user_name(String::from("bigtuna"));
+        storage.update_user(&jim).unwrap();
+        let found = storage.find_user("jhalpert-id").unwrap().unwrap();
+        assert_eq!("bigtuna", found.user_name());
+        assert_eq!(rooms(&["sales"]), found.favorite_rooms);
+    }
+
+    pub fn favorites_keep_their_order(storage: &dyn Storage) {
+        user(storage, "jhalpert");
+        storage.add_favorites("jhalpert-id", &rooms(&["sales", "annex"])).unwrap();
+        let found = storage.add_favorites("jhalpert-id", &rooms(&["annex", "accounting"])).unwrap();
+        assert_eq!(rooms(&["sales", "annex", "accounting"]), found.favorite_rooms);
+
+        let found = storage.replace_favorites("jhalpert-id", &rooms(&["accounting", "sales"])).unwrap();
+        assert_eq!(rooms(&["accounting", "sales"]), found.favorite_rooms);
+
+        let found = storage.remove_favorite("jhalpert-id", "accounting").unwrap();
+        assert_eq!(rooms(&["sales"]), found.favorite_rooms);
+    }
+
+    pub fn removed_room_leaves_every_favorites_list(storage: &dyn Storage) {
+        user(storage, "jhalpert");
+        user(storage, "pbeesly");
+        storage.add_favorites("jhalpert-id", &rooms(&["sales", "annex"])).unwrap();
+        storage.add_favorites("pbeesly-id", &rooms(&["annex"])).unwrap();
+        storage.remove_room_from_favorites("annex").unwrap();
+        assert_eq!(rooms(&["sales"]), storage.find_user("jhalpert-id").unwrap().unwrap().favorite_rooms);
+        assert_eq!(0, storage.find_user("pbeesly-id").unwrap().unwrap().favorites().count());
+    }
+
+    pub fn guests_upgrade_keeping_their_history(storage: &dyn Storage) {
+        let mut guest = User::new(String::from("guest-4f2a"));
+        guest.set_user_id(String::from("guest-id"));
+        guest.guest = true;
+        storage.create_user(guest).unwrap();
+        storage.add_favorites("guest-id", &rooms(&["annex"])).unwrap();
+        let asked = StoredMessage { sender_id: Some(String::from("guest-id")), ..message("annex", 1, "guest-4f2a", "Is this the annex?", 100) };
+        storage.save_message(&asked).unwrap();
+        assert!(storage.find_user("guest-id").unwrap().unwrap().guest);
+
+        user(storage, "kkapoor");
+        assert!(matches!(storage.upgrade_guest("guest-id", "KKapoor"), Err(DbServiceError::Conflict(_))));
+        assert!(storage.find_user("guest-id").unwrap().unwrap().guest);
+        assert_eq!("guest-4f2a", storage.room_messages("annex").unwrap()[0].from);
+
+        let upgraded = storage.upgrade_guest("guest-id", "rhoward").unwrap();
+        assert!(!upgraded.guest);
+        assert_eq!("rhoward", upgraded.user_name);
+        assert_eq!(rooms(&["annex"]), upgraded.favorite_rooms);
+        assert_eq!("rhoward", storage.room_messages("annex").unwrap()[0].from);
+        assert!(!storage.find_user("guest-id").unwrap().unwrap().guest);
+        assert!(matches!(storage.upgrade_guest("nobody", "nobody"), Err(DbServiceError::NotFound(_))));
+
+        let mut guest = User::new(String::from("guest-9c1d"));
+        guest.set_user_id(String::from("other-guest-id"));
+        guest.guest = true;
+        storage.create_user(guest).unwrap();
+        storage.delete_user("other-guest-id", None).unwrap();
+        assert!(storage.find_user("other-guest-id").unwrap().is_none());
+    }
+
+    pub fn guests_are_idle_until_seen(storage: &dyn Storage) {
+        let created_by = Utc::now().timestamp() + 1;
+        let mut guest = User::new(String::from("guest-4f2a"));
+        guest.set_user_id(String::from("guest-id"));
+        guest.guest = true;
+        storage.create_user(guest).unwrap();
+        user(storage, "rhoward");
+        assert_eq!(vec![String::from("guest-id")], storage.idle_guests(created_by).unwrap());
+
+        storage.touch_guest("guest-id", created_by + 100).unwrap();
+        storage.touch_guest("rhoward-id", created_by + 100).unwrap();
+        assert!(storage.idle_guests(created_by + 100).unwrap().is_empty());
+        storage.touch_guest("guest-id", created_by).unwrap();
+        assert_eq!(vec![String::from("guest-id")], storage.idle_guests(created_by + 101).unwrap());
+
+        storage.upgrade_guest("guest-id", "kkapoor").unwrap();
+        assert!(storage.idle_guests(created_by + 101).unwrap().is_empty());
+    }
+
+    pub fn unknown_users_are_not_found(storage: &dyn Storage) {
+        assert!(matches!(storage.add_favorites("nobody", &rooms(&["sales"])), Err(DbServiceError::NotFound(_))));
+        assert!(matches!(storage.remove_favorite("nobody", "sales"), Err(DbServiceError::NotFound(_))));
+        assert!(matches!(storage.rename_user("nobody", "nobody"), Err(DbServiceError::NotFound(_))));
+        assert!(matches!(storage.update_read_marker("nobody", "sales", 1), Err(DbServiceError::NotFound(_))));
+        assert!(matches!(storage.update_profile("nobody", &Profile::default()), Err(DbServiceError::NotFound(_))));
+        assert!(matches!(storage.delete_user("nobody", None), Err(DbServiceError::NotFound(_))));
+    }
+
+    pub fn read_markers_only_move_forward(storage: &dyn Storage) {
+        user(storage, "jhalpert");
+        storage.update_read_marker("jhalpert-id", "sales", 9).unwrap();
+        let found = storage.update_read_marker("jhalpert-id", "sales", 2).unwrap();
+        assert_eq!(9, found.read_marker("sales"));
+    }
+
+    pub fn deleted_user_takes_their_rows(storage: &dyn Storage) {
+        user(storage, "jhalpert");
+        storage.add_favorites("jhalpert-id", &rooms(&["sales"])).unwrap();
+        storage.update_read_marker("jhalpert-id", "sales", 3).unwrap();
+        storage.update_profile("jhalpert-id", &profile()).unwrap();
+        user(storage, "mscott");
+        storage.block_user("jhalpert-id", "mscott-id").unwrap();
+        storage.block_user("mscott-id", "jhalpert-id").unwrap();
+        storage.save_attachment(&Attachment {
+            id: profile().avatar.unwrap(),
+            ..attachment("", "jhalpert-id")
+        }).unwrap();
+        storage.delete_user("jhalpert-id", None).unwrap();
+        assert!(storage.find_attachment(&profile().avatar.unwrap()).unwrap().is_none());
+        assert!(storage.find_user("mscott-id").unwrap().unwrap().blocked_users.is_empty());
+        assert!(storage.find_user("jhalpert-id").unwrap().is_none());
+
+        user(storage, "jhalpert");
+        let found = storage.find_user("jhalpert-id").unwrap().unwrap();
+        assert_eq!(0, found.favorites().count());
+        assert_eq!(0, found.read_marker("sales"));
+        assert_eq!(Profile::default(), found.profile);
+        assert!(storage.blocked_by("jhalpert-id").unwrap().is_empty());
+    }
+
+    pub fn blocks_are_kept_both_ways(storage: &dyn Storage) {
+        user(storage, "jhalpert");
+        user(storage, "dschrute");
+        user(storage, "abernard");
+        storage.block_user("jhalpert-id", "dschrute-id").unwrap();
+        storage.block_user("jhalpert-id", "dschrute-id").unwrap();
+        let found = storage.block_user("abernard-id", "dschrute-id").unwrap();
+        assert_eq!(vec![String::from("dschrute-id")], found.blocked_users);
+        assert_eq!(vec![String::from("jhalpert-id"), String::from("abernard-id")], storage.blocked_by("dschrute-id").unwrap());
+        assert!(matches!(storage.block_user("jhalpert-id", "nobody"), Err(DbServiceError::NotFound(_))));
+
+        let found = storage.unblock_user("jhalpert-id", "dschrute-id").unwrap();
+        assert!(found.blocked_users.is_empty());
+        assert_eq!(vec![String::from("abernard-id")], storage.blocked_by("dschrute-id").unwrap());
+    }
+
+    pub fn user_content_is_found_and_can_be_anonymized(storage: &dyn Storage) {
+        user(storage, "jhalpert");
+        storage.save_message(&message("sales", 1, "jhalpert", "Bears, beets", 100)).unwrap();
+        storage.save_message(&message("sales", 2, "dschrute", "Identity theft is not a joke", 200)).unwrap();
+        storage.save_message(&message("annex", 1, "jhalpert", "Battlestar Galactica", 300)).unwrap();
+        let upload = attachment("sales", "jhalpert-id");
+        storage.save_attachment(&upload).unwrap();
+
+        storage.rename_user("jhalpert-id", "bigtuna").unwrap();
+        let sent: Vec<u64> = storage.user_messages("jhalpert-id").unwrap().iter().map(|m| m.sent_at as u64).collect();
+        assert_eq!(vec![100, 300], sent);
+        assert_eq!(vec![upload.clone()], storage.user_attachments("jhalpert-id").unwrap());
+
+        storage.delete_user("jhalpert-id", Some("Deleted user")).unwrap();
+        assert!(storage.user_messages("jhalpert-id").unwrap().is_empty());
+        let sales = storage.room_messages("sales").unwrap();
+        assert_eq!(("Deleted user", None), (sales[0].from.as_str(), sales[0].sender_id.as_deref()));
+        assert_eq!("dschrute", sales[1].from);
+        assert_eq!("", storage.find_attachment(&upload.id).unwrap().unwrap().uploader_id);
+
+        //Left under their name, but whoever registers it next doesn't get them.
+        user(storage, "dschrute");
+        storage.delete_user("dschrute-id", None).unwrap();
+        let mut impostor = User::new(String::from("dschrute"));
+        impostor.set_user_id(String::from("impostor-id"));
+        storage.create_user(impostor).unwrap();
+        assert!(storage.user_messages("impostor-id").unwrap().is_empty());
+        let sales = storage.room_messages("sales").unwrap();
+        assert_eq!(("dschrute", None), (sales[1].from.as_str(), sales[1].sender_id.as_deref()));
+    }
+
+    pub fn profiles_round_trip(storage: &dyn Storage) {
+        user(storage, "jhalpert");
+        assert_eq!(Profile::default(), storage.find_user("jhalpert-id").unwrap().unwrap().profile);
+
+        let updated = storage.update_profile("jhalpert-id", &profile()).unwrap();
+        assert_eq!(profile(), updated.profile);
+        assert_eq!(profile(), storage.find_user("jhalpert-id").unwrap().unwrap().profile);
+
+        let cleared = Profile { bio: None, ..profile() };
+        storage.update_profile("jhalpert-id", &cleared).unwrap();
+        assert_eq!(cleared, storage.find_user("jhalpert-id").unwrap().unwrap().profile);
+    }
+
+    pub fn replaced_avatars_lose_their_attachment(storage: &dyn Storage) {
+        user(storage, "jhalpert");
+        let first = attachment("", "jhalpert-id");
+        let second = attachment("", "jhalpert-id");
+        storage.save_attachment(&first).unwrap();
+        storage.save_attachment(&second).unwrap();
+        let with_avatar = |id: &str| Profile { avatar: Some(String::from(id)), ..profile() };
+
+        storage.update_profile("jhalpert-id", &with_avatar(&first.id)).unwrap();
+        storage.update_profile("jhalpert-id", &with_avatar(&first.id)).unwrap();
+        assert!(storage.find_attachment(&first.id).unwrap().is_some());
+        storage.update_profile("jhalpert-id", &with_avatar(&second.id)).unwrap();
+        assert!(storage.find_attachment(&first.id).unwrap().is_none());
+        assert!(storage.find_attachment(&second.id).unwrap().is_some());
+
+        storage.update_profile("jhalpert-id", &Profile { avatar: None, ..profile() }).unwrap();
+        assert!(storage.find_attachment(&second.id).unwrap().is_none());
+    }
+
+    pub fn rooms_round_trip(storage: &dyn Storage) {
+        let room = RoomRecord {
+            room_id: String::from("room-1"),
+            name: String::from("sales"),
+            owner_id: String::from("mscott-id"),
+            last_message_id: 0,
+            topic: None,
+            description: None,
+            slug: String::from("sales"),
+            co_owners: vec![],
+            grace: None,
+            expires_at: None,
+            capacity: None
+        };
+        storage.save_room(&room).unwrap();
+        storage.save_message(&message("room-1", 1, "mscott", "hello", 100)).unwrap();
+        storage.save_message(&message("room-1", 2, "mscott", "again", 200)).unwrap();
+        assert_eq!(vec![RoomRecord { last_message_id: 2, ..room.clone() }], storage.rooms().unwrap());
+
+        let renamed = RoomRecord {
+            name: String::from("Sales"),
+            slug: String::from("sales-2"),
+            topic: Some(String::from("Quarterly numbers")),
+            description: Some(String::from("Scranton's sales team.")),
+            grace: Some(300),
+            expires_at: Some(1_600_000_000),
+            capacity: Some(25),
+            last_message_id: 2,
+            ..room
+        };
+        storage.save_room(&renamed).unwrap();
+        assert_eq!(vec![renamed], storage.rooms().unwrap());
+
+        storage.delete_room("room-1").unwrap();
+        assert!(storage.rooms().unwrap().is_empty());
+    }
+
+    pub fn ownership_changes_are_kept_with_the_room(storage: &dyn Storage) {
+        let room = RoomRecord {
+            room_id: String::from("room-1"),
+            name: String::from("sales"),
+            owner_id: String::from("jhalpert-id"),
+            last_message_id: 0,
+            topic: None,
+            description: None,
+            slug: String::from("sales"),
+            co_owners: vec![String::from("mscott-id"), String::from("dschrute-id")],
+            grace: None,
+            expires_at: None,
+            capacity: None
+        };
+        let change = OwnershipChange {
+            room_id: String::from("room-1"),
+            action: OwnershipAction::Transferred,
+            user_id: String::from("jhalpert-id"),
+            changed_by: String::from("mscott-id"),
+            changed_at: 100
+        };
+        storage.change_owners(&room, &change).unwrap();
+        let added = OwnershipChange { action: OwnershipAction::CoOwnerAdded, user_id: String::from("dschrute-id"), ..change.clone() };
+        storage.change_owners(&room, &added).unwrap();
+
+        assert_eq!(vec![room], storage.rooms().unwrap());
+        assert_eq!(vec![change, added], storage.ownership_changes("room-1").unwrap());
+        assert!(storage.ownership_changes("room-2").unwrap().is_empty());
+    }
+
+    pub fn messages_keep_raw_and_rendered_text(storage: &dyn Storage) {
+        let mut msg = message("sales", 1, "mscott", "**World's best boss** @dschrute", 100);
+        msg.rendered = String::from("<strong>World&#39;s best boss</strong> @dschrute");
+        msg.mentions = vec![String::from("dschrute")];
+        msg.attachments = vec![String::from("mug.png")];
+        storage.save_message(&msg).unwrap();
+
+        let saved = storage.room_messages("sales").unwrap();
+        assert_eq!(1, saved.len());
+        assert_eq!(msg.msg, saved[0].msg);
+        assert_eq!(msg.rendered, saved[0].rendered);
+        assert_eq!(msg.mentions, saved[0].mentions);
+        assert_eq!(msg.attachments, saved[0].attachments);
+        assert_eq!(100, saved[0].sent_at);
+    }
+
+    pub fn attachments_round_trip(storage: &dyn Storage) {
+        let attachment = Attachment::new(String::from("sales"), String::from("mscott-id"),
+                                         String::from("mug.png"), String::from("image/png"), 42);
+        storage.save_attachment(&attachment).unwrap();
+        assert_eq!(Some(attachment.clone()), storage.find_attachment(&attachment.id).unwrap());
+        assert_eq!(None, storage.find_attachment("missing").unwrap());
+    }
+
+    pub fn search_is_scoped_and_filtered(storage: &dyn Storage) {
+        storage.save_message(&message("sales", 1, "jhalpert", "Pranking Dwight with jello", 100)).unwrap();
+        storage.save_message(&message("sales", 2, "dschrute", "Who put my stapler in jello?", 200)).unwrap();
+        storage.save_message(&message("accounting", 1, "kmalone", "Famous chili day tomorrow", 300)).unwrap();
+        storage.save_message(&message("annex", 1, "kkapoor", "jello shots at the party", 400)).unwrap();
+
+        assert_eq!(2, storage.search(&query("jello", vec!["sales"])).unwrap().len());
+        assert!(storage.search(&query("jello", vec!["sales", "accounting"])).unwrap().iter().all(|h| h.room_id == "sales"));
+        assert!(storage.search(&query("jello", vec![])).unwrap().is_empty());
+
+        let mut by_sender = query("jello", vec!["sales", "annex"]);
+        by_sender.from = Some(String::from("dschrute"));
+        let hits = storage.search(&by_sender).unwrap();
+        assert_eq!(1, hits.len());
+        assert_eq!(2, hits[0].message_id);
+
+        let mut by_date = query("jello", vec!["sales", "annex"]);
+        by_date.after = Some(150);
+        by_date.before = Some(350);
+        let hits = storage.search(&by_date).unwrap();
+        assert_eq!(1, hits.len());
+        assert_eq!("dschrute", hits[0].from);
+
+        assert!(storage.search(&query("chili\" OR \"jello", vec!["accounting"])).unwrap().is_empty());
+    }
+
+    pub fn snippets_highlight_matches_and_escape_markup(storage: &dyn Storage) {
+        storage.save_message(&message("sales", 1, "rhoward", "<b>beets</b> & bears", 100)).unwrap();
+        let hits = storage.search(&query("beets", vec!["sales"])).unwrap();
+        assert_eq!("&lt;b&gt;<mark>beets</mark>&lt;/b&gt; &amp; bears", hits[0].snippet);
+    }
+}
+
+//Runs the shared suite against a backend, $storage builds a fresh, empty one per test.
+//It can be anything that gives a &dyn Storage, so a backend can clean up once the test is done.
+#[cfg(test)]
+#[macro_export]
+macro_rules! storage_tests {
+    ($storage:expr) => {
+        $crate::storage_tests!($storage; users_round_trip, user_names_are_unique_ignoring_case, update_is_all_or_nothing,
+            favorites_keep_their_order, removed_room_leaves_every_favorites_list, unknown_users_are_not_found,
+            read_markers_only_move_forward, deleted_user_takes_their_rows, profiles_round_trip,
+            replaced_avatars_lose_their_attachment,
+            blocks_are_kept_both_ways, user_content_is_found_and_can_be_anonymized, guests_upgrade_keeping_their_history,
+            guests_are_idle_until_seen, rooms_round_trip, ownership_changes_are_kept_with_the_room,
+            messages_keep_raw_and_rendered_text, attachments_round_trip, search_is_scoped_and_filtered,
+            snippets_highlight_matches_and_escape_markup);
+    };
+    ($storage:expr; $($name:ident),+) => {
+        $(
+            #[test]
+            fn $name() {
+                let storage = $storage;
+                $crate::storage::suite::$name(storage.as_ref());
+            }
+        )+
+    };
+}
@@ -0,0 +1,574 @@
+use r2d2_postgres::PostgresConnectionManager;
+use r2d2_postgres::postgres::{Config, NoTls, Row, Transaction, IsolationLevel};
+use r2d2_postgres::postgres::error::SqlState;
+use r2d2_postgres::postgres::types::ToSql;
+use r2d2_postgres::r2d2::{self, Pool};
+use chrono::{TimeZone, Utc};
+use crate::storage::{Storage, RoomRecord, user_not_found, highlight, split_list, MATCH_START, MATCH_END, SEARCH_LIMIT};
+use crate::user::{IUser, User, Profile};
+use crate::user::user_db_service::DbServiceError;
+use crate::chat::message_store::{StoredMessage, SearchQuery};
+use crate::chat::attachments::Attachment;
+use crate::chat::chat_data::{SearchHit, OwnershipChange, OwnershipAction};
+
+const POOL_SIZE: u32 = 8;
+//Held while migrating so servers starting together don't race to create the tables.
+const MIGRATION_LOCK: i64 = 0x6372_6162;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS users(id BIGSERIAL PRIMARY KEY, user_id TEXT UNIQUE, user_name TEXT);
+    CREATE UNIQUE INDEX IF NOT EXISTS users_user_name ON users(lower(user_name));
+    CREATE TABLE IF NOT EXISTS favorites(id BIGSERIAL PRIMARY KEY, user_id TEXT REFERENCES users (user_id), room_id TEXT, position BIGINT, UNIQUE(user_id, room_id));
+    CREATE TABLE IF NOT EXISTS read_markers(id BIGSERIAL PRIMARY KEY, user_id TEXT REFERENCES users (user_id), room_id TEXT, last_read BIGINT, UNIQUE(user_id, room_id));
+    CREATE TABLE IF NOT EXISTS profiles(user_id TEXT PRIMARY KEY REFERENCES users (user_id), display_name TEXT, avatar TEXT, status TEXT, bio TEXT, timezone TEXT);
+    CREATE TABLE IF NOT EXISTS blocks(id BIGSERIAL PRIMARY KEY, user_id TEXT REFERENCES users (user_id), blocked_id TEXT REFERENCES users (user_id), UNIQUE(user_id, blocked_id));
+    CREATE INDEX IF NOT EXISTS blocks_blocked_id ON blocks(blocked_id);
+    CREATE TABLE IF NOT EXISTS guests(user_id TEXT PRIMARY KEY REFERENCES users (user_id));
+    ALTER TABLE guests ADD COLUMN IF NOT EXISTS last_seen BIGINT DEFAULT floor(extract(epoch FROM now()))::BIGINT;
+    CREATE TABLE IF NOT EXISTS rooms(id BIGSERIAL PRIMARY KEY, room_id TEXT UNIQUE, name TEXT, owner_id TEXT);
+    ALTER TABLE rooms ADD COLUMN IF NOT EXISTS topic TEXT, ADD COLUMN IF NOT EXISTS description TEXT,
+        ADD COLUMN IF NOT EXISTS slug TEXT, ADD COLUMN IF NOT EXISTS co_owners TEXT,
+        ADD COLUMN IF NOT EXISTS grace BIGINT, ADD COLUMN IF NOT EXISTS expires_at BIGINT,
+        ADD COLUMN IF NOT EXISTS capacity BIGINT;
+    CREATE TABLE IF NOT EXISTS ownership_changes(id BIGSERIAL PRIMARY KEY, room_id TEXT, action TEXT, user_id TEXT, changed_by TEXT, changed_at BIGINT);
+    CREATE INDEX IF NOT EXISTS ownership_changes_room_id ON ownership_changes(room_id);
+    CREATE TABLE IF NOT EXISTS messages(id BIGSERIAL PRIMARY KEY, room_id TEXT, message_id BIGINT, sender TEXT, body TEXT, rendered TEXT, mentions TEXT, attachments TEXT, sent_at BIGINT,
+        search TSVECTOR GENERATED ALWAYS AS (to_tsvector('simple', body)) STORED);
+    ALTER TABLE messages ADD COLUMN IF NOT EXISTS sender_id TEXT;
+    CREATE INDEX IF NOT EXISTS messages_sender_id ON messages(sender_id);
+    CREATE INDEX IF NOT EXISTS messages_room ON messages(room_id, message_id);
+    CREATE INDEX IF NOT EXISTS messages_search ON messages USING GIN (search);
+    CREATE TABLE IF NOT EXISTS attachments(id TEXT PRIMARY KEY, room_id TEXT, uploader_id TEXT, file_name TEXT, content_type TEXT, size BIGINT);
+";
+
+//A shared database for deployments running more than one server.
+pub struct PostgresStorage {
+    pool: Pool<PostgresConnectionManager<NoTls>>
+}
+
+impl PostgresStorage {
+    pub fn connect(url: &str) -> Result<Self, DbServiceError> {
+        let config: Config = url.parse()?;
+        PostgresStorage::from_config(config)
+    }
+
+    pub fn from_config(config: Config) -> Result<Self, DbServiceError> {
+        let pool = Pool::builder()
+            .max_size(POOL_SIZE)
+            .min_idle(Some(1))
+            .build(PostgresConnectionManager::new(config, NoTls))?;
+        let storage = PostgresStorage {
+            pool
+        };
+        storage.write(|tx| {
+            tx.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK])?;
+            Ok(tx.batch_execute(SCHEMA)?)
+        })?;
+        Ok(storage)
+    }
+
+    //Reads see one snapshot, so a user and their favorites always match.
+    fn read<T, F>(&self, f: F) -> Result<T, DbServiceError> where F: FnOnce(&mut Transaction) -> Result<T, DbServiceError> {
+        let mut client = self.pool.get()?;
+        let mut tx = client.build_transaction().isolation_level(IsolationLevel::RepeatableRead).read_only(true).start()?;
+        let result = f(&mut tx)?;
+        tx.commit()?;
+        Ok(result)
+    }
+
+    fn write<T, F>(&self, f: F) -> Result<T, DbServiceError> where F: FnOnce(&mut Transaction) -> Result<T, DbServiceError> {
+        let mut client = self.pool.get()?;
+        let mut tx = client.transaction()?;
+        let result = f(&mut tx)?;
+        tx.commit()?;
+        Ok(result)
+    }
+
+    fn upsert_room(tx: &mut Transaction, room: &RoomRecord) -> Result<(), DbServiceError> {
+        tx.execute("\
+            INSERT INTO rooms (room_id, name, owner_id, topic, description, slug, co_owners, grace, expires_at, capacity) \
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) \
+            ON CONFLICT (room_id) DO UPDATE SET name=excluded.name, owner_id=excluded.owner_id, \
+            topic=excluded.topic, description=excluded.description, slug=excluded.slug, co_owners=excluded.co_owners, \
+            grace=excluded.grace, expires_at=excluded.expires_at, capacity=excluded.capacity",
+                   &[&room.room_id, &room.name, &room.owner_id, &room.topic, &room.description, &room.slug, &room.co_owners.join(","),
+                       &room.grace, &room.expires_at, &room.capacity])?;
+        Ok(())
+    }
+
+    //Locks the user's row, so writes for one user queue up instead of interleaving.
+    fn lock_user(tx: &mut Transaction, user_id: &str) -> Result<(), DbServiceError> {
+        match tx.query_opt("SELECT 1 FROM users WHERE user_id=$1 FOR UPDATE", &[&user_id])? {
+            Some(_) => Ok(()),
+            None => Err(user_not_found())
+        }
+    }
+
+    fn load_user(tx: &mut Transaction, user_id: &str) -> Result<Option<User>, DbServiceError> {
+        let row = match tx.query_opt("SELECT users.user_id, user_name, guests.user_id IS NOT NULL FROM users \
+            LEFT JOIN guests ON guests.user_id=users.user_id WHERE users.user_id=$1", &[&user_id])? {
+            Some(row) => row,
+            None => return Ok(None)
+        };
+        let mut user = User::new(row.get(1));
+        user.set_user_id(row.get(0));
+        user.guest = row.get(2);
+        let favorites = tx.query("SELECT room_id FROM favorites WHERE user_id=$1 ORDER BY position", &[&user_id])?;
+        user.add_favorites(favorites.iter().map(|r| r.get(0)).collect());
+        for marker in tx.query("SELECT room_id, last_read FROM read_markers WHERE user_id=$1", &[&user_id])? {
+            let last_read: i64 = marker.get(1);
+            user.set_read_marker(marker.get(0), last_read as u64);
+        }
+        let blocked = tx.query("SELECT blocked_id FROM blocks WHERE user_id=$1 ORDER BY id", &[&user_id])?;
+        user.blocked_users = blocked.iter().map(|r| r.get(0)).collect();
+        if let Some(p) = tx.query_opt("SELECT display_name, avatar, status, bio, timezone FROM profiles WHERE user_id=$1", &[&user_id])? {
+            user.profile = Profile {
+                display_name: p.get(0),
+                avatar: p.get(1),
+                status: p.get(2),
+                bio: p.get(3),
+                timezone: p.get(4)
+            };
+        }
+        Ok(Some(user))
+    }
+
+    fn message(r: &Row) -> StoredMessage {
+        let message_id: i64 = r.get(1);
+        let mentions: String = r.get(5);
+        let attachments: String = r.get(6);
+        StoredMessage {
+            room_id: r.get(0),
+            message_id: message_id as u64,
+            from: r.get(2),
+            sender_id: r.get(8),
+            msg: r.get(3),
+            rendered: r.get(4),
+            mentions: split_list(&mentions),
+            attachments: split_list(&attachments),
+            sent_at: r.get(7)
+        }
+    }
+
+    fn attachment(r: &Row) -> Attachment {
+        let size: i64 = r.get(5);
+        Attachment {
+            id: r.get(0),
+            room_id: r.get(1),
+            uploader_id: r.get(2),
+            file_name: r.get(3),
+            content_type: r.get(4),
+            size: size as u64
+        }
+    }
+
+    fn existing_user(tx: &mut Transaction, user_id: &str) -> Result<User, DbServiceError> {
+        PostgresStorage::load_user(tx, user_id)?.ok_or_else(user_not_found)
+    }
+
+    //New favorites go to the end of the list, ones already saved keep their place.
+    fn append_favorites(tx: &mut Transaction, user_id: &str, room_ids: &[String]) -> Result<(), DbServiceError> {
+        let append = tx.prepare("\
+            INSERT INTO favorites (user_id, room_id, position) \
+            SELECT $1, $2, COALESCE(MAX(position) + 1, 0) FROM favorites WHERE user_id=$1 \
+            ON CONFLICT (user_id, room_id) DO NOTHING")?;
+        for room_id in room_ids {
+            tx.execute(&append, &[&user_id, room_id])?;
+        }
+        Ok(())
+    }
+
+    fn rename(tx: &mut Transaction, user_id: &str, user_name: &str) -> Result<(), DbServiceError> {
+        if tx.execute("UPDATE users SET user_name=$1 WHERE user_id=$2", &[&user_name, &user_id])? == 0 {
+            return Err(DbServiceError::NotFound(String::from("Record doesn't exist.")));
+        }
+        Ok(())
+    }
+}
+
+impl Storage for PostgresStorage {
+    fn create_user(&self, user: User) -> Result<User, DbServiceError> {
+        self.write(|tx| {
+            tx.execute("INSERT INTO users (user_id, user_name) VALUES ($1, $2)", &[&user.user_id, &user.user_name])?;
+            if user.guest {
+                tx.execute("INSERT INTO guests (user_id) VALUES ($1)", &[&user.user_id])?;
+            }
+            Ok(user)
+        })
+    }
+
+    fn find_user(&self, user_id: &str) -> Result<Option<User>, DbServiceError> {
+        self.read(|tx| PostgresStorage::load_user(tx, user_id))
+    }
+
+    fn find_user_by_name(&self, user_name: &str) -> Result<Option<User>, DbServiceError> {
+        let mut client = self.pool.get()?;
+        let row = client.query_opt("SELECT user_id, user_name FROM users WHERE lower(user_name) = lower($1)", &[&user_name])?;
+        Ok(row.map(|row| {
+            let mut user = User::new(row.get(1));
+            user.set_user_id(row.get(0));
+            user
+        }))
+    }
+
+    fn update_user(&self, user: &User) -> Result<(), DbServiceError> {
+        let user_id = user.user_id.clone().ok_or_else(user_not_found)?;
+        self.write(|tx| {
+            PostgresStorage::rename(tx, &user_id, &user.user_name)?;
+            PostgresStorage::append_favorites(tx, &user_id, &user.favorite_rooms)
+        })
+    }
+
+    fn rename_user(&self, user_id: &str, user_name: &str) -> Result<User, DbServiceError> {
+        self.write(|tx| {
+            PostgresStorage::lock_user(tx, user_id)?;
+            PostgresStorage::rename(tx, user_id, user_name)?;
+            PostgresStorage::existing_user(tx, user_id)
+        })
+    }
+
+    fn upgrade_guest(&self, user_id: &str, user_name: &str) -> Result<User, DbServiceError> {
+        self.write(|tx| {
+            PostgresStorage::lock_user(tx, user_id)?;
+            tx.execute("UPDATE messages SET sender=$2 WHERE sender_id=$1", &[&user_id, &user_name])?;
+            PostgresStorage::rename(tx, user_id, user_name)?;
+            tx.execute("DELETE FROM guests WHERE user_id=$1", &[&user_id])?;
+            PostgresStorage::existing_user(tx, user_id)
+        })
+    }
+
+    fn touch_guest(&self, user_id: &str, seen_at: i64) -> Result<(), DbServiceError> {
+        self.write(|tx| {
+            tx.execute("UPDATE guests SET last_seen=$2 WHERE user_id=$1 AND last_seen<$2", &[&user_id, &seen_at])?;
+            Ok(())
+        })
+    }
+
+    fn idle_guests(&self, seen_before: i64) -> Result<Vec<String>, DbServiceError> {
+        self.read(|tx| {
+            let rows = tx.query("SELECT user_id FROM guests WHERE last_seen<$1 ORDER BY last_seen", &[&seen_before])?;
+            Ok(rows.iter().map(|r| r.get(0)).collect())
+        })
+    }
+
+    fn delete_user(&self, user_id: &str, anonymize_as: Option<&str>) -> Result<(), DbServiceError> {
+        self.write(|tx| {
+            PostgresStorage::lock_user(tx, user_id)?;
+            if let Some(name) = anonymize_as {
+                tx.execute("UPDATE messages SET sender=$2 WHERE sender_id=$1", &[&user_id, &name])?;
+                tx.execute("UPDATE attachments SET uploader_id='' WHERE uploader_id=$1", &[&user_id])?;
+            }
+            tx.execute("UPDATE messages SET sender_id=NULL WHERE sender_id=$1", &[&user_id])?;
+            //Rows referencing the user go first or the foreign keys block the delete.
+            tx.execute("DELETE FROM attachments WHERE id=(SELECT avatar FROM profiles WHERE user_id=$1)", &[&user_id])?;
+            tx.execute("DELETE FROM favorites WHERE user_id=$1", &[&user_id])?;
+            tx.execute("DELETE FROM read_markers WHERE user_id=$1", &[&user_id])?;
+            tx.execute("DELETE FROM profiles WHERE user_id=$1", &[&user_id])?;
+            tx.execute("DELETE FROM blocks WHERE user_id=$1 OR blocked_id=$1", &[&user_id])?;
+            tx.execute("DELETE FROM guests WHERE user_id=$1", &[&user_id])?;
+            tx.execute("DELETE FROM users WHERE user_id=$1", &[&user_id])?;
+            Ok(())
+        })
+    }
+
+    fn add_favorites(&self, user_id: &str, room_ids: &[String]) -> Result<User, DbServiceError> {
+        self.write(|tx| {
+            PostgresStorage::lock_user(tx, user_id)?;
+            PostgresStorage::append_favorites(tx, user_id, room_ids)?;
+            PostgresStorage::existing_user(tx, user_id)
+        })
+    }
+
+    fn replace_favorites(&self, user_id: &str, room_ids: &[String]) -> Result<User, DbServiceError> {
+        self.write(|tx| {
+            PostgresStorage::lock_user(tx, user_id)?;
+            tx.execute("DELETE FROM favorites WHERE user_id=$1", &[&user_id])?;
+            let insert = tx.prepare("INSERT INTO favorites (user_id, room_id, position) VALUES ($1, $2, $3)")?;
+            for (position, room_id) in room_ids.iter().enumerate() {
+                tx.execute(&insert, &[&user_id, room_id, &(position as i64)])?;
+            }
+            PostgresStorage::existing_user(tx, user_id)
+        })
+    }
+
+    fn remove_favorite(&self, user_id: &str, room_id: &str) -> Result<User, DbServiceError> {
+        self.write(|tx| {
+            PostgresStorage::lock_user(tx, user_id)?;
+            tx.execute("DELETE FROM favorites WHERE user_id=$1 AND room_id=$2", &[&user_id, &room_id])?;
+            PostgresStorage::existing_user(tx, user_id)
+        })
+    }
+
+    fn remove_room_from_favorites(&self, room_id: &str) -> Result<(), DbServiceError> {
+        self.write(|tx| {
+            tx.execute("DELETE FROM favorites WHERE room_id=$1", &[&room_id])?;
+            Ok(())
+        })
+    }
+
+    fn update_read_marker(&self, user_id: &str, room_id: &str, last_read: u64) -> Result<User, DbServiceError> {
+        self.write(|tx| {
+            PostgresStorage::lock_user(tx, user_id)?;
+            tx.execute("\
+                INSERT INTO read_markers (user_id, room_id, last_read) VALUES ($1, $2, $3) \
+                ON CONFLICT (user_id, room_id) DO UPDATE SET last_read=excluded.last_read \
+                WHERE excluded.last_read > read_markers.last_read", &[&user_id, &room_id, &(last_read as i64)])?;
+            PostgresStorage::existing_user(tx, user_id)
+        })
+    }
+
+    fn update_profile(&self, user_id: &str, profile: &Profile) -> Result<User, DbServiceError> {
+        self.write(|tx| {
+            PostgresStorage::lock_user(tx, user_id)?;
+            tx.execute("DELETE FROM attachments WHERE id=(SELECT avatar FROM profiles WHERE user_id=$1) AND id IS DISTINCT FROM $2",
+                       &[&user_id, &profile.avatar])?;
+            tx.execute("\
+                INSERT INTO profiles (user_id, display_name, avatar, status, bio, timezone) VALUES ($1, $2, $3, $4, $5, $6) \
+                ON CONFLICT (user_id) DO UPDATE SET display_name=excluded.display_name, avatar=excluded.avatar, \
+                status=excluded.status, bio=excluded.bio, timezone=excluded.timezone",
+                &[&user_id, &profile.display_name, &profile.avatar, &profile.status, &profile.bio, &profile.timezone])?;
+            PostgresStorage::existing_user(tx, user_id)
+        })
+    }
+
+    fn block_user(&self, user_id: &str, blocked_id: &str) -> Result<User, DbServiceError> {
+        self.write(|tx| {
+            PostgresStorage::lock_user(tx, user_id)?;
+            PostgresStorage::lock_user(tx, blocked_id)?;
+            tx.execute("INSERT INTO blocks (user_id, blocked_id) VALUES ($1, $2) ON CONFLICT DO NOTHING", &[&user_id, &blocked_id])?;
+            PostgresStorage::existing_user(tx, user_id)
+        })
+    }
+
+    fn unblock_user(&self, user_id: &str, blocked_id: &str) -> Result<User, DbServiceError> {
+        self.write(|tx| {
+            PostgresStorage::lock_user(tx, user_id)?;
+            tx.execute("DELETE FROM blocks WHERE user_id=$1 AND blocked_id=$2", &[&user_id, &blocked_id])?;
+            PostgresStorage::existing_user(tx, user_id)
+        })
+    }
+
+    fn blocked_by(&self, user_id: &str) -> Result<Vec<String>, DbServiceError> {
+        self.read(|tx| {
+            let rows = tx.query("SELECT user_id FROM blocks WHERE blocked_id=$1 ORDER BY id", &[&user_id])?;
+            Ok(rows.iter().map(|r| r.get(0)).collect())
+        })
+    }
+
+    fn save_room(&self, room: &RoomRecord) -> Result<(), DbServiceError> {
+        self.write(|tx| PostgresStorage::upsert_room(tx, room))
+    }
+
+    fn delete_room(&self, room_id: &str) -> Result<(), DbServiceError> {
+        self.write(|tx| {
+            tx.execute("DELETE FROM rooms WHERE room_id=$1", &[&room_id])?;
+            Ok(())
+        })
+    }
+
+    fn rooms(&self) -> Result<Vec<RoomRecord>, DbServiceError> {
+        let mut client = self.pool.get()?;
+        let rows = client.query("\
+            SELECT r.room_id, r.name, r.owner_id, COALESCE(MAX(m.message_id), 0), r.topic, r.description, r.slug, r.co_owners, r.grace, r.expires_at, r.capacity \
+            FROM rooms r LEFT JOIN messages m ON m.room_id = r.room_id \
+            GROUP BY r.id ORDER BY r.id", &[])?;
+        Ok(rows.iter().map(|r| {
+            let last_message_id: i64 = r.get(3);
+            RoomRecord {
+                room_id: r.get(0),
+                name: r.get(1),
+                owner_id: r.get(2),
+                last_message_id: last_message_id as u64,
+                topic: r.get(4),
+                description: r.get(5),
+                slug: r.get::<_, Option<String>>(6).unwrap_or_default(),
+                co_owners: split_list(&r.get::<_, Option<String>>(7).unwrap_or_default()),
+                grace: r.get(8),
+                expires_at: r.get(9),
+                capacity: r.get(10)
+            }
+        }).collect())
+    }
+
+    fn change_owners(&self, room: &RoomRecord, change: &OwnershipChange) -> Result<(), DbServiceError> {
+        self.write(|tx| {
+            PostgresStorage::upsert_room(tx, room)?;
+            tx.execute("\
+                INSERT INTO ownership_changes (room_id, action, user_id, changed_by, changed_at) VALUES ($1, $2, $3, $4, $5)",
+                       &[&change.room_id, &change.action.as_str(), &change.user_id, &change.changed_by, &change.changed_at])?;
+            Ok(())
+        })
+    }
+
+    fn ownership_changes(&self, room_id: &str) -> Result<Vec<OwnershipChange>, DbServiceError> {
+        let mut client = self.pool.get()?;
+        let rows = client.query("\
+            SELECT room_id, action, user_id, changed_by, changed_at FROM ownership_changes \
+            WHERE room_id=$1 ORDER BY id", &[&room_id])?;
+        Ok(rows.iter().filter_map(|r| {
+            let action: String = r.get(1);
+            OwnershipAction::parse(&action).map(|action| OwnershipChange {
+                room_id: r.get(0),
+                action,
+                user_id: r.get(2),
+                changed_by: r.get(3),
+                changed_at: r.get(4)
+            })
+        }).collect())
+    }
+
+    fn save_message(&self, msg: &StoredMessage) -> Result<(), DbServiceError> {
+        self.write(|tx| {
+            tx.execute("\
+                INSERT INTO messages (room_id, message_id, sender, body, rendered, mentions, attachments, sent_at, sender_id) \
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+                       &[&msg.room_id, &(msg.message_id as i64), &msg.from, &msg.msg, &msg.rendered,
+                           &msg.mentions.join(","), &msg.attachments.join(","), &msg.sent_at, &msg.sender_id])?;
+            Ok(())
+        })
+    }
+
+    fn room_messages(&self, room_id: &str) -> Result<Vec<StoredMessage>, DbServiceError> {
+        let mut client = self.pool.get()?;
+        let rows = client.query("\
+            SELECT room_id, message_id, sender, body, rendered, mentions, attachments, sent_at, sender_id \
+            FROM messages WHERE room_id=$1 ORDER BY message_id", &[&room_id])?;
+        Ok(rows.iter().map(PostgresStorage::message).collect())
+    }
+
+    fn user_messages(&self, user_id: &str) -> Result<Vec<StoredMessage>, DbServiceError> {
+        let mut client = self.pool.get()?;
+        let rows = client.query("\
+            SELECT room_id, message_id, sender, body, rendered, mentions, attachments, sent_at, sender_id \
+            FROM messages WHERE sender_id=$1 ORDER BY sent_at, id", &[&user_id])?;
+        Ok(rows.iter().map(PostgresStorage::message).collect())
+    }
+
+    fn save_attachment(&self, attachment: &Attachment) -> Result<(), DbServiceError> {
+        self.write(|tx| {
+            tx.execute("\
+                INSERT INTO attachments (id, room_id, uploader_id, file_name, content_type, size) \
+                VALUES ($1, $2, $3, $4, $5, $6)",
+                       &[&attachment.id, &attachment.room_id, &attachment.uploader_id,
+                           &attachment.file_name, &attachment.content_type, &(attachment.size as i64)])?;
+            Ok(())
+        })
+    }
+
+    fn find_attachment(&self, id: &str) -> Result<Option<Attachment>, DbServiceError> {
+        let mut client = self.pool.get()?;
+        let row = client.query_opt(
+            "SELECT id, room_id, uploader_id, file_name, content_type, size FROM attachments WHERE id=$1", &[&id])?;
+        Ok(row.as_ref().map(PostgresStorage::attachment))
+    }
+
+    fn user_attachments(&self, user_id: &str) -> Result<Vec<Attachment>, DbServiceError> {
+        let mut client = self.pool.get()?;
+        let rows = client.query(
+            "SELECT id, room_id, uploader_id, file_name, content_type, size FROM attachments WHERE uploader_id=$1 ORDER BY id", &[&user_id])?;
+        Ok(rows.iter().map(PostgresStorage::attachment).collect())
+    }
+
+    //plainto_tsquery ignores operators, so the text is only ever a list of words. The body is
+    //escaped before ts_headline, which would otherwise drop anything that looks like a tag.
+    fn search(&self, query: &SearchQuery) -> Result<Vec<SearchHit>, DbServiceError> {
+        if query.text.trim().is_empty() || query.rooms.is_empty() {
+            return Ok(vec![]);
+        }
+        let headline_options = format!("StartSel={}, StopSel={}, MaxWords=16, MinWords=8", MATCH_START, MATCH_END);
+        let args: [&(dyn ToSql + Sync); 7] = [&query.text, &query.from, &query.after, &query.before,
+            &query.rooms, &SEARCH_LIMIT, &headline_options];
+        let mut client = self.pool.get()?;
+        let rows = client.query("\
+            SELECT m.room_id, m.message_id, m.sender, m.sent_at, \
+                   ts_headline('simple', replace(replace(replace(m.body, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'), q, $7) \
+            FROM messages m, plainto_tsquery('simple', $1) q \
+            WHERE m.search @@ q \
+              AND ($2::TEXT IS NULL OR m.sender = $2) \
+              AND ($3::BIGINT IS NULL OR m.sent_at >= $3) \
+              AND ($4::BIGINT IS NULL OR m.sent_at <= $4) \
+              AND m.room_id = ANY($5) \
+            ORDER BY ts_rank(m.search, q) DESC LIMIT $6", &args)?;
+        Ok(rows.iter().map(|r| {
+            let message_id: i64 = r.get(1);
+            let sent_at: i64 = r.get(3);
+            let snippet: String = r.get(4);
+            SearchHit {
+                room_id: r.get(0),
+                message_id: message_id as u64,
+                from: r.get(2),
+                sent_at: Utc.timestamp_opt(sent_at, 0).unwrap().to_rfc3339(),
+                snippet: highlight(&unescape(&snippet))
+            }
+        }).collect())
+    }
+}
+
+//Undoes the escaping done for ts_headline, &amp; goes last so "&amp;lt;" comes back as "&lt;".
+fn unescape(snippet: &str) -> String {
+    snippet.replace("&lt;", "<").replace("&gt;", ">").replace("&amp;", "&")
+}
+
+impl From<r2d2_postgres::postgres::Error> for DbServiceError {
+    fn from(e: r2d2_postgres::postgres::Error) -> Self {
+        match e.code() {
+            Some(code) if *code == SqlState::UNIQUE_VIOLATION || *code == SqlState::FOREIGN_KEY_VIOLATION =>
+                DbServiceError::Conflict(String::from("Record conflicts with an existing one.")),
+            _ => DbServiceError::Storage(e.to_string())
+        }
+    }
+}
+
+impl From<r2d2::Error> for DbServiceError {
+    fn from(e: r2d2::Error) -> Self {
+        DbServiceError::Storage(e.to_string())
+    }
+}
+
+//Set TEST_DATABASE_URL to run the shared suite, e.g. against a local container:
+//docker run -e POSTGRES_PASSWORD=crabby -p 5432:5432 postgres
+//TEST_DATABASE_URL="host=localhost user=postgres password=crabby" cargo test --features postgres-backend
+//Each test gets its own schema, so nothing leaks between tests.
+#[cfg(test)]
+mod tests {
+    use crate::storage::Storage;
+    use crate::storage::postgres::PostgresStorage;
+    use r2d2_postgres::postgres::{Client, Config, NoTls};
+    use uuid::Uuid;
+
+    //Each test gets a schema of its own, dropped again when the test is done, pass or fail.
+    struct TestSchema {
+        url: String,
+        schema: String,
+        storage: Option<PostgresStorage>
+    }
+
+    impl AsRef<dyn Storage> for TestSchema {
+        fn as_ref(&self) -> &(dyn Storage + 'static) {
+            self.storage.as_ref().unwrap()
+        }
+    }
+
+    impl Drop for TestSchema {
+        fn drop(&mut self) {
+            self.storage.take();
+            if let Ok(mut client) = Client::connect(&self.url, NoTls) {
+                let _ = client.batch_execute(&format!("DROP SCHEMA {} CASCADE", self.schema));
+            }
+        }
+    }
+
+    //The suite only runs against a real database, so with the feature on it has to be given one.
+    fn storage() -> TestSchema {
+        let url = std::env::var("TEST_DATABASE_URL")
+            .expect("Set TEST_DATABASE_URL to a PostgreSQL database to test the postgres-backend feature.");
+        let schema = format!("crabby_{}", Uuid::new_v4().to_simple());
+        Client::connect(&url, NoTls).unwrap().batch_execute(&format!("CREATE SCHEMA {}", schema)).unwrap();
+        let mut config: Config = url.parse().unwrap();
+        config.options(&format!("-c search_path={}", schema));
+        let storage = Some(PostgresStorage::from_config(config).unwrap());
+        TestSchema { url, schema, storage }
+    }
+
+    crate::storage_tests!(storage());
+}
@@ -0,0 +1,341 @@
+mod db_command;
+mod pool;
+
+use rusqlite::{Connection, Transaction, TransactionBehavior};
+use std::fs::File;
+use std::io::Read;
+use crate::storage::{Storage, RoomRecord, user_not_found};
+use crate::storage::sqlite::pool::ConnectionPool;
+use crate::storage::sqlite::db_command::DbCommand;
+use crate::storage::sqlite::db_command::create_user::CreateUser;
+use crate::storage::sqlite::db_command::get_user::GetUser;
+use crate::storage::sqlite::db_command::get_user_by_name::GetUserByName;
+use crate::storage::sqlite::db_command::update_user::UpdateUser;
+use crate::storage::sqlite::db_command::upgrade_guest::UpgradeGuest;
+use crate::storage::sqlite::db_command::touch_guest::TouchGuest;
+use crate::storage::sqlite::db_command::get_idle_guests::GetIdleGuests;
+use crate::storage::sqlite::db_command::delete_user::DeleteUser;
+use crate::storage::sqlite::db_command::get_favorites::GetFavorites;
+use crate::storage::sqlite::db_command::update_favorites::UpdateFavorites;
+use crate::storage::sqlite::db_command::replace_favorites::ReplaceFavorites;
+use crate::storage::sqlite::db_command::remove_favorite::RemoveFavorite;
+use crate::storage::sqlite::db_command::get_read_markers::GetReadMarkers;
+use crate::storage::sqlite::db_command::update_read_markers::UpdateReadMarkers;
+use crate::storage::sqlite::db_command::get_profile::GetProfile;
+use crate::storage::sqlite::db_command::update_profile::UpdateProfile;
+use crate::storage::sqlite::db_command::get_blocks::GetBlocks;
+use crate::storage::sqlite::db_command::block_user::BlockUser;
+use crate::storage::sqlite::db_command::unblock_user::UnblockUser;
+use crate::storage::sqlite::db_command::save_room::SaveRoom;
+use crate::storage::sqlite::db_command::delete_room::DeleteRoom;
+use crate::storage::sqlite::db_command::get_rooms::GetRooms;
+use crate::storage::sqlite::db_command::record_ownership_change::RecordOwnershipChange;
+use crate::storage::sqlite::db_command::get_ownership_changes::GetOwnershipChanges;
+use crate::storage::sqlite::db_command::save_message::SaveMessage;
+use crate::storage::sqlite::db_command::get_messages::GetMessages;
+use crate::storage::sqlite::db_command::save_attachment::SaveAttachment;
+use crate::storage::sqlite::db_command::get_attachment::GetAttachment;
+use crate::storage::sqlite::db_command::get_user_attachments::GetUserAttachments;
+use crate::storage::sqlite::db_command::search_messages::SearchMessages;
+use crate::user::{IUser, User, Profile};
+use crate::user::user_db_service::DbServiceError;
+use crate::user::user_db_service::DbServiceError::EmptyFile;
+use crate::chat::message_store::{StoredMessage, SearchQuery};
+use crate::chat::attachments::Attachment;
+use crate::chat::chat_data::{SearchHit, OwnershipChange};
+
+//IF NOT EXISTS lets a seed file create some of these tables itself.
+const SCHEMA: &str = "\
+    BEGIN;
+    CREATE TABLE IF NOT EXISTS users(id INTEGER PRIMARY KEY, user_id TEXT UNIQUE, user_name TEXT);
+    CREATE UNIQUE INDEX IF NOT EXISTS users_user_name ON users(user_name COLLATE NOCASE);
+    CREATE TABLE IF NOT EXISTS favorites(id INTEGER PRIMARY KEY, user_id TEXT, room_id TEXT, position INTEGER, UNIQUE(user_id, room_id), FOREIGN KEY(user_id) REFERENCES users (user_id));
+    CREATE TABLE IF NOT EXISTS read_markers(id INTEGER PRIMARY KEY, user_id TEXT, room_id TEXT, last_read INTEGER, UNIQUE(user_id, room_id), FOREIGN KEY(user_id) REFERENCES users (user_id));
+    CREATE TABLE IF NOT EXISTS profiles(user_id TEXT PRIMARY KEY, display_name TEXT, avatar TEXT, status TEXT, bio TEXT, timezone TEXT, FOREIGN KEY(user_id) REFERENCES users (user_id));
+    CREATE TABLE IF NOT EXISTS blocks(id INTEGER PRIMARY KEY, user_id TEXT, blocked_id TEXT, UNIQUE(user_id, blocked_id), FOREIGN KEY(user_id) REFERENCES users (user_id), FOREIGN KEY(blocked_id) REFERENCES users (user_id));
+    CREATE INDEX IF NOT EXISTS blocks_blocked_id ON blocks(blocked_id);
+    CREATE TABLE IF NOT EXISTS guests(user_id TEXT PRIMARY KEY, last_seen INTEGER DEFAULT (CAST(strftime('%s', 'now') AS INTEGER)), FOREIGN KEY(user_id) REFERENCES users (user_id));
+    CREATE TABLE IF NOT EXISTS rooms(id INTEGER PRIMARY KEY, room_id TEXT UNIQUE, name TEXT, owner_id TEXT, topic TEXT, description TEXT, slug TEXT, co_owners TEXT, grace INTEGER, expires_at INTEGER, capacity INTEGER);
+    CREATE TABLE IF NOT EXISTS ownership_changes(id INTEGER PRIMARY KEY, room_id TEXT, action TEXT, user_id TEXT, changed_by TEXT, changed_at INTEGER);
+    CREATE INDEX IF NOT EXISTS ownership_changes_room_id ON ownership_changes(room_id);
+    CREATE TABLE IF NOT EXISTS messages(id INTEGER PRIMARY KEY, room_id TEXT, message_id INTEGER, sender TEXT, body TEXT, rendered TEXT, mentions TEXT, attachments TEXT, sent_at INTEGER, sender_id TEXT);
+    CREATE INDEX IF NOT EXISTS messages_sender_id ON messages(sender_id);
+    CREATE TABLE IF NOT EXISTS attachments(id TEXT PRIMARY KEY, room_id TEXT, uploader_id TEXT, file_name TEXT, content_type TEXT, size INTEGER);
+    CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5(body, content='messages', content_rowid='id');
+    CREATE TRIGGER IF NOT EXISTS messages_ai AFTER INSERT ON messages BEGIN
+        INSERT INTO messages_fts(rowid, body) VALUES (new.id, new.body);
+    END;
+    CREATE TRIGGER IF NOT EXISTS messages_ad AFTER DELETE ON messages BEGIN
+        INSERT INTO messages_fts(messages_fts, rowid, body) VALUES ('delete', old.id, old.body);
+    END;
+    CREATE TRIGGER IF NOT EXISTS messages_au AFTER UPDATE ON messages BEGIN
+        INSERT INTO messages_fts(messages_fts, rowid, body) VALUES ('delete', old.id, old.body);
+        INSERT INTO messages_fts(rowid, body) VALUES (new.id, new.body);
+    END;
+    COMMIT;
+";
+
+//The default backend, an in-process database that lasts as long as the server does.
+pub struct SqliteStorage {
+    pool: ConnectionPool
+}
+
+impl SqliteStorage {
+    #[cfg(test)]
+    pub fn in_memory() -> Result<Self, DbServiceError> {
+        let storage = SqliteStorage {
+            pool: ConnectionPool::in_memory()?
+        };
+        storage.pool.get()?.execute_batch(SCHEMA)?;
+        Ok(storage)
+    }
+
+    //Runs the file's SQL before the schema, so seed data can bring its own tables.
+    pub fn from_file(mut file: File) -> Result<Self, DbServiceError> {
+        let mut contents = String::new();
+        if file.read_to_string(&mut contents).is_err() {
+            return Err(EmptyFile);
+        }
+        let storage = SqliteStorage {
+            pool: ConnectionPool::in_memory()?
+        };
+        let conn = storage.pool.get()?;
+        conn.execute_batch(&contents)?;
+        conn.execute_batch(SCHEMA)?;
+        drop(conn);
+        Ok(storage)
+    }
+
+    //Reads share a snapshot, so a user and their favorites always match.
+    fn read<T, F>(&self, f: F) -> Result<T, DbServiceError> where F: FnOnce(&Transaction) -> Result<T, DbServiceError> {
+        self.run(TransactionBehavior::Deferred, f)
+    }
+
+    //Writes take the lock up front so two of them can't deadlock upgrading from a read.
+    fn write<T, F>(&self, f: F) -> Result<T, DbServiceError> where F: FnOnce(&Transaction) -> Result<T, DbServiceError> {
+        self.run(TransactionBehavior::Immediate, f)
+    }
+
+    fn run<T, F>(&self, behavior: TransactionBehavior, f: F) -> Result<T, DbServiceError> where F: FnOnce(&Transaction) -> Result<T, DbServiceError> {
+        let mut conn = self.pool.get()?;
+        let tx = conn.transaction_with_behavior(behavior)?;
+        let result = f(&tx)?;
+        tx.commit()?;
+        Ok(result)
+    }
+
+    fn load_user(conn: &Connection, user_id: &str) -> Result<Option<User>, DbServiceError> {
+        match GetUser::new(String::from(user_id)).execute(conn)? {
+            Some(mut user) => {
+                user.add_favorites(GetFavorites::new(String::from(user_id)).execute(conn)?);
+                for (room_id, last_read) in GetReadMarkers::new(String::from(user_id)).execute(conn)? {
+                    user.set_read_marker(room_id, last_read);
+                }
+                user.profile = GetProfile::new(String::from(user_id)).execute(conn)?;
+                user.blocked_users = GetBlocks::by_user(String::from(user_id)).execute(conn)?;
+                Ok(Some(user))
+            },
+            None => Ok(None)
+        }
+    }
+
+    fn existing_user(conn: &Connection, user_id: &str) -> Result<User, DbServiceError> {
+        SqliteStorage::load_user(conn, user_id)?.ok_or_else(user_not_found)
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn create_user(&self, user: User) -> Result<User, DbServiceError> {
+        self.write(|tx| Ok(CreateUser::new(user).execute(tx)?))
+    }
+
+    fn find_user(&self, user_id: &str) -> Result<Option<User>, DbServiceError> {
+        self.read(|tx| SqliteStorage::load_user(tx, user_id))
+    }
+
+    fn find_user_by_name(&self, user_name: &str) -> Result<Option<User>, DbServiceError> {
+        let conn = self.pool.get()?;
+        Ok(GetUserByName::new(String::from(user_name)).execute(&conn)?)
+    }
+
+    fn update_user(&self, user: &User) -> Result<(), DbServiceError> {
+        let user_id = user.user_id.clone().ok_or_else(user_not_found)?;
+        self.write(|tx| {
+            UpdateUser::new(user_id.clone(), user.user_name.clone()).execute(tx)?;
+            UpdateFavorites::new(user_id, user.favorite_rooms.clone()).execute(tx)?;
+            Ok(())
+        })
+    }
+
+    fn rename_user(&self, user_id: &str, user_name: &str) -> Result<User, DbServiceError> {
+        self.write(|tx| {
+            SqliteStorage::existing_user(tx, user_id)?;
+            UpdateUser::new(String::from(user_id), String::from(user_name)).execute(tx)?;
+            SqliteStorage::existing_user(tx, user_id)
+        })
+    }
+
+    fn upgrade_guest(&self, user_id: &str, user_name: &str) -> Result<User, DbServiceError> {
+        self.write(|tx| {
+            SqliteStorage::existing_user(tx, user_id)?;
+            UpgradeGuest::new(String::from(user_id), String::from(user_name)).execute(tx)?;
+            SqliteStorage::existing_user(tx, user_id)
+        })
+    }
+
+    fn touch_guest(&self, user_id: &str, seen_at: i64) -> Result<(), DbServiceError> {
+        self.write(|tx| Ok(TouchGuest::new(String::from(user_id), seen_at).execute(tx)?))
+    }
+
+    fn idle_guests(&self, seen_before: i64) -> Result<Vec<String>, DbServiceError> {
+        let conn = self.pool.get()?;
+        Ok(GetIdleGuests::new(seen_before).execute(&conn)?)
+    }
+
+    fn delete_user(&self, user_id: &str, anonymize_as: Option<&str>) -> Result<(), DbServiceError> {
+        self.write(|tx| {
+            SqliteStorage::existing_user(tx, user_id)?;
+            Ok(DeleteUser::new(String::from(user_id), anonymize_as.map(String::from)).execute(tx)?)
+        })
+    }
+
+    fn add_favorites(&self, user_id: &str, room_ids: &[String]) -> Result<User, DbServiceError> {
+        self.write(|tx| {
+            SqliteStorage::existing_user(tx, user_id)?;
+            UpdateFavorites::new(String::from(user_id), room_ids.to_vec()).execute(tx)?;
+            SqliteStorage::existing_user(tx, user_id)
+        })
+    }
+
+    fn replace_favorites(&self, user_id: &str, room_ids: &[String]) -> Result<User, DbServiceError> {
+        self.write(|tx| {
+            SqliteStorage::existing_user(tx, user_id)?;
+            ReplaceFavorites::new(String::from(user_id), room_ids.to_vec()).execute(tx)?;
+            SqliteStorage::existing_user(tx, user_id)
+        })
+    }
+
+    fn remove_favorite(&self, user_id: &str, room_id: &str) -> Result<User, DbServiceError> {
+        self.write(|tx| {
+            SqliteStorage::existing_user(tx, user_id)?;
+            RemoveFavorite::for_user(String::from(user_id), String::from(room_id)).execute(tx)?;
+            SqliteStorage::existing_user(tx, user_id)
+        })
+    }
+
+    fn remove_room_from_favorites(&self, room_id: &str) -> Result<(), DbServiceError> {
+        self.write(|tx| {
+            RemoveFavorite::for_room(String::from(room_id)).execute(tx)?;
+            Ok(())
+        })
+    }
+
+    fn update_read_marker(&self, user_id: &str, room_id: &str, last_read: u64) -> Result<User, DbServiceError> {
+        self.write(|tx| {
+            SqliteStorage::existing_user(tx, user_id)?;
+            UpdateReadMarkers::new(String::from(user_id), String::from(room_id), last_read).execute(tx)?;
+            SqliteStorage::existing_user(tx, user_id)
+        })
+    }
+
+    fn update_profile(&self, user_id: &str, profile: &Profile) -> Result<User, DbServiceError> {
+        self.write(|tx| {
+            SqliteStorage::existing_user(tx, user_id)?;
+            UpdateProfile::new(String::from(user_id), profile).execute(tx)?;
+            SqliteStorage::existing_user(tx, user_id)
+        })
+    }
+
+    fn block_user(&self, user_id: &str, blocked_id: &str) -> Result<User, DbServiceError> {
+        self.write(|tx| {
+            SqliteStorage::existing_user(tx, user_id)?;
+            SqliteStorage::existing_user(tx, blocked_id)?;
+            BlockUser::new(String::from(user_id), String::from(blocked_id)).execute(tx)?;
+            SqliteStorage::existing_user(tx, user_id)
+        })
+    }
+
+    fn unblock_user(&self, user_id: &str, blocked_id: &str) -> Result<User, DbServiceError> {
+        self.write(|tx| {
+            SqliteStorage::existing_user(tx, user_id)?;
+            UnblockUser::new(String::from(user_id), String::from(blocked_id)).execute(tx)?;
+            SqliteStorage::existing_user(tx, user_id)
+        })
+    }
+
+    fn blocked_by(&self, user_id: &str) -> Result<Vec<String>, DbServiceError> {
+        let conn = self.pool.get()?;
+        Ok(GetBlocks::of_user(String::from(user_id)).execute(&conn)?)
+    }
+
+    fn save_room(&self, room: &RoomRecord) -> Result<(), DbServiceError> {
+        self.write(|tx| Ok(SaveRoom::new(room.clone()).execute(tx)?))
+    }
+
+    fn delete_room(&self, room_id: &str) -> Result<(), DbServiceError> {
+        self.write(|tx| Ok(DeleteRoom::new(String::from(room_id)).execute(tx)?))
+    }
+
+    fn rooms(&self) -> Result<Vec<RoomRecord>, DbServiceError> {
+        let conn = self.pool.get()?;
+        Ok(GetRooms.execute(&conn)?)
+    }
+
+    fn change_owners(&self, room: &RoomRecord, change: &OwnershipChange) -> Result<(), DbServiceError> {
+        self.write(|tx| {
+            SaveRoom::new(room.clone()).execute(tx)?;
+            RecordOwnershipChange::new(change).execute(tx)?;
+            Ok(())
+        })
+    }
+
+    fn ownership_changes(&self, room_id: &str) -> Result<Vec<OwnershipChange>, DbServiceError> {
+        let conn = self.pool.get()?;
+        Ok(GetOwnershipChanges::new(String::from(room_id)).execute(&conn)?)
+    }
+
+    fn save_message(&self, msg: &StoredMessage) -> Result<(), DbServiceError> {
+        self.write(|tx| Ok(SaveMessage::new(msg).execute(tx)?))
+    }
+
+    fn room_messages(&self, room_id: &str) -> Result<Vec<StoredMessage>, DbServiceError> {
+        let conn = self.pool.get()?;
+        Ok(GetMessages::new(String::from(room_id)).execute(&conn)?)
+    }
+
+    fn user_messages(&self, user_id: &str) -> Result<Vec<StoredMessage>, DbServiceError> {
+        let conn = self.pool.get()?;
+        Ok(GetMessages::by_sender(String::from(user_id)).execute(&conn)?)
+    }
+
+    fn save_attachment(&self, attachment: &Attachment) -> Result<(), DbServiceError> {
+        self.write(|tx| Ok(SaveAttachment::new(attachment).execute(tx)?))
+    }
+
+    fn find_attachment(&self, id: &str) -> Result<Option<Attachment>, DbServiceError> {
+        let conn = self.pool.get()?;
+        Ok(GetAttachment::new(String::from(id)).execute(&conn)?)
+    }
+
+    fn user_attachments(&self, user_id: &str) -> Result<Vec<Attachment>, DbServiceError> {
+        let conn = self.pool.get()?;
+        Ok(GetUserAttachments::new(String::from(user_id)).execute(&conn)?)
+    }
+
+    fn search(&self, query: &SearchQuery) -> Result<Vec<SearchHit>, DbServiceError> {
+        let conn = self.pool.get()?;
+        Ok(SearchMessages::new(query).execute(&conn)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::Storage;
+    use crate::storage::sqlite::SqliteStorage;
+
+    fn storage() -> Box<dyn Storage> {
+        Box::new(SqliteStorage::in_memory().unwrap())
+    }
+
+    crate::storage_tests!(storage());
+}
@@ -0,0 +1,39 @@
+pub mod get_user;
+pub mod get_user_by_name;
+pub mod create_user;
+pub mod update_user;
+pub mod upgrade_guest;
+pub mod touch_guest;
+pub mod get_idle_guests;
+pub mod delete_user;
+pub mod get_favorites;
+pub mod update_favorites;
+pub mod replace_favorites;
+pub mod remove_favorite;
+pub mod get_read_markers;
+pub mod update_read_markers;
+pub mod get_profile;
+pub mod update_profile;
+pub mod get_blocks;
+pub mod block_user;
+pub mod unblock_user;
+pub mod save_room;
+pub mod delete_room;
+pub mod get_rooms;
+pub mod record_ownership_change;
+pub mod get_ownership_changes;
+pub mod save_message;
+pub mod get_messages;
+pub mod save_attachment;
+pub mod get_attachment;
+pub mod get_user_attachments;
+pub mod search_messages;
+
+use rusqlite::{Error, Connection};
+
+//One statement or a small group of them, SqliteStorage decides which transaction they run in.
+pub trait DbCommand {
+    type Output;
+
+    fn execute(&mut self, conn: &Connection) -> Result<Self::Output, Error>;
+}
@@ -0,0 +1,26 @@
+use crate::storage::sqlite::db_command::DbCommand;
+use rusqlite::{Connection, Error, params};
+
+pub struct BlockUser {
+    user_id: String,
+    blocked_id: String
+}
+
+impl BlockUser {
+    pub fn new(user_id: String, blocked_id: String) -> Self {
+        BlockUser {
+            user_id,
+            blocked_id
+        }
+    }
+}
+
+impl DbCommand for BlockUser {
+    type Output = ();
+
+    fn execute(&mut self, conn: &Connection) -> Result<(), Error> {
+        let mut block = conn.prepare_cached("INSERT OR IGNORE INTO blocks (user_id, blocked_id) VALUES (?1, ?2)")?;
+        block.execute(params![self.user_id, self.blocked_id])?;
+        Ok(())
+    }
+}
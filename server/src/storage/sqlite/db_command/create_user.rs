@@ -0,0 +1,28 @@
+use rusqlite::{Error, Connection, params};
+use crate::user::User;
+use crate::storage::sqlite::db_command::DbCommand;
+
+pub struct CreateUser {
+    user: User
+}
+
+impl CreateUser {
+    pub fn new(user: User) -> Self {
+        CreateUser {
+            user
+        }
+    }
+}
+
+impl DbCommand for CreateUser {
+    type Output = User;
+
+    fn execute(&mut self, conn: &Connection) -> Result<User, Error> {
+        let mut create = conn.prepare_cached("INSERT INTO users (user_id, user_name) VALUES(?1, ?2)")?;
+        create.execute(params![self.user.user_id, self.user.user_name])?;
+        if self.user.guest {
+            conn.prepare_cached("INSERT INTO guests (user_id) VALUES(?1)")?.execute(params![self.user.user_id])?;
+        }
+        Ok(self.user.clone())
+    }
+}
@@ -0,0 +1,23 @@
+use crate::storage::sqlite::db_command::DbCommand;
+use rusqlite::{Connection, Error, params};
+
+pub struct DeleteRoom {
+    room_id: String
+}
+
+impl DeleteRoom {
+    pub fn new(room_id: String) -> Self {
+        DeleteRoom {
+            room_id
+        }
+    }
+}
+
+impl DbCommand for DeleteRoom {
+    type Output = ();
+
+    fn execute(&mut self, conn: &Connection) -> Result<(), Error> {
+        conn.prepare_cached("DELETE FROM rooms WHERE room_id=?1")?.execute(params![self.room_id])?;
+        Ok(())
+    }
+}
@@ -0,0 +1,42 @@
+use crate::storage::sqlite::db_command::DbCommand;
+use rusqlite::{Connection, Error, params};
+
+pub struct DeleteUser {
+    user_id: String,
+    anonymize_as: Option<String>
+}
+
+impl DeleteUser {
+    //The user's messages stay either way, with anonymize_as they and any uploads are credited to that name instead.
+    pub fn new(user_id: String, anonymize_as: Option<String>) -> Self {
+        DeleteUser {
+            user_id,
+            anonymize_as
+        }
+    }
+}
+
+impl DbCommand for DeleteUser {
+    type Output = ();
+
+    fn execute(&mut self, conn: &Connection) -> Result<(), Error> {
+        if let Some(name) = self.anonymize_as.as_ref() {
+            conn.prepare_cached("UPDATE messages SET sender=?2 WHERE sender_id=?1")?.execute(params![self.user_id, name])?;
+            conn.prepare_cached("UPDATE attachments SET uploader_id='' WHERE uploader_id=?1")?.execute(params![self.user_id])?;
+        }
+        conn.prepare_cached("UPDATE messages SET sender_id=NULL WHERE sender_id=?1")?.execute(params![self.user_id])?;
+        //Rows referencing the user go first or the foreign keys block the delete.
+        conn.prepare_cached("DELETE FROM attachments WHERE id=(SELECT avatar FROM profiles WHERE user_id=?1)")?
+            .execute(params![self.user_id])?;
+        conn.prepare_cached("DELETE FROM favorites WHERE user_id=?1")?.execute(params![self.user_id])?;
+        conn.prepare_cached("DELETE FROM read_markers WHERE user_id=?1")?.execute(params![self.user_id])?;
+        conn.prepare_cached("DELETE FROM profiles WHERE user_id=?1")?.execute(params![self.user_id])?;
+        conn.prepare_cached("DELETE FROM blocks WHERE user_id=?1 OR blocked_id=?1")?.execute(params![self.user_id])?;
+        conn.prepare_cached("DELETE FROM guests WHERE user_id=?1")?.execute(params![self.user_id])?;
+        let mut delete_stmt = conn.prepare_cached("DELETE FROM users WHERE user_id=?1")?;
+        if delete_stmt.execute(params![self.user_id])? == 0 {
+            return Err(Error::QueryReturnedNoRows);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,38 @@
+use crate::storage::sqlite::db_command::DbCommand;
+use crate::chat::attachments::Attachment;
+use rusqlite::{Connection, Error, params};
+
+pub struct GetAttachment {
+    id: String
+}
+
+impl GetAttachment {
+    pub fn new(id: String) -> Self {
+        GetAttachment {
+            id
+        }
+    }
+}
+
+impl DbCommand for GetAttachment {
+    type Output = Option<Attachment>;
+
+    fn execute(&mut self, conn: &Connection) -> Result<Option<Attachment>, Error> {
+        let mut select = conn.prepare_cached(
+            "SELECT id, room_id, uploader_id, file_name, content_type, size FROM attachments WHERE id=?1")?;
+        let mut rows = select.query(params![self.id])?;
+        if let Some(r) = rows.next()? {
+            let size: i64 = r.get(5)?;
+            Ok(Some(Attachment {
+                id: r.get(0)?,
+                room_id: r.get(1)?,
+                uploader_id: r.get(2)?,
+                file_name: r.get(3)?,
+                content_type: r.get(4)?,
+                size: size as u64
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use crate::storage::sqlite::db_command::DbCommand;
+use rusqlite::{Connection, Error, params};
+
+//Who a user has blocked, or who has blocked them.
+pub struct GetBlocks {
+    user_id: String,
+    blocked_by_user: bool
+}
+
+impl GetBlocks {
+    pub fn by_user(user_id: String) -> Self {
+        GetBlocks {
+            user_id,
+            blocked_by_user: true
+        }
+    }
+
+    pub fn of_user(user_id: String) -> Self {
+        GetBlocks {
+            user_id,
+            blocked_by_user: false
+        }
+    }
+}
+
+impl DbCommand for GetBlocks {
+    type Output = Vec<String>;
+
+    fn execute(&mut self, conn: &Connection) -> Result<Vec<String>, Error> {
+        let mut get_blocks = if self.blocked_by_user {
+            conn.prepare_cached("SELECT blocked_id FROM blocks WHERE user_id=?1 ORDER BY id")?
+        } else {
+            conn.prepare_cached("SELECT user_id FROM blocks WHERE blocked_id=?1 ORDER BY id")?
+        };
+        let mut ids = vec![];
+        let mut rows = get_blocks.query(params![self.user_id])?;
+        while let Some(r) = rows.next()? {
+            ids.push(r.get(0)?);
+        }
+        Ok(ids)
+    }
+}
@@ -0,0 +1,28 @@
+use crate::storage::sqlite::db_command::DbCommand;
+use rusqlite::{Connection, Error, params};
+
+pub struct GetFavorites {
+    user_id: String
+}
+
+impl GetFavorites {
+    pub fn new(user_id: String) -> Self {
+        GetFavorites {
+            user_id
+        }
+    }
+}
+
+impl DbCommand for GetFavorites {
+    type Output = Vec<String>;
+
+    fn execute(&mut self, conn: &Connection) -> Result<Vec<String>, Error> {
+        let mut get_favs = conn.prepare_cached("SELECT room_id FROM favorites WHERE user_id=?1 ORDER BY position")?;
+        let mut user_favs: Vec<String> = vec![];
+        let mut rows = get_favs.query(params![self.user_id])?;
+        while let Some(r) = rows.next()? {
+            user_favs.push(r.get(0)?);
+        }
+        Ok(user_favs)
+    }
+}
@@ -0,0 +1,29 @@
+use crate::storage::sqlite::db_command::DbCommand;
+use rusqlite::{Connection, Error, params};
+
+//Guests who haven't been seen since seen_before.
+pub struct GetIdleGuests {
+    seen_before: i64
+}
+
+impl GetIdleGuests {
+    pub fn new(seen_before: i64) -> Self {
+        GetIdleGuests {
+            seen_before
+        }
+    }
+}
+
+impl DbCommand for GetIdleGuests {
+    type Output = Vec<String>;
+
+    fn execute(&mut self, conn: &Connection) -> Result<Vec<String>, Error> {
+        let mut get_guests = conn.prepare_cached("SELECT user_id FROM guests WHERE last_seen<?1 ORDER BY last_seen")?;
+        let mut ids = vec![];
+        let mut rows = get_guests.query(params![self.seen_before])?;
+        while let Some(r) = rows.next()? {
+            ids.push(r.get(0)?);
+        }
+        Ok(ids)
+    }
+}
@@ -0,0 +1,61 @@
+use crate::storage::sqlite::db_command::DbCommand;
+use crate::chat::message_store::StoredMessage;
+use crate::storage::split_list;
+use rusqlite::{Connection, Error, params};
+
+//A room's messages in order, or everything sent from one account.
+pub struct GetMessages {
+    key: String,
+    by_sender: bool
+}
+
+impl GetMessages {
+    pub fn new(room_id: String) -> Self {
+        GetMessages {
+            key: room_id,
+            by_sender: false
+        }
+    }
+
+    pub fn by_sender(sender_id: String) -> Self {
+        GetMessages {
+            key: sender_id,
+            by_sender: true
+        }
+    }
+}
+
+impl DbCommand for GetMessages {
+    type Output = Vec<StoredMessage>;
+
+    fn execute(&mut self, conn: &Connection) -> Result<Vec<StoredMessage>, Error> {
+        let mut get_messages = if self.by_sender {
+            conn.prepare_cached("\
+                SELECT room_id, message_id, sender, body, rendered, mentions, attachments, sent_at, sender_id \
+                FROM messages WHERE sender_id=?1 ORDER BY sent_at, id")?
+        } else {
+            conn.prepare_cached("\
+                SELECT room_id, message_id, sender, body, rendered, mentions, attachments, sent_at, sender_id \
+                FROM messages WHERE room_id=?1 ORDER BY message_id")?
+        };
+        let mut messages = vec![];
+        let mut rows = get_messages.query(params![self.key])?;
+        while let Some(r) = rows.next()? {
+            let message_id: i64 = r.get(1)?;
+            let mentions: String = r.get(5)?;
+            let attachments: String = r.get(6)?;
+            messages.push(StoredMessage {
+                room_id: r.get(0)?,
+                message_id: message_id as u64,
+                from: r.get(2)?,
+                sender_id: r.get(8)?,
+                msg: r.get(3)?,
+                rendered: r.get(4)?,
+                mentions: split_list(&mentions),
+                attachments: split_list(&attachments),
+                sent_at: r.get(7)?
+            });
+        }
+        Ok(messages)
+    }
+}
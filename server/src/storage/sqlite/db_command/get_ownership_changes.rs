@@ -0,0 +1,40 @@
+use crate::storage::sqlite::db_command::DbCommand;
+use crate::chat::chat_data::{OwnershipChange, OwnershipAction};
+use rusqlite::{Connection, Error, params};
+
+pub struct GetOwnershipChanges {
+    room_id: String
+}
+
+impl GetOwnershipChanges {
+    pub fn new(room_id: String) -> Self {
+        GetOwnershipChanges {
+            room_id
+        }
+    }
+}
+
+impl DbCommand for GetOwnershipChanges {
+    type Output = Vec<OwnershipChange>;
+
+    fn execute(&mut self, conn: &Connection) -> Result<Vec<OwnershipChange>, Error> {
+        let mut get_changes = conn.prepare_cached("\
+            SELECT room_id, action, user_id, changed_by, changed_at FROM ownership_changes \
+            WHERE room_id=?1 ORDER BY id")?;
+        let mut changes = vec![];
+        let mut rows = get_changes.query(params![self.room_id])?;
+        while let Some(r) = rows.next()? {
+            let action: String = r.get(1)?;
+            if let Some(action) = OwnershipAction::parse(&action) {
+                changes.push(OwnershipChange {
+                    room_id: r.get(0)?,
+                    action,
+                    user_id: r.get(2)?,
+                    changed_by: r.get(3)?,
+                    changed_at: r.get(4)?
+                });
+            }
+        }
+        Ok(changes)
+    }
+}
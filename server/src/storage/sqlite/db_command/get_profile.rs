@@ -0,0 +1,36 @@
+use crate::storage::sqlite::db_command::DbCommand;
+use rusqlite::{Connection, Error, params};
+use crate::user::Profile;
+
+pub struct GetProfile {
+    user_id: String
+}
+
+impl GetProfile {
+    pub fn new(user_id: String) -> Self {
+        GetProfile {
+            user_id
+        }
+    }
+}
+
+impl DbCommand for GetProfile {
+    type Output = Profile;
+
+    //A user who never set up a profile has no row and gets an empty one.
+    fn execute(&mut self, conn: &Connection) -> Result<Profile, Error> {
+        let mut get_profile = conn.prepare_cached("\
+            SELECT display_name, avatar, status, bio, timezone FROM profiles WHERE user_id=?1")?;
+        let mut rows = get_profile.query(params![self.user_id])?;
+        match rows.next()? {
+            Some(r) => Ok(Profile {
+                display_name: r.get(0)?,
+                avatar: r.get(1)?,
+                status: r.get(2)?,
+                bio: r.get(3)?,
+                timezone: r.get(4)?
+            }),
+            None => Ok(Profile::default())
+        }
+    }
+}
@@ -0,0 +1,29 @@
+use crate::storage::sqlite::db_command::DbCommand;
+use rusqlite::{Connection, Error, params};
+
+pub struct GetReadMarkers {
+    user_id: String
+}
+
+impl GetReadMarkers {
+    pub fn new(user_id: String) -> Self {
+        GetReadMarkers {
+            user_id
+        }
+    }
+}
+
+impl DbCommand for GetReadMarkers {
+    type Output = Vec<(String, u64)>;
+
+    fn execute(&mut self, conn: &Connection) -> Result<Vec<(String, u64)>, Error> {
+        let mut get_markers = conn.prepare_cached("SELECT room_id, last_read FROM read_markers WHERE user_id=?1")?;
+        let mut markers = vec![];
+        let mut rows = get_markers.query(params![self.user_id])?;
+        while let Some(r) = rows.next()? {
+            let last_read: i64 = r.get(1)?;
+            markers.push((r.get(0)?, last_read as u64));
+        }
+        Ok(markers)
+    }
+}
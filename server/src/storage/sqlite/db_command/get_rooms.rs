@@ -0,0 +1,35 @@
+use crate::storage::sqlite::db_command::DbCommand;
+use crate::storage::{RoomRecord, split_list};
+use rusqlite::{Connection, Error, NO_PARAMS};
+
+pub struct GetRooms;
+
+impl DbCommand for GetRooms {
+    type Output = Vec<RoomRecord>;
+
+    fn execute(&mut self, conn: &Connection) -> Result<Vec<RoomRecord>, Error> {
+        let mut get_rooms = conn.prepare_cached("\
+            SELECT r.room_id, r.name, r.owner_id, COALESCE(MAX(m.message_id), 0), r.topic, r.description, r.slug, r.co_owners, r.grace, r.expires_at, r.capacity \
+            FROM rooms r LEFT JOIN messages m ON m.room_id = r.room_id \
+            GROUP BY r.id ORDER BY r.id")?;
+        let mut rooms = vec![];
+        let mut rows = get_rooms.query(NO_PARAMS)?;
+        while let Some(r) = rows.next()? {
+            let last_message_id: i64 = r.get(3)?;
+            rooms.push(RoomRecord {
+                room_id: r.get(0)?,
+                name: r.get(1)?,
+                owner_id: r.get(2)?,
+                last_message_id: last_message_id as u64,
+                topic: r.get(4)?,
+                description: r.get(5)?,
+                slug: r.get::<_, Option<String>>(6)?.unwrap_or_default(),
+                co_owners: split_list(&r.get::<_, Option<String>>(7)?.unwrap_or_default()),
+                grace: r.get(8)?,
+                expires_at: r.get(9)?,
+                capacity: r.get(10)?
+            });
+        }
+        Ok(rooms)
+    }
+}
@@ -0,0 +1,33 @@
+use crate::storage::sqlite::db_command::DbCommand;
+use rusqlite::{Connection, Error, params};
+use crate::user::{IUser, User};
+
+pub struct GetUser {
+    user_id: String
+}
+
+impl GetUser {
+    pub fn new(user_id: String) -> Self {
+        GetUser {
+            user_id
+        }
+    }
+}
+
+impl DbCommand for GetUser {
+    type Output = Option<User>;
+
+    fn execute(&mut self, conn: &Connection) -> Result<Option<User>, Error> {
+        let mut retrieve_stmt = conn.prepare_cached("SELECT users.user_id, user_name, guests.user_id IS NOT NULL AS guest FROM users \
+            LEFT JOIN guests ON guests.user_id=users.user_id WHERE users.user_id=?1")?;
+        let mut row = retrieve_stmt.query(params![self.user_id])?;
+        if let Some(user_row) = row.next()? {
+            let mut user = User::new(user_row.get("user_name")?);
+            user.set_user_id(user_row.get("user_id")?);
+            user.guest = user_row.get("guest")?;
+            Ok(Some(user))
+        } else {
+            Ok(None)
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use crate::storage::sqlite::db_command::DbCommand;
+use crate::chat::attachments::Attachment;
+use rusqlite::{Connection, Error, params};
+
+pub struct GetUserAttachments {
+    uploader_id: String
+}
+
+impl GetUserAttachments {
+    pub fn new(uploader_id: String) -> Self {
+        GetUserAttachments {
+            uploader_id
+        }
+    }
+}
+
+impl DbCommand for GetUserAttachments {
+    type Output = Vec<Attachment>;
+
+    fn execute(&mut self, conn: &Connection) -> Result<Vec<Attachment>, Error> {
+        let mut select = conn.prepare_cached(
+            "SELECT id, room_id, uploader_id, file_name, content_type, size FROM attachments WHERE uploader_id=?1 ORDER BY id")?;
+        let mut attachments = vec![];
+        let mut rows = select.query(params![self.uploader_id])?;
+        while let Some(r) = rows.next()? {
+            let size: i64 = r.get(5)?;
+            attachments.push(Attachment {
+                id: r.get(0)?,
+                room_id: r.get(1)?,
+                uploader_id: r.get(2)?,
+                file_name: r.get(3)?,
+                content_type: r.get(4)?,
+                size: size as u64
+            });
+        }
+        Ok(attachments)
+    }
+}
@@ -0,0 +1,31 @@
+use crate::storage::sqlite::db_command::DbCommand;
+use rusqlite::{Connection, Error, params};
+use crate::user::{IUser, User};
+
+pub struct GetUserByName {
+    user_name: String
+}
+
+impl GetUserByName {
+    pub fn new(user_name: String) -> Self {
+        GetUserByName {
+            user_name
+        }
+    }
+}
+
+impl DbCommand for GetUserByName {
+    type Output = Option<User>;
+
+    fn execute(&mut self, conn: &Connection) -> Result<Option<User>, Error> {
+        let mut retrieve_stmt = conn.prepare_cached("SELECT user_id, user_name FROM users WHERE user_name=?1 COLLATE NOCASE")?;
+        let mut row = retrieve_stmt.query(params![self.user_name])?;
+        if let Some(user_row) = row.next()? {
+            let mut user = User::new(user_row.get("user_name")?);
+            user.set_user_id(user_row.get("user_id")?);
+            Ok(Some(user))
+        } else {
+            Ok(None)
+        }
+    }
+}
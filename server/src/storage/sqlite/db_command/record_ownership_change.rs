@@ -0,0 +1,27 @@
+use crate::storage::sqlite::db_command::DbCommand;
+use crate::chat::chat_data::OwnershipChange;
+use rusqlite::{Connection, Error, params};
+
+pub struct RecordOwnershipChange<'a> {
+    change: &'a OwnershipChange
+}
+
+impl<'a> RecordOwnershipChange<'a> {
+    pub fn new(change: &'a OwnershipChange) -> Self {
+        RecordOwnershipChange {
+            change
+        }
+    }
+}
+
+impl DbCommand for RecordOwnershipChange<'_> {
+    type Output = ();
+
+    fn execute(&mut self, conn: &Connection) -> Result<(), Error> {
+        let mut record = conn.prepare_cached(
+            "INSERT INTO ownership_changes (room_id, action, user_id, changed_by, changed_at) VALUES (?1, ?2, ?3, ?4, ?5)")?;
+        record.execute(params![self.change.room_id, self.change.action.as_str(), self.change.user_id,
+            self.change.changed_by, self.change.changed_at])?;
+        Ok(())
+    }
+}
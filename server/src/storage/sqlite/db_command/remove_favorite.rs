@@ -0,0 +1,37 @@
+use crate::storage::sqlite::db_command::DbCommand;
+use rusqlite::{Connection, Error, params};
+
+//Removes a room from one user's favorites, or from everyone's when no user is given.
+pub struct RemoveFavorite {
+    user_id: Option<String>,
+    room_id: String
+}
+
+impl RemoveFavorite {
+    pub fn for_user(user_id: String, room_id: String) -> Self {
+        RemoveFavorite {
+            user_id: Some(user_id),
+            room_id
+        }
+    }
+
+    pub fn for_room(room_id: String) -> Self {
+        RemoveFavorite {
+            user_id: None,
+            room_id
+        }
+    }
+}
+
+impl DbCommand for RemoveFavorite {
+    type Output = usize;
+
+    fn execute(&mut self, conn: &Connection) -> Result<usize, Error> {
+        match self.user_id.as_ref() {
+            Some(user_id) => conn.prepare_cached("DELETE FROM favorites WHERE user_id=?1 AND room_id=?2")?
+                .execute(params![user_id, self.room_id]),
+            None => conn.prepare_cached("DELETE FROM favorites WHERE room_id=?1")?
+                .execute(params![self.room_id])
+        }
+    }
+}
@@ -0,0 +1,29 @@
+use crate::storage::sqlite::db_command::DbCommand;
+use rusqlite::{Connection, Error, params};
+
+pub struct ReplaceFavorites {
+    user_id: String,
+    room_ids: Vec<String>
+}
+
+impl ReplaceFavorites {
+    pub fn new(user_id: String, room_ids: Vec<String>) -> Self {
+        ReplaceFavorites {
+            user_id,
+            room_ids
+        }
+    }
+}
+
+impl DbCommand for ReplaceFavorites {
+    type Output = ();
+
+    fn execute(&mut self, conn: &Connection) -> Result<(), Error> {
+        conn.prepare_cached("DELETE FROM favorites WHERE user_id=?1")?.execute(params![self.user_id])?;
+        let mut insert_fav = conn.prepare_cached("INSERT INTO favorites (user_id, room_id, position) VALUES (?1, ?2, ?3)")?;
+        for (position, f) in self.room_ids.iter().enumerate() {
+            insert_fav.execute(params![self.user_id, f, position as i64])?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,27 @@
+use crate::storage::sqlite::db_command::DbCommand;
+use crate::chat::attachments::Attachment;
+use rusqlite::{Connection, Error, params};
+
+pub struct SaveAttachment<'a> {
+    attachment: &'a Attachment
+}
+
+impl<'a> SaveAttachment<'a> {
+    pub fn new(attachment: &'a Attachment) -> Self {
+        SaveAttachment {
+            attachment
+        }
+    }
+}
+
+impl<'a> DbCommand for SaveAttachment<'a> {
+    type Output = ();
+
+    fn execute(&mut self, conn: &Connection) -> Result<(), Error> {
+        let mut insert = conn.prepare_cached(
+            "INSERT INTO attachments (id, room_id, uploader_id, file_name, content_type, size) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
+        insert.execute(params![self.attachment.id, self.attachment.room_id, self.attachment.uploader_id,
+            self.attachment.file_name, self.attachment.content_type, self.attachment.size as i64])?;
+        Ok(())
+    }
+}
@@ -0,0 +1,27 @@
+use crate::storage::sqlite::db_command::DbCommand;
+use crate::chat::message_store::StoredMessage;
+use rusqlite::{Connection, Error, params};
+
+pub struct SaveMessage<'a> {
+    msg: &'a StoredMessage
+}
+
+impl<'a> SaveMessage<'a> {
+    pub fn new(msg: &'a StoredMessage) -> Self {
+        SaveMessage {
+            msg
+        }
+    }
+}
+
+impl<'a> DbCommand for SaveMessage<'a> {
+    type Output = ();
+
+    fn execute(&mut self, conn: &Connection) -> Result<(), Error> {
+        let mut insert = conn.prepare_cached(
+            "INSERT INTO messages (room_id, message_id, sender, body, rendered, mentions, attachments, sent_at, sender_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)")?;
+        insert.execute(params![self.msg.room_id, self.msg.message_id as i64, self.msg.from, self.msg.msg, self.msg.rendered,
+            self.msg.mentions.join(","), self.msg.attachments.join(","), self.msg.sent_at, self.msg.sender_id])?;
+        Ok(())
+    }
+}
@@ -0,0 +1,32 @@
+use crate::storage::sqlite::db_command::DbCommand;
+use crate::storage::RoomRecord;
+use rusqlite::{Connection, Error, params};
+
+pub struct SaveRoom {
+    room: RoomRecord
+}
+
+impl SaveRoom {
+    pub fn new(room: RoomRecord) -> Self {
+        SaveRoom {
+            room
+        }
+    }
+}
+
+impl DbCommand for SaveRoom {
+    type Output = ();
+
+    fn execute(&mut self, conn: &Connection) -> Result<(), Error> {
+        let mut save = conn.prepare_cached("\
+            INSERT INTO rooms (room_id, name, owner_id, topic, description, slug, co_owners, grace, expires_at, capacity) \
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10) \
+            ON CONFLICT(room_id) DO UPDATE SET name=excluded.name, owner_id=excluded.owner_id, \
+            topic=excluded.topic, description=excluded.description, slug=excluded.slug, co_owners=excluded.co_owners, \
+            grace=excluded.grace, expires_at=excluded.expires_at, capacity=excluded.capacity")?;
+        save.execute(params![self.room.room_id, self.room.name, self.room.owner_id, self.room.topic,
+            self.room.description, self.room.slug, self.room.co_owners.join(","), self.room.grace, self.room.expires_at,
+            self.room.capacity])?;
+        Ok(())
+    }
+}
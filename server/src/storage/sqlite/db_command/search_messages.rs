@@ -0,0 +1,84 @@
+use crate::storage::sqlite::db_command::DbCommand;
+use crate::storage::{highlight, MATCH_START, MATCH_END, SEARCH_LIMIT};
+use crate::chat::message_store::SearchQuery;
+use crate::chat::chat_data::SearchHit;
+use rusqlite::{Connection, Error, ToSql};
+use rusqlite::types::Null;
+use chrono::{TimeZone, Utc};
+
+pub struct SearchMessages<'a> {
+    query: &'a SearchQuery
+}
+
+impl<'a> SearchMessages<'a> {
+    pub fn new(query: &'a SearchQuery) -> Self {
+        SearchMessages {
+            query
+        }
+    }
+
+    //Each word is quoted so user input can't use FTS5 query syntax.
+    fn match_expression(text: &str) -> String {
+        text.split_whitespace()
+            .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    fn optional<T: ToSql>(value: &Option<T>) -> &dyn ToSql {
+        match value {
+            Some(v) => v,
+            None => &Null
+        }
+    }
+}
+
+impl<'a> DbCommand for SearchMessages<'a> {
+    type Output = Vec<SearchHit>;
+
+    fn execute(&mut self, conn: &Connection) -> Result<Vec<SearchHit>, Error> {
+        let query = self.query;
+        let match_expr = SearchMessages::match_expression(&query.text);
+        if match_expr.is_empty() || query.rooms.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let room_params: Vec<String> = (0..query.rooms.len()).map(|i| format!("?{}", i + 6)).collect();
+        let sql = format!("\
+            SELECT m.room_id, m.message_id, m.sender, m.sent_at, \
+                   snippet(messages_fts, 0, '{}', '{}', '...', 16) \
+            FROM messages_fts JOIN messages m ON m.id = messages_fts.rowid \
+            WHERE messages_fts MATCH ?1 \
+              AND (?2 IS NULL OR m.sender = ?2) \
+              AND (?3 IS NULL OR m.sent_at >= ?3) \
+              AND (?4 IS NULL OR m.sent_at <= ?4) \
+              AND m.room_id IN ({}) \
+            ORDER BY rank LIMIT ?5", MATCH_START, MATCH_END, room_params.join(","));
+
+        let mut args: Vec<&dyn ToSql> = vec![&match_expr];
+        args.push(SearchMessages::optional(&query.from));
+        args.push(SearchMessages::optional(&query.after));
+        args.push(SearchMessages::optional(&query.before));
+        args.push(&SEARCH_LIMIT);
+        for room in query.rooms.iter() {
+            args.push(room);
+        }
+
+        let mut stmt = conn.prepare(&sql)?;
+        let mut rows = stmt.query(args)?;
+        let mut hits = vec![];
+        while let Some(r) = rows.next()? {
+            let message_id: i64 = r.get(1)?;
+            let sent_at: i64 = r.get(3)?;
+            let snippet: String = r.get(4)?;
+            hits.push(SearchHit {
+                room_id: r.get(0)?,
+                message_id: message_id as u64,
+                from: r.get(2)?,
+                sent_at: Utc.timestamp_opt(sent_at, 0).unwrap().to_rfc3339(),
+                snippet: highlight(&snippet)
+            });
+        }
+        Ok(hits)
+    }
+}
@@ -0,0 +1,26 @@
+use crate::storage::sqlite::db_command::DbCommand;
+use rusqlite::{Connection, Error, params};
+
+pub struct TouchGuest {
+    user_id: String,
+    seen_at: i64
+}
+
+impl TouchGuest {
+    pub fn new(user_id: String, seen_at: i64) -> Self {
+        TouchGuest {
+            user_id,
+            seen_at
+        }
+    }
+}
+
+impl DbCommand for TouchGuest {
+    type Output = ();
+
+    fn execute(&mut self, conn: &Connection) -> Result<(), Error> {
+        let mut touch = conn.prepare_cached("UPDATE guests SET last_seen=?2 WHERE user_id=?1 AND last_seen<?2")?;
+        touch.execute(params![self.user_id, self.seen_at])?;
+        Ok(())
+    }
+}
@@ -0,0 +1,26 @@
+use crate::storage::sqlite::db_command::DbCommand;
+use rusqlite::{Connection, Error, params};
+
+pub struct UnblockUser {
+    user_id: String,
+    blocked_id: String
+}
+
+impl UnblockUser {
+    pub fn new(user_id: String, blocked_id: String) -> Self {
+        UnblockUser {
+            user_id,
+            blocked_id
+        }
+    }
+}
+
+impl DbCommand for UnblockUser {
+    type Output = ();
+
+    fn execute(&mut self, conn: &Connection) -> Result<(), Error> {
+        let mut unblock = conn.prepare_cached("DELETE FROM blocks WHERE user_id=?1 AND blocked_id=?2")?;
+        unblock.execute(params![self.user_id, self.blocked_id])?;
+        Ok(())
+    }
+}
@@ -0,0 +1,32 @@
+use crate::storage::sqlite::db_command::DbCommand;
+use rusqlite::{Connection, Error, params};
+
+pub struct UpdateFavorites {
+    user_id: String,
+    room_ids: Vec<String>
+}
+
+impl UpdateFavorites {
+    pub fn new(user_id: String, room_ids: Vec<String>) -> Self {
+        UpdateFavorites {
+            user_id,
+            room_ids
+        }
+    }
+}
+
+impl DbCommand for UpdateFavorites {
+    type Output = ();
+
+    fn execute(&mut self, conn: &Connection) -> Result<(), Error> {
+        //New favorites go to the end of the list, ones already saved keep their place.
+        let mut update_favs = conn.prepare_cached("\
+            INSERT INTO favorites (user_id, room_id, position) \
+            SELECT ?1, ?2, COALESCE(MAX(position) + 1, 0) FROM favorites WHERE user_id=?1 \
+            ON CONFLICT(user_id, room_id) DO NOTHING")?;
+        for f in self.room_ids.iter() {
+            update_favs.execute(params![self.user_id, f])?;
+        }
+        Ok(())
+    }
+}
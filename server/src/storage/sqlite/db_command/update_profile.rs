@@ -0,0 +1,33 @@
+use crate::storage::sqlite::db_command::DbCommand;
+use rusqlite::{Connection, Error, params};
+use crate::user::Profile;
+
+pub struct UpdateProfile<'a> {
+    user_id: String,
+    profile: &'a Profile
+}
+
+impl<'a> UpdateProfile<'a> {
+    pub fn new(user_id: String, profile: &'a Profile) -> Self {
+        UpdateProfile {
+            user_id,
+            profile
+        }
+    }
+}
+
+impl<'a> DbCommand for UpdateProfile<'a> {
+    type Output = ();
+
+    fn execute(&mut self, conn: &Connection) -> Result<(), Error> {
+        conn.prepare_cached("DELETE FROM attachments WHERE id=(SELECT avatar FROM profiles WHERE user_id=?1) AND id IS NOT ?2")?
+            .execute(params![self.user_id, self.profile.avatar])?;
+        let mut update_profile = conn.prepare_cached("\
+            INSERT INTO profiles (user_id, display_name, avatar, status, bio, timezone) VALUES (?1, ?2, ?3, ?4, ?5, ?6) \
+            ON CONFLICT(user_id) DO UPDATE SET display_name=excluded.display_name, avatar=excluded.avatar, \
+            status=excluded.status, bio=excluded.bio, timezone=excluded.timezone")?;
+        update_profile.execute(params![self.user_id, self.profile.display_name, self.profile.avatar,
+            self.profile.status, self.profile.bio, self.profile.timezone])?;
+        Ok(())
+    }
+}
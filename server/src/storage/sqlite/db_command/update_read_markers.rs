@@ -0,0 +1,31 @@
+use crate::storage::sqlite::db_command::DbCommand;
+use rusqlite::{Connection, Error, params};
+
+pub struct UpdateReadMarkers {
+    user_id: String,
+    room_id: String,
+    last_read: u64
+}
+
+impl UpdateReadMarkers {
+    pub fn new(user_id: String, room_id: String, last_read: u64) -> Self {
+        UpdateReadMarkers {
+            user_id,
+            room_id,
+            last_read
+        }
+    }
+}
+
+impl DbCommand for UpdateReadMarkers {
+    type Output = ();
+
+    fn execute(&mut self, conn: &Connection) -> Result<(), Error> {
+        let mut update_markers = conn.prepare_cached("\
+            INSERT INTO read_markers (user_id, room_id, last_read) VALUES (?1, ?2, ?3) \
+            ON CONFLICT(user_id, room_id) DO UPDATE SET last_read=excluded.last_read \
+            WHERE excluded.last_read > read_markers.last_read")?;
+        update_markers.execute(params![self.user_id, self.room_id, self.last_read as i64])?;
+        Ok(())
+    }
+}
@@ -0,0 +1,28 @@
+use crate::storage::sqlite::db_command::DbCommand;
+use rusqlite::{Connection, Error, params};
+
+pub struct UpdateUser {
+    user_id: String,
+    user_name: String
+}
+
+impl UpdateUser {
+    pub fn new(user_id: String, user_name: String) -> Self {
+        UpdateUser {
+            user_id,
+            user_name
+        }
+    }
+}
+
+impl DbCommand for UpdateUser {
+    type Output = ();
+
+    fn execute(&mut self, conn: &Connection) -> Result<(), Error> {
+        let mut update_stmt = conn.prepare_cached("UPDATE users SET user_name=?1 WHERE user_id=?2")?;
+        if update_stmt.execute(params![self.user_name, self.user_id])? == 0 {
+            return Err(Error::QueryReturnedNoRows);
+        }
+        Ok(())
+    }
+}
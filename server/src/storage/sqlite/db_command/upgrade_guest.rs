@@ -0,0 +1,31 @@
+use crate::storage::sqlite::db_command::DbCommand;
+use rusqlite::{Connection, Error, params};
+
+pub struct UpgradeGuest {
+    user_id: String,
+    user_name: String
+}
+
+impl UpgradeGuest {
+    pub fn new(user_id: String, user_name: String) -> Self {
+        UpgradeGuest {
+            user_id,
+            user_name
+        }
+    }
+}
+
+impl DbCommand for UpgradeGuest {
+    type Output = ();
+
+    fn execute(&mut self, conn: &Connection) -> Result<(), Error> {
+        conn.prepare_cached("UPDATE messages SET sender=?2 WHERE sender_id=?1")?
+            .execute(params![self.user_id, self.user_name])?;
+        let mut rename_stmt = conn.prepare_cached("UPDATE users SET user_name=?2 WHERE user_id=?1")?;
+        if rename_stmt.execute(params![self.user_id, self.user_name])? == 0 {
+            return Err(Error::QueryReturnedNoRows);
+        }
+        conn.prepare_cached("DELETE FROM guests WHERE user_id=?1")?.execute(params![self.user_id])?;
+        Ok(())
+    }
+}
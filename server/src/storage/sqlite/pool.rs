@@ -0,0 +1,104 @@
+use rusqlite::{Connection, Error, OpenFlags};
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+const MAX_IDLE: usize = 8;
+
+//Connections to one shared-cache in-memory database, so requests don't queue behind a single connection.
+pub struct ConnectionPool {
+    uri: String,
+    idle: Mutex<Vec<Connection>>,
+    //The database only lives while a connection to it is open.
+    _anchor: Mutex<Connection>
+}
+
+impl ConnectionPool {
+    pub fn in_memory() -> Result<Self, Error> {
+        let uri = format!("file:crabby-{}?mode=memory&cache=shared", Uuid::new_v4());
+        let anchor = ConnectionPool::open(&uri)?;
+        Ok(ConnectionPool {
+            uri,
+            idle: Mutex::new(vec![]),
+            _anchor: Mutex::new(anchor)
+        })
+    }
+
+    pub fn get(&self) -> Result<PooledConnection, Error> {
+        let idle = self.idle.lock().unwrap().pop();
+        let conn = match idle {
+            Some(conn) => conn,
+            None => ConnectionPool::open(&self.uri)?
+        };
+        Ok(PooledConnection {
+            pool: self,
+            conn: Some(conn)
+        })
+    }
+
+    fn open(uri: &str) -> Result<Connection, Error> {
+        let conn = Connection::open_with_flags(uri, OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE
+            | OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX)?;
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        Ok(conn)
+    }
+
+    fn release(&self, conn: Connection) {
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < MAX_IDLE {
+            idle.push(conn);
+        }
+    }
+}
+
+//Goes back to the pool when dropped, along with its cached statements.
+pub struct PooledConnection<'a> {
+    pool: &'a ConnectionPool,
+    conn: Option<Connection>
+}
+
+impl<'a> Deref for PooledConnection<'a> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl<'a> DerefMut for PooledConnection<'a> {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.conn.as_mut().unwrap()
+    }
+}
+
+impl<'a> Drop for PooledConnection<'a> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            self.pool.release(conn);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::sqlite::pool::ConnectionPool;
+    use rusqlite::NO_PARAMS;
+
+    #[test]
+    fn pooled_connections_share_one_database() {
+        let pool = ConnectionPool::in_memory().unwrap();
+        let first = pool.get().unwrap();
+        first.execute_batch("CREATE TABLE t(x INTEGER); INSERT INTO t VALUES (1);").unwrap();
+        let second = pool.get().unwrap();
+        let count: i64 = second.query_row("SELECT COUNT(*) FROM t", NO_PARAMS, |r| r.get(0)).unwrap();
+        assert_eq!(1, count);
+    }
+
+    #[test]
+    fn separate_pools_are_isolated() {
+        let one = ConnectionPool::in_memory().unwrap();
+        one.get().unwrap().execute_batch("CREATE TABLE t(x INTEGER);").unwrap();
+        let two = ConnectionPool::in_memory().unwrap();
+        assert!(two.get().unwrap().execute_batch("SELECT * FROM t;").is_err());
+    }
+}
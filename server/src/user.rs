@@ -1,10 +1,10 @@
 pub mod user_db_service;
 use uuid::Uuid;
 use std::borrow::Borrow;
-use std::collections::HashSet;
-use std::collections::hash_set;
+use std::collections::HashMap;
+use std::collections::hash_map;
+use std::slice;
 use serde::{Deserialize, Serialize};
-use std::collections::hash_set::Iter;
 
 #[derive(FromForm)]
 pub struct NewUserForm {
@@ -12,11 +12,100 @@ pub struct NewUserForm {
     password: String
 }
 
+#[derive(Deserialize)]
+pub struct UserPatch {
+    pub user_name: String
+}
+
+//Anything left out of the patch stays as it is, an empty string clears the field.
+#[derive(Deserialize, Default)]
+pub struct ProfilePatch {
+    pub display_name: Option<String>,
+    pub status: Option<String>,
+    pub bio: Option<String>,
+    pub timezone: Option<String>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    //Attachment id of the uploaded image, served from /user/<user_id>/avatar.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avatar: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bio: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>
+}
+
+//Everything kept about one user, in the shape GET /user/me/export returns it.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UserExport {
+    pub exported_at: String,
+    pub user: User,
+    pub messages: Vec<ExportedMessage>,
+    pub attachments: Vec<ExportedAttachment>,
+    pub rooms_owned: Vec<ExportedRoom>
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ExportedMessage {
+    pub room_id: String,
+    pub message_id: u64,
+    pub msg: String,
+    pub sent_at: String
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ExportedAttachment {
+    pub id: String,
+    pub room_id: String,
+    pub name: String,
+    pub content_type: String,
+    pub size: u64
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ExportedRoom {
+    pub room_id: String,
+    pub name: String
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BlockedUser {
+    pub user_name: String
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UserAvailable {
+    pub name: String,
+    pub available: bool
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UserDeleted {
+    pub user_id: String
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct User {
     pub user_id: Option<String>,
     pub user_name: String,
-    pub favorite_rooms: HashSet<String>
+    //Room ids, in the order the user arranged them.
+    pub favorite_rooms: Vec<String>,
+    #[serde(default)]
+    pub read_markers: HashMap<String, u64>,
+    #[serde(default)]
+    pub profile: Profile,
+    //Ids of users whose messages this user never receives, they stay on the server and the block list goes out by name.
+    #[serde(default, skip_serializing)]
+    pub blocked_users: Vec<String>,
+    //Guests get a generated name and can't create rooms until they upgrade.
+    #[serde(default)]
+    pub guest: bool
 }
 
 pub trait IUser {
@@ -33,7 +122,13 @@ pub trait IUser {
 
     fn add_favorites(&mut self, favs: Vec<String>);
 
-    fn favorites(&self) -> hash_set::Iter<String>;
+    fn favorites(&self) -> slice::Iter<String>;
+
+    fn read_marker(&self, room_id: &str) -> u64;
+
+    fn set_read_marker(&mut self, room_id: String, message_id: u64);
+
+    fn read_markers(&self) -> hash_map::Iter<String, u64>;
 
     fn to_user(&self) -> User;
 
@@ -45,7 +140,11 @@ impl User {
         User {
             user_id: None,
             user_name,
-            favorite_rooms: HashSet::new()
+            favorite_rooms: vec![],
+            read_markers: HashMap::new(),
+            profile: Profile::default(),
+            blocked_users: vec![],
+            guest: false
         }
     }
 
@@ -53,7 +152,11 @@ impl User {
         User {
             user_id: None,
             user_name: form.user_name,
-            favorite_rooms: HashSet::new()
+            favorite_rooms: vec![],
+            read_markers: HashMap::new(),
+            profile: Profile::default(),
+            blocked_users: vec![],
+            guest: false
         }
     }
 }
@@ -62,7 +165,8 @@ impl User {
 struct NullUser{
     user_id: Option<String>,
     user_name: String,
-    favorite_rooms: HashSet<String>
+    favorite_rooms: Vec<String>,
+    read_markers: HashMap<String, u64>
 }
 
 impl NullUser {
@@ -70,7 +174,8 @@ impl NullUser {
         NullUser {
             user_id: None,
             user_name: String::new(),
-            favorite_rooms: HashSet::new()
+            favorite_rooms: vec![],
+            read_markers: HashMap::new()
         }
     }
 }
@@ -100,15 +205,31 @@ impl IUser for NullUser {
         //
     }
 
-    fn favorites(&self) -> Iter<String> {
+    fn favorites(&self) -> slice::Iter<String> {
         self.favorite_rooms.iter()
     }
 
+    fn read_marker(&self, _room_id: &str) -> u64 {
+        0
+    }
+
+    fn set_read_marker(&mut self, _room_id: String, _message_id: u64) {
+        //
+    }
+
+    fn read_markers(&self) -> hash_map::Iter<String, u64> {
+        self.read_markers.iter()
+    }
+
     fn to_user(&self) -> User {
         User {
             user_id: None,
             user_name: String::from(""),
-            favorite_rooms: HashSet::new()
+            favorite_rooms: vec![],
+            read_markers: HashMap::new(),
+            profile: Profile::default(),
+            blocked_users: vec![],
+            guest: false
         }
     }
 
@@ -140,15 +261,32 @@ impl IUser for User {
     }
 
     fn add_favorites(&mut self, favs: Vec<String>) {
-        for fav in favs.iter() {
-            self.favorite_rooms.insert(fav.clone());
+        for fav in favs {
+            if !self.favorite_rooms.contains(&fav) {
+                self.favorite_rooms.push(fav);
+            }
         }
     }
 
-    fn favorites(&self) -> hash_set::Iter<String> {
+    fn favorites(&self) -> slice::Iter<String> {
         self.favorite_rooms.iter()
     }
 
+    fn read_marker(&self, room_id: &str) -> u64 {
+        self.read_markers.get(room_id).copied().unwrap_or(0)
+    }
+
+    fn set_read_marker(&mut self, room_id: String, message_id: u64) {
+        let marker = self.read_markers.entry(room_id).or_insert(0);
+        if message_id > *marker {
+            *marker = message_id;
+        }
+    }
+
+    fn read_markers(&self) -> hash_map::Iter<String, u64> {
+        self.read_markers.iter()
+    }
+
     fn to_user(&self) -> User {
         self.clone()
     }
@@ -228,4 +366,13 @@ mod tests {
                  String::from("foot-bath"), String::from("chili")]);
         assert_eq!(3, user.total_favorites());
     }
+
+    #[test]
+    fn read_markers_only_move_forward() {
+        let mut user = User::new(String::from("pbeesly"));
+        assert_eq!(0, user.read_marker("reception"));
+        user.set_read_marker(String::from("reception"), 7);
+        user.set_read_marker(String::from("reception"), 3);
+        assert_eq!(7, user.read_marker("reception"));
+    }
 }
\ No newline at end of file
@@ -1,100 +1,348 @@
-mod db_command;
-use rusqlite::{Connection, params, Error};
-use crate::user::{IUser, User};
+use rusqlite::{Error, ErrorCode};
+use crate::user::{IUser, User, NullUser, Profile, ProfilePatch, UserExport, ExportedMessage, ExportedAttachment, ExportedRoom};
+use crate::storage::{Storage, user_not_found};
+#[cfg(test)]
+use crate::storage::sqlite::SqliteStorage;
 use uuid::Uuid;
-use std::collections::HashSet;
+#[cfg(test)]
 use std::fs::File;
-use std::io::Read;
+use std::sync::Arc;
 use std::error::Error as StdError;
 use std::fmt::{Display, Formatter};
 use std::fmt;
-use crate::user::user_db_service::DbServiceError::EmptyFile;
-use crate::user::user_db_service::db_command::{delete_user, create_user, get_user, update_user, DbCommand};
-use crate::user::user_db_service::db_command::delete_user::DeleteUser;
-use crate::user::user_db_service::db_command::update_user::UpdateUser;
-use crate::user::user_db_service::db_command::get_user::GetUser;
-use crate::user::user_db_service::db_command::create_user::CreateUser;
-use crate::user::user_db_service::db_command::get_favorites::GetFavorites;
-use crate::user::user_db_service::db_command::update_favorites::UpdateFavorites;
+use chrono::{NaiveTime, TimeZone, Timelike, Utc};
+
+//Who anonymized messages are credited to once their sender deletes their account.
+pub const DELETED_USER_NAME: &str = "Deleted user";
+const GUEST_PREFIX: &str = "guest-";
+const GUEST_SUFFIX_LEN: usize = 6;
+const GUEST_NAME_ATTEMPTS: usize = 5;
+//How long, in seconds, a guest can go unseen before they're deleted.
+pub const GUEST_TTL: i64 = 24 * 60 * 60;
+const MAX_DISPLAY_NAME: usize = 64;
+const MAX_STATUS: usize = 140;
+const MAX_BIO: usize = 1000;
+const MAX_TIMEZONE: usize = 64;
 
+//User rules (name checks, ids, the null user) over whichever backend holds the data.
 pub struct UserDbService {
-    conn: Connection
+    storage: Arc<dyn Storage>
 }
 
 impl UserDbService {
+    #[cfg(test)]
     pub fn new() -> Self {
-        let mut conn = Connection::open_in_memory().unwrap();
-        conn.execute_batch("\
-            BEGIN;
-            CREATE TABLE users(id INTEGER PRIMARY KEY, user_id TEXT UNIQUE, user_name TEXT);
-            CREATE TABLE favorites(id INTEGER PRIMARY KEY, user_id TEXT, name TEXT, FOREIGN KEY(user_id) REFERENCES users (user_id));
-            COMMIT;
-        \
-        ");
+        UserDbService::with_storage(Arc::new(SqliteStorage::in_memory().unwrap()))
+    }
+
+    #[cfg(test)]
+    pub fn from_file(file: File) -> Result<Self, DbServiceError> {
+        Ok(UserDbService::with_storage(Arc::new(SqliteStorage::from_file(file)?)))
+    }
 
+    pub fn with_storage(storage: Arc<dyn Storage>) -> Self {
         UserDbService {
-            conn
+            storage
         }
     }
 
-    pub fn from_file(mut file: File) -> Result<Self, Box<dyn StdError>> {
-        let mut contents = String::new();
-        let read = file.read_to_string(&mut contents);
-        if let Ok(_) = read {
-            let conn = Connection::open_in_memory()?;
-            conn.execute_batch(&contents);
-            Ok(UserDbService {
-                conn
-            })
-        } else {
-            Err(Box::new(EmptyFile))
+    pub fn create_user(&self, new_user: Box<dyn IUser>) -> Result<Box<dyn IUser>, DbServiceError> {
+        let mut user = User::new(valid_name(new_user.user_name())?);
+        user.set_user_id(Uuid::new_v4().to_string());
+        let created = self.storage.create_user(user).map_err(name_taken)?;
+        Ok(Box::new(created))
+    }
+
+    //Generated names can collide, so a taken one is retried with a fresh suffix.
+    pub fn create_guest(&self) -> Result<Box<dyn IUser>, DbServiceError> {
+        let mut attempts = 0;
+        loop {
+            let suffix = Uuid::new_v4().to_simple().to_string();
+            let mut guest = User::new(format!("{}{}", GUEST_PREFIX, &suffix[..GUEST_SUFFIX_LEN]));
+            guest.set_user_id(Uuid::new_v4().to_string());
+            guest.guest = true;
+            match self.storage.create_user(guest) {
+                Err(DbServiceError::Conflict(_)) if attempts < GUEST_NAME_ATTEMPTS => attempts += 1,
+                result => return Ok(Box::new(result.map_err(name_taken)?))
+            }
+        }
+    }
+
+    //Keeps the guest's id, so favorites, read markers and the profile stay with them.
+    pub fn upgrade_guest(&self, user_id: String, user_name: String) -> Result<Box<dyn IUser>, DbServiceError> {
+        let user_name = valid_name(&user_name)?;
+        let user = self.storage.find_user(&user_id)?.ok_or_else(user_not_found)?;
+        if !user.guest {
+            return Err(DbServiceError::Validation(String::from("User is already registered.")));
+        }
+        Ok(Box::new(self.storage.upgrade_guest(&user_id, &user_name).map_err(name_taken)?))
+    }
+
+    //Any signed in request or room join counts, so guests who keep using their session don't expire.
+    pub fn guest_seen(&self, user: &dyn IUser) -> Result<(), DbServiceError> {
+        match user.user_id() {
+            Some(user_id) if user.to_user().guest => self.storage.touch_guest(user_id, Utc::now().timestamp()),
+            _ => Ok(())
         }
     }
 
-    pub fn create_user(&self, mut new_user: Box<dyn IUser>) -> Result<Box<dyn IUser>, Error> {
-        new_user.set_user_id(Uuid::new_v4().to_string());
-        CreateUser::new(new_user).execute(&self.conn)
+    //Deletes the guests unseen for GUEST_TTL and hands back their avatars, whose files are left to the caller.
+    pub fn expire_guests(&self, now: i64) -> Result<Vec<String>, DbServiceError> {
+        let mut avatars = vec![];
+        for user_id in self.storage.idle_guests(now - GUEST_TTL)? {
+            let avatar = match self.storage.find_user(&user_id)? {
+                Some(user) if user.guest => user.profile.avatar,
+                _ => continue
+            };
+            match self.storage.delete_user(&user_id, None) {
+                Ok(()) => avatars.extend(avatar),
+                Err(DbServiceError::NotFound(_)) => {},
+                Err(e) => return Err(e)
+            }
+        }
+        Ok(avatars)
+    }
+
+    #[cfg(test)]
+    pub fn retrieve_user(&self, user: Box<dyn IUser>) -> Result<Box<dyn IUser>, DbServiceError> {
+        let found = match user.user_id() {
+            Some(id) => self.storage.find_user(id)?,
+            None => None
+        };
+        Ok(or_null_user(found))
+    }
+
+    pub fn retrieve_user_by_id(&self, user_id: String) -> Result<Box<dyn IUser>, DbServiceError> {
+        Ok(or_null_user(self.storage.find_user(&user_id)?))
+    }
+
+    pub fn find_user_by_name(&self, user_name: String) -> Result<Box<dyn IUser>, DbServiceError> {
+        Ok(or_null_user(self.storage.find_user_by_name(&user_name)?))
+    }
+
+    pub fn name_is_available(&self, user_name: &str) -> Result<bool, DbServiceError> {
+        Ok(self.storage.find_user_by_name(user_name)?.is_none())
+    }
+
+    //Removes everything kept for the user, anonymizing keeps their messages in the rooms without their name.
+    pub fn delete_user(&self, user: Box<dyn IUser>, anonymize: bool) -> Result<(), DbServiceError> {
+        let user_id = user.user_id().cloned().ok_or_else(user_not_found)?;
+        let anonymize_as = if anonymize { Some(DELETED_USER_NAME) } else { None };
+        self.storage.delete_user(&user_id, anonymize_as)
+    }
+
+    //Everything stored about the user, as they'd get it from an export request.
+    pub fn export_user(&self, user_id: &str) -> Result<UserExport, DbServiceError> {
+        let user = self.storage.find_user(user_id)?.ok_or_else(user_not_found)?;
+        let messages = self.storage.user_messages(user_id)?.into_iter()
+            .map(|m| ExportedMessage {
+                room_id: m.room_id,
+                message_id: m.message_id,
+                msg: m.msg,
+                sent_at: Utc.timestamp_opt(m.sent_at, 0).single().map(|t| t.to_rfc3339()).unwrap_or_default()
+            })
+            .collect();
+        let attachments = self.storage.user_attachments(user_id)?.into_iter()
+            .map(|a| ExportedAttachment {
+                id: a.id,
+                room_id: a.room_id,
+                name: a.file_name,
+                content_type: a.content_type,
+                size: a.size
+            })
+            .collect();
+        let rooms_owned = self.storage.rooms()?.into_iter()
+            .filter(|r| r.owner_id == user_id)
+            .map(|r| ExportedRoom {
+                room_id: r.room_id,
+                name: r.name
+            })
+            .collect();
+        Ok(UserExport {
+            exported_at: Utc::now().to_rfc3339(),
+            user,
+            messages,
+            attachments,
+            rooms_owned
+        })
+    }
+
+    //Renames the user and saves any new favorites, neither change is kept if the other fails.
+    #[cfg(test)]
+    pub fn update_user(&self, user: Box<dyn IUser>) -> Result<Box<dyn IUser>, DbServiceError> {
+        self.storage.update_user(&user.to_user()).map_err(name_taken)?;
+        Ok(user)
+    }
+
+    //Changes only the name, favorites are left as they are.
+    pub fn rename_user(&self, user_id: String, user_name: String) -> Result<Box<dyn IUser>, DbServiceError> {
+        let user_name = valid_name(&user_name)?;
+        let user = self.storage.rename_user(&user_id, &user_name).map_err(name_taken)?;
+        Ok(Box::new(user))
+    }
+
+    pub fn add_favorites(&self, user_id: String, room_ids: Vec<String>) -> Result<Box<dyn IUser>, DbServiceError> {
+        Ok(Box::new(self.storage.add_favorites(&user_id, &room_ids)?))
+    }
+
+    //Swaps the whole list for the given one, its order becomes the saved order.
+    pub fn replace_favorites(&self, user_id: String, room_ids: Vec<String>) -> Result<Box<dyn IUser>, DbServiceError> {
+        let mut deduped = User::new(String::new());
+        deduped.add_favorites(room_ids);
+        Ok(Box::new(self.storage.replace_favorites(&user_id, &deduped.favorite_rooms)?))
+    }
+
+    pub fn remove_favorite(&self, user_id: String, room_id: String) -> Result<Box<dyn IUser>, DbServiceError> {
+        Ok(Box::new(self.storage.remove_favorite(&user_id, &room_id)?))
+    }
+
+    pub fn remove_room_from_favorites(&self, room_id: String) -> Result<(), DbServiceError> {
+        self.storage.remove_room_from_favorites(&room_id)
     }
 
-    pub fn retrieve_user(&self, mut user: Box<dyn IUser>) -> Result<Box<dyn IUser>, Error> {
-        let user_id = match user.user_id() {
-            Some(id) => id.clone(),
-            None => String::new()
+    //Fields left out of the patch keep their saved values.
+    pub fn update_profile(&self, user_id: String, patch: ProfilePatch) -> Result<Box<dyn IUser>, DbServiceError> {
+        let mut profile = self.profile(&user_id)?;
+        set_field(&mut profile.display_name, patch.display_name, "Display name", MAX_DISPLAY_NAME)?;
+        set_field(&mut profile.status, patch.status, "Status", MAX_STATUS)?;
+        set_field(&mut profile.bio, patch.bio, "Bio", MAX_BIO)?;
+        set_field(&mut profile.timezone, patch.timezone, "Timezone", MAX_TIMEZONE)?;
+        if let Some(ref tz) = profile.timezone {
+            if !valid_timezone(tz) {
+                return Err(DbServiceError::Validation(format!("{} isn't a known timezone format.", tz)));
+            }
+        }
+        Ok(Box::new(self.storage.update_profile(&user_id, &profile)?))
+    }
+
+    //Takes the id of an already stored attachment, None removes the avatar.
+    pub fn set_avatar(&self, user_id: String, attachment_id: Option<String>) -> Result<Box<dyn IUser>, DbServiceError> {
+        let profile = Profile {
+            avatar: attachment_id,
+            ..self.profile(&user_id)?
         };
-        let retrieved_user = GetUser::new(user_id).execute(&self.conn)?;
-        GetFavorites::new(retrieved_user).execute(&self.conn)
+        Ok(Box::new(self.storage.update_profile(&user_id, &profile)?))
+    }
+
+    fn profile(&self, user_id: &str) -> Result<Profile, DbServiceError> {
+        Ok(self.storage.find_user(user_id)?.ok_or_else(user_not_found)?.profile)
+    }
+
+    pub fn block_user(&self, user_id: String, blocked_id: String) -> Result<Box<dyn IUser>, DbServiceError> {
+        if user_id == blocked_id {
+            return Err(DbServiceError::Validation(String::from("You can't block yourself.")));
+        }
+        Ok(Box::new(self.storage.block_user(&user_id, &blocked_id)?))
+    }
+
+    pub fn unblock_user(&self, user_id: String, blocked_id: String) -> Result<Box<dyn IUser>, DbServiceError> {
+        Ok(Box::new(self.storage.unblock_user(&user_id, &blocked_id)?))
+    }
+
+    //Who shouldn't receive anything this user sends.
+    pub fn blocked_by(&self, user_id: &str) -> Result<Vec<String>, DbServiceError> {
+        self.storage.blocked_by(user_id)
+    }
+
+    pub fn update_read_marker(&self, user_id: String, room_id: String, message_id: u64) -> Result<Box<dyn IUser>, DbServiceError> {
+        Ok(Box::new(self.storage.update_read_marker(&user_id, &room_id, message_id)?))
+    }
+}
+
+fn or_null_user(user: Option<User>) -> Box<dyn IUser> {
+    match user {
+        Some(user) => Box::new(user),
+        None => Box::new(NullUser::new())
+    }
+}
+
+//The guest prefix is kept for generated names, so nobody can pass themselves off as a guest.
+fn valid_name(user_name: &str) -> Result<String, DbServiceError> {
+    let user_name = user_name.trim();
+    if user_name.is_empty() {
+        Err(DbServiceError::Validation(String::from("User name can't be empty.")))
+    } else if user_name.to_lowercase().starts_with(GUEST_PREFIX) {
+        Err(DbServiceError::Validation(format!("User names can't start with {}.", GUEST_PREFIX)))
+    } else {
+        Ok(String::from(user_name))
+    }
+}
+
+//An empty value clears the field, None leaves it alone.
+fn set_field(field: &mut Option<String>, value: Option<String>, label: &str, max_len: usize) -> Result<(), DbServiceError> {
+    if let Some(value) = value {
+        let value = value.trim();
+        if value.chars().count() > max_len {
+            return Err(DbServiceError::Validation(format!("{} can't be longer than {} characters.", label, max_len)));
+        }
+        *field = if value.is_empty() { None } else { Some(String::from(value)) };
     }
+    Ok(())
+}
 
-    pub fn delete_user(&self, mut user: Box<dyn IUser>) -> Result<(), Error> {
-        DeleteUser::new(user).execute(&self.conn);
-        Ok(())
+//Accepts UTC, an IANA name like America/New_York, or a fixed offset like +05:30.
+fn valid_timezone(tz: &str) -> bool {
+    match tz.strip_prefix('+').or_else(|| tz.strip_prefix('-')) {
+        Some(offset) => NaiveTime::parse_from_str(offset, "%H:%M").map_or(false, |t| t.hour() <= 14),
+        None => tz == "UTC" || (tz.contains('/') && tz.split('/').all(|part| {
+            !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '+')
+        }))
     }
+}
 
-    pub fn update_user(&self, user: Box<dyn IUser>) -> Result<Box<dyn IUser>, Error> {
-        let updated_user = UpdateUser::new(user.to_iuser()).execute(&self.conn)?;
-        UpdateFavorites::new(updated_user).execute(&self.conn)
+//Only the case-insensitive name index can be violated when writing a user row.
+fn name_taken(e: DbServiceError) -> DbServiceError {
+    match e {
+        DbServiceError::Conflict(_) => DbServiceError::Conflict(String::from("User name is already taken.")),
+        other => other
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Eq, PartialEq)]
 pub enum DbServiceError {
-    EmptyFile
+    EmptyFile,
+    NotFound(String),
+    Conflict(String),
+    Validation(String),
+    Storage(String)
 }
+
+impl From<Error> for DbServiceError {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::QueryReturnedNoRows => DbServiceError::NotFound(String::from("Record doesn't exist.")),
+            Error::SqliteFailure(ref f, _) if f.code == ErrorCode::ConstraintViolation =>
+                DbServiceError::Conflict(String::from("Record conflicts with an existing one.")),
+            e => DbServiceError::Storage(e.to_string())
+        }
+    }
+}
+
 impl StdError for DbServiceError {}
 impl Display for DbServiceError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match *self {
-            DbServiceError::EmptyFile => write!(f, "File was of zero length, unable to generate")
+            DbServiceError::EmptyFile => write!(f, "File was of zero length, unable to generate"),
+            DbServiceError::NotFound(ref m) => write!(f, "{}", m),
+            DbServiceError::Conflict(ref m) => write!(f, "{}", m),
+            DbServiceError::Validation(ref m) => write!(f, "{}", m),
+            DbServiceError::Storage(ref m) => write!(f, "Storage failure: {}", m)
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::user::user_db_service::UserDbService;
-    use crate::user::{User, IUser};
+    use crate::user::user_db_service::{UserDbService, DbServiceError, DELETED_USER_NAME, GUEST_TTL};
+    use chrono::Utc;
+    use crate::storage::{RoomRecord, Storage};
+    use crate::storage::sqlite::SqliteStorage;
+    use crate::chat::message_store::StoredMessage;
+    use crate::user::{User, IUser, Profile, ProfilePatch};
     use std::path::Path;
-    use std::collections::HashSet;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::thread;
 
     fn setup() -> (UserDbService, User) {
         let mut db_service = UserDbService::new();
@@ -117,7 +365,11 @@ mod tests {
         let retrieved_user = service.retrieve_user(Box::new(User {
            user_id: Some(String::from("abcd-1234")),
             user_name: String::from("jhalpert"),
-            favorite_rooms: HashSet::new()
+            favorite_rooms: vec![],
+            read_markers: HashMap::new(),
+            profile: Profile::default(),
+            blocked_users: vec![],
+            guest: false
         }));
         assert!(retrieved_user.is_ok());
     }
@@ -138,10 +390,140 @@ mod tests {
     #[test]
     fn can_delete_a_user() {
         let (db_service, new_user) = setup();
-        let result = db_service.delete_user(Box::new(new_user));
+        let result = db_service.delete_user(Box::new(new_user), false);
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn deleted_user_and_favorites_are_gone() {
+        let (db_service, mut new_user) = setup();
+        new_user.add_favorites(vec![String::from("chili")]);
+        db_service.update_user(new_user.to_iuser()).unwrap();
+        db_service.delete_user(new_user.to_iuser(), false).unwrap();
+        let found = db_service.retrieve_user(new_user.to_iuser()).unwrap();
+        assert!(found.user_id().is_none());
+    }
+
+    #[test]
+    fn export_has_the_users_content_and_anonymized_messages_outlive_them() {
+        let storage = Arc::new(SqliteStorage::in_memory().unwrap());
+        let db_service = UserDbService::with_storage(storage.clone());
+        let created = db_service.create_user(Box::new(User::new(String::from("jhalpert")))).unwrap();
+        let user_id = created.user_id().cloned().unwrap();
+        storage.save_room(&RoomRecord {
+            room_id: String::from("sales"),
+            name: String::from("Sales"),
+            owner_id: user_id.clone(),
+            last_message_id: 0,
+            topic: None,
+            description: None,
+            slug: String::from("sales"),
+            co_owners: vec![],
+            grace: None,
+            expires_at: None,
+            capacity: None
+        }).unwrap();
+        storage.save_message(&StoredMessage {
+            room_id: String::from("sales"),
+            message_id: 1,
+            from: String::from("jhalpert"),
+            sender_id: Some(user_id.clone()),
+            msg: String::from("Bears, beets"),
+            rendered: String::from("Bears, beets"),
+            mentions: vec![],
+            attachments: vec![],
+            sent_at: 0
+        }).unwrap();
+
+        let user = db_service.rename_user(user_id.clone(), String::from("bigtuna")).unwrap().to_user();
+        let export = db_service.export_user(&user_id).unwrap();
+        assert_eq!(user, export.user);
+        assert_eq!(vec![String::from("Bears, beets")], export.messages.iter().map(|m| m.msg.clone()).collect::<Vec<_>>());
+        assert_eq!("1970-01-01T00:00:00+00:00", export.messages[0].sent_at);
+        assert_eq!(vec![String::from("Sales")], export.rooms_owned.iter().map(|r| r.name.clone()).collect::<Vec<_>>());
+
+        db_service.delete_user(Box::new(user), true).unwrap();
+        assert_eq!(DELETED_USER_NAME, storage.room_messages("sales").unwrap()[0].from);
+        assert!(matches!(db_service.export_user(&user_id), Err(DbServiceError::NotFound(_))));
+    }
+
+    #[test]
+    fn guests_get_generated_names_and_upgrade_in_place() {
+        let db_service = UserDbService::new();
+        let guest = db_service.create_guest().unwrap().to_user();
+        let other = db_service.create_guest().unwrap().to_user();
+        assert!(guest.guest);
+        assert!(guest.user_name.starts_with("guest-"));
+        assert_ne!(guest.user_name, other.user_name);
+
+        let guest_id = guest.user_id.unwrap();
+        db_service.add_favorites(guest_id.clone(), vec![String::from("annex")]).unwrap();
+        let upgraded = db_service.upgrade_guest(guest_id.clone(), String::from(" rhoward ")).unwrap().to_user();
+        assert!(!upgraded.guest);
+        assert_eq!("rhoward", upgraded.user_name);
+        assert_eq!(Some(guest_id.clone()), upgraded.user_id);
+        assert_eq!(vec![String::from("annex")], upgraded.favorite_rooms);
+
+        let result = db_service.upgrade_guest(guest_id, String::from("bigtuna"));
+        assert!(matches!(result, Err(DbServiceError::Validation(_))));
+        let result = db_service.upgrade_guest(other.user_id.unwrap(), String::from("RHoward"));
+        assert!(matches!(result, Err(DbServiceError::Conflict(_))));
+    }
+
+    #[test]
+    fn guests_unseen_for_the_ttl_are_deleted_with_their_avatars() {
+        let db_service = UserDbService::new();
+        let idle = db_service.create_guest().unwrap().to_user();
+        let active = db_service.create_guest().unwrap().to_user();
+        let upgraded = db_service.create_guest().unwrap().to_user();
+        let idle_id = idle.user_id.unwrap();
+        let avatar = String::from("a5e0c1f2-0000-4000-8000-000000000000");
+        db_service.set_avatar(idle_id.clone(), Some(avatar.clone())).unwrap();
+        db_service.upgrade_guest(upgraded.user_id.clone().unwrap(), String::from("rhoward")).unwrap();
+
+        let later = Utc::now().timestamp() + GUEST_TTL + 60;
+        db_service.storage.touch_guest(active.user_id.as_ref().unwrap(), later - 60).unwrap();
+        assert_eq!(vec![avatar], db_service.expire_guests(later).unwrap());
+
+        assert!(db_service.retrieve_user_by_id(idle_id).unwrap().user_id().is_none());
+        assert!(db_service.retrieve_user_by_id(active.user_id.unwrap()).unwrap().user_id().is_some());
+        assert!(db_service.retrieve_user_by_id(upgraded.user_id.unwrap()).unwrap().user_id().is_some());
+    }
+
+    #[test]
+    fn blank_names_are_rejected() {
+        let db_service = UserDbService::new();
+        let result = db_service.create_user(Box::new(User::new(String::from("  "))));
+        assert!(matches!(result, Err(DbServiceError::Validation(_))));
+    }
+
+    #[test]
+    fn guest_names_are_reserved_for_guests() {
+        let db_service = UserDbService::new();
+        let result = db_service.create_user(Box::new(User::new(String::from("guest-1234"))));
+        assert!(matches!(result, Err(DbServiceError::Validation(_))));
+
+        let user_id = db_service.create_user(Box::new(User::new(String::from("tflenderson")))).unwrap().user_id().cloned().unwrap();
+        let result = db_service.rename_user(user_id, String::from(" Guest-Toby"));
+        assert!(matches!(result, Err(DbServiceError::Validation(_))));
+
+        let guest_id = db_service.create_guest().unwrap().user_id().cloned().unwrap();
+        let result = db_service.upgrade_guest(guest_id.clone(), String::from("GUEST-kevin"));
+        assert!(matches!(result, Err(DbServiceError::Validation(_))));
+        assert!(db_service.retrieve_user_by_id(guest_id).unwrap().to_user().guest);
+    }
+
+    #[test]
+    fn missing_users_are_reported() {
+        let db_service = UserDbService::new();
+        let result = db_service.rename_user(String::from("nobody"), String::from("tflenderson"));
+        assert!(matches!(result, Err(DbServiceError::NotFound(_))));
+        let result = db_service.add_favorites(String::from("nobody"), vec![String::from("chili")]);
+        assert!(matches!(result, Err(DbServiceError::NotFound(_))));
+        let result = db_service.delete_user(Box::new(User { user_id: Some(String::from("nobody")), ..User::new(String::from("x")) }), false);
+        assert!(matches!(result, Err(DbServiceError::NotFound(_))));
+    }
+
     #[test]
     fn can_update_a_user() {
         let (db_service, mut new_user) = setup();
@@ -154,6 +536,17 @@ mod tests {
         assert_eq!(updated_user.to_user(), retrieved_user.to_user());
     }
 
+    #[test]
+    fn rename_keeps_favorites() {
+        let (db_service, mut new_user) = setup();
+        new_user.add_favorites(vec![String::from("chili")]);
+        db_service.update_user(new_user.to_iuser()).unwrap();
+        let user_id = new_user.user_id().unwrap().clone();
+        let renamed = db_service.rename_user(user_id, String::from("bigtuna")).unwrap();
+        assert_eq!("bigtuna", renamed.user_name());
+        assert_eq!(1, renamed.total_favorites());
+    }
+
     #[test]
     fn new_user_has_zero_favorites() {
         let (db_service, mut new_user) = setup();
@@ -171,6 +564,38 @@ mod tests {
         assert_eq!(3, found_user.total_favorites());
     }
 
+    #[test]
+    fn adding_favorites_twice_does_not_duplicate() {
+        let (db_service, new_user) = setup();
+        let user_id = new_user.user_id().unwrap().clone();
+        db_service.add_favorites(user_id.clone(), vec![String::from("chili"), String::from("gambling")]).unwrap();
+        let found = db_service.add_favorites(user_id, vec![String::from("gambling"), String::from("foot-bath")]).unwrap();
+        assert_eq!(vec!["chili", "gambling", "foot-bath"], found.favorites().collect::<Vec<&String>>());
+    }
+
+    #[test]
+    fn favorites_can_be_reordered_and_removed() {
+        let (db_service, new_user) = setup();
+        let user_id = new_user.user_id().unwrap().clone();
+        db_service.add_favorites(user_id.clone(), vec![String::from("chili"), String::from("gambling")]).unwrap();
+        let found = db_service.replace_favorites(user_id.clone(), vec![String::from("gambling"), String::from("chili")]).unwrap();
+        assert_eq!(vec!["gambling", "chili"], found.favorites().collect::<Vec<&String>>());
+
+        let found = db_service.remove_favorite(user_id, String::from("gambling")).unwrap();
+        assert_eq!(vec!["chili"], found.favorites().collect::<Vec<&String>>());
+    }
+
+    #[test]
+    fn removed_room_leaves_every_favorites_list() {
+        let (db_service, new_user) = setup();
+        let other = db_service.create_user(Box::new(User::new(String::from("pbeesly")))).unwrap();
+        db_service.add_favorites(new_user.user_id().unwrap().clone(), vec![String::from("chili")]).unwrap();
+        db_service.add_favorites(other.user_id().unwrap().clone(), vec![String::from("chili")]).unwrap();
+        db_service.remove_room_from_favorites(String::from("chili")).unwrap();
+        assert_eq!(0, db_service.retrieve_user(new_user.to_iuser()).unwrap().total_favorites());
+        assert_eq!(0, db_service.retrieve_user(other).unwrap().total_favorites());
+    }
+
     #[test]
     fn non_existent_user_returns_null_user() {
         let user = Box::new(User::new(String::from("jhalpert")));
@@ -180,4 +605,140 @@ mod tests {
         let found_user = found.unwrap();
         assert!(found_user.user_id().is_none())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn can_find_user_by_name_ignoring_case() {
+        let (db_service, new_user) = setup();
+        let found = db_service.find_user_by_name(String::from("JHalpert")).unwrap();
+        assert_eq!(new_user.user_id(), found.user_id());
+
+        let missing = db_service.find_user_by_name(String::from("tflenderson")).unwrap();
+        assert!(missing.user_id().is_none());
+    }
+
+    #[test]
+    fn user_names_are_unique_ignoring_case() {
+        let (db_service, _) = setup();
+        let result = db_service.create_user(Box::new(User::new(String::from("JHalpert"))));
+        assert_eq!(DbServiceError::Conflict(String::from("User name is already taken.")), result.err().unwrap());
+        assert!(!db_service.name_is_available("JHALPERT").unwrap());
+        assert!(db_service.name_is_available("kmalone").unwrap());
+    }
+
+    #[test]
+    fn read_marker_is_persisted_with_user() {
+        let (db_service, new_user) = setup();
+        let user_id = new_user.user_id().unwrap().clone();
+        db_service.update_read_marker(user_id.clone(), String::from("room-1"), 4).unwrap();
+        db_service.update_read_marker(user_id, String::from("room-1"), 9).unwrap();
+        let found_user = db_service.retrieve_user(new_user.to_iuser()).unwrap();
+        assert_eq!(9, found_user.read_marker("room-1"));
+    }
+
+    #[test]
+    fn read_marker_never_moves_backwards() {
+        let (db_service, new_user) = setup();
+        let user_id = new_user.user_id().unwrap().clone();
+        db_service.update_read_marker(user_id.clone(), String::from("room-1"), 9).unwrap();
+        db_service.update_read_marker(user_id, String::from("room-1"), 2).unwrap();
+        let found_user = db_service.retrieve_user(new_user.to_iuser()).unwrap();
+        assert_eq!(9, found_user.read_marker("room-1"));
+    }
+
+    #[test]
+    fn failed_update_leaves_favorites_untouched() {
+        let (db_service, mut new_user) = setup();
+        db_service.create_user(Box::new(User::new(String::from("mscott")))).unwrap();
+        new_user.set_user_name(String::from("MScott"));
+        new_user.add_favorites(vec![String::from("room-1")]);
+        assert!(matches!(db_service.update_user(new_user.to_iuser()), Err(DbServiceError::Conflict(_))));
+        let found_user = db_service.retrieve_user(new_user.to_iuser()).unwrap();
+        assert_eq!("jhalpert", found_user.user_name());
+        assert_eq!(0, found_user.favorites().count());
+    }
+
+    #[test]
+    fn concurrent_writers_do_not_lose_updates() {
+        let db_service = Arc::new(UserDbService::new());
+        let handles: Vec<_> = (0..8).map(|i| {
+            let db_service = db_service.clone();
+            thread::spawn(move || {
+                let user = db_service.create_user(Box::new(User::new(format!("user-{}", i)))).unwrap();
+                let user_id = user.to_user().user_id().unwrap().clone();
+                for room in 0..10 {
+                    db_service.add_favorites(user_id.clone(), vec![format!("room-{}", room)]).unwrap();
+                }
+                user_id
+            })
+        }).collect();
+        for handle in handles {
+            let user_id = handle.join().unwrap();
+            assert_eq!(10, db_service.retrieve_user_by_id(user_id).unwrap().favorites().count());
+        }
+    }
+
+    #[test]
+    fn profile_patch_only_touches_given_fields() {
+        let (db_service, new_user) = setup();
+        let user_id = new_user.user_id().unwrap().clone();
+        db_service.update_profile(user_id.clone(), ProfilePatch {
+            display_name: Some(String::from(" Jim Halpert ")),
+            bio: Some(String::from("Salesman")),
+            ..ProfilePatch::default()
+        }).unwrap();
+        let found = db_service.update_profile(user_id.clone(), ProfilePatch {
+            bio: Some(String::new()),
+            timezone: Some(String::from("America/New_York")),
+            ..ProfilePatch::default()
+        }).unwrap().to_user();
+        assert_eq!(Some(String::from("Jim Halpert")), found.profile.display_name);
+        assert_eq!(None, found.profile.bio);
+        assert_eq!(Some(String::from("America/New_York")), found.profile.timezone);
+
+        let found = db_service.set_avatar(user_id, Some(String::from("avatar-id"))).unwrap().to_user();
+        assert_eq!(Some(String::from("avatar-id")), found.profile.avatar);
+        assert_eq!(Some(String::from("Jim Halpert")), found.profile.display_name);
+    }
+
+    #[test]
+    fn invalid_profiles_are_rejected() {
+        let (db_service, new_user) = setup();
+        let user_id = new_user.user_id().unwrap().clone();
+        let long_status = ProfilePatch {
+            status: Some("a".repeat(141)),
+            ..ProfilePatch::default()
+        };
+        assert!(matches!(db_service.update_profile(user_id.clone(), long_status), Err(DbServiceError::Validation(_))));
+        for tz in &["Scranton", "+25:00", "America//New_York"] {
+            let patch = ProfilePatch {
+                timezone: Some(String::from(*tz)),
+                ..ProfilePatch::default()
+            };
+            assert!(matches!(db_service.update_profile(user_id.clone(), patch), Err(DbServiceError::Validation(_))));
+        }
+        for tz in &["UTC", "-05:00", "America/Argentina/Buenos_Aires"] {
+            let patch = ProfilePatch {
+                timezone: Some(String::from(*tz)),
+                ..ProfilePatch::default()
+            };
+            assert!(db_service.update_profile(user_id.clone(), patch).is_ok());
+        }
+        assert!(matches!(db_service.update_profile(String::from("nobody"), ProfilePatch::default()), Err(DbServiceError::NotFound(_))));
+    }
+
+    #[test]
+    fn users_can_block_others_but_not_themselves() {
+        let (db_service, new_user) = setup();
+        let user_id = new_user.user_id().unwrap().clone();
+        let other = db_service.create_user(Box::new(User::new(String::from("dschrute")))).unwrap();
+        let other_id = other.user_id().unwrap().clone();
+
+        assert!(matches!(db_service.block_user(user_id.clone(), user_id.clone()), Err(DbServiceError::Validation(_))));
+        let found = db_service.block_user(user_id.clone(), other_id.clone()).unwrap().to_user();
+        assert_eq!(vec![other_id.clone()], found.blocked_users);
+        assert_eq!(vec![user_id.clone()], db_service.blocked_by(&other_id).unwrap());
+
+        db_service.unblock_user(user_id, other_id.clone()).unwrap();
+        assert!(db_service.blocked_by(&other_id).unwrap().is_empty());
+    }
+}
@@ -4,14 +4,26 @@ CREATE TABLE users(
     user_id TEXT UNIQUE,
     user_name TEXT);
 
+CREATE UNIQUE INDEX users_user_name ON users(user_name COLLATE NOCASE);
+
 CREATE TABLE favorites(
     id INTEGER PRIMARY KEY,
     user_id TEXT,
-    name TEXT,
+    room_id TEXT,
+    position INTEGER,
+    UNIQUE(user_id, room_id),
+    FOREIGN KEY(user_id) REFERENCES users (user_id));
+
+CREATE TABLE read_markers(
+    id INTEGER PRIMARY KEY,
+    user_id TEXT,
+    room_id TEXT,
+    last_read INTEGER,
+    UNIQUE(user_id, room_id),
     FOREIGN KEY(user_id) REFERENCES users (user_id));
 
 INSERT INTO users (user_id, user_name) VALUES ("abcd-1234", "jhalpert"), ("bcde-2345", "mscott");
-INSERT INTO favorites (user_id, name) VALUES ("abcd-1234", "dunmifsys"),
-                                             ("abcd-1234", "bigtuna"),
-                                             ("bcde-2345", "scotts-tots");
+INSERT INTO favorites (user_id, room_id, position) VALUES ("abcd-1234", "dunmifsys", 0),
+                                                         ("abcd-1234", "bigtuna", 1),
+                                                         ("bcde-2345", "scotts-tots", 0);
 COMMIT;
\ No newline at end of file